@@ -0,0 +1,102 @@
+//! Time indexing and querying a large synthetic layout
+//!
+//! The index tables use a faster hasher when built with the `fxhash` feature, so run the
+//! benchmark with and without it to compare:
+//!
+//! ```sh
+//! cargo bench --bench queries
+//! cargo bench --bench queries --features fxhash
+//! ```
+//!
+//! Outside of `cargo bench`, a small layout is timed once so the benchmark stays quick to
+//! check.
+
+use std::{
+    collections::HashMap,
+    hint::black_box,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use rsbids::layout::{Layout, LayoutOptions, QueryTerms};
+
+type Record = (PathBuf, Option<HashMap<String, String>>);
+
+/// Records of a dataset with 40 files per subject
+fn records(subjects: usize) -> Vec<Record> {
+    let mut records = vec![(PathBuf::from("/bench/dataset_description.json"), None)];
+    for sub in 0..subjects {
+        for ses in 1..=4 {
+            for run in 1..=5 {
+                for ext in [".nii.gz", ".json"] {
+                    let path = format!(
+                        "/bench/sub-{sub:04}/ses-{ses}/func/\
+                        sub-{sub:04}_ses-{ses}_task-rest_run-{run}_bold{ext}"
+                    );
+                    records.push((PathBuf::from(path), None));
+                }
+            }
+        }
+    }
+    records
+}
+
+fn query(layout: &Layout, subject: &str, run: &str) -> Layout {
+    let query = HashMap::from([
+        (
+            "subject".to_string(),
+            vec![QueryTerms::String(subject.into())],
+        ),
+        ("run".to_string(), vec![QueryTerms::String(run.into())]),
+    ]);
+    layout.query(Some(query), None, None, true).unwrap()
+}
+
+/// Print the median time of `iters` calls of `f`, which is less affected than the mean by
+/// other load on the machine
+fn time<T>(name: &str, iters: u32, mut f: impl FnMut() -> T) {
+    let mut times: Vec<Duration> = (0..iters)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    println!("{name:<24} {:>12.3?}", times[times.len() / 2]);
+}
+
+fn main() {
+    let bench = std::env::args().any(|arg| arg == "--bench");
+    let (subjects, iters) = if bench { (5000, 51) } else { (10, 1) };
+    let hasher = if cfg!(feature = "fxhash") {
+        "fxhash"
+    } else {
+        "default"
+    };
+    println!("{} files, {} hasher", subjects * 40, hasher);
+
+    let records = records(subjects);
+    time("index", iters.min(11), || {
+        Layout::from_records(records.clone(), LayoutOptions::default()).unwrap()
+    });
+    let layout = Layout::from_records(records, LayoutOptions::default()).unwrap();
+    time("query", iters, || query(&layout, "0001", "2"));
+    time("query view", iters, || {
+        query(&query(&layout, "0001", "2"), "0001", "2")
+    });
+    time("entity values", iters, || layout.count_by("subject"));
+    // Both count the entities of every path, but get_paths clones each path first
+    time("get_paths entities", iters.min(11), || {
+        layout
+            .get_paths(false)
+            .map(|path| path.get_entities().len())
+            .sum::<usize>()
+    });
+    time("iter_entities", iters.min(11), || {
+        layout
+            .iter_entities()
+            .map(|(_, entities)| entities.len())
+            .sum::<usize>()
+    });
+}
@@ -1,5 +1,21 @@
 from rsbids.entry import parse
-from rsbids._lib import BidsLayout
+from rsbids._lib import (
+    BidsLayout,
+    DatasetDescription,
+    GeneratedBy,
+    Not,
+    SourceDataset,
+    build_path,
+)
 from rsbids.bidspath import BidsPath
 
-__all__ = ["BidsLayout", "parse", "BidsPath"]
+__all__ = [
+    "BidsLayout",
+    "DatasetDescription",
+    "GeneratedBy",
+    "SourceDataset",
+    "Not",
+    "parse",
+    "build_path",
+    "BidsPath",
+]
@@ -1,5 +1,5 @@
 from rsbids.entry import parse
-from rsbids._lib import BidsLayout
+from rsbids._lib import BidsLayout, Not, build_path
 from rsbids.bidspath import BidsPath
 
-__all__ = ["BidsLayout", "parse", "BidsPath"]
+__all__ = ["BidsLayout", "Not", "parse", "build_path", "BidsPath"]
@@ -0,0 +1,66 @@
+from __future__ import annotations
+
+import asyncio
+import contextlib
+import functools
+from typing import TYPE_CHECKING, Any, Callable, Iterable, Mapping
+
+from rsbids._lib import CancelToken
+
+if TYPE_CHECKING:
+    from rsbids._lib import (
+        BidsLayout,
+        DatasetDescription,
+        DerivPathList,
+        GlobList,
+        StrPath,
+    )
+
+
+async def create_async(
+    cls: type[BidsLayout],
+    roots: None | StrPath | Iterable[StrPath] = None,
+    derivatives: None | bool | DerivPathList | Mapping[str, DerivPathList] = None,
+    *,
+    validate: bool = False,
+    follow_links: bool = False,
+    extra_entities: Mapping[str, str] | None = None,
+    ignore_key_case: bool = False,
+    ignore_dirs: set[str] | frozenset[str] | None = None,
+    include: GlobList | None = None,
+    json_data_suffixes: set[str] | frozenset[str] | None = None,
+    descriptions: Mapping[StrPath, DatasetDescription | str] | None = None,
+    quiet: bool = False,
+    on_progress: Callable[[int], Any] | None = None,
+    progress_every: int = 1000,
+) -> BidsLayout:
+    token = CancelToken()
+    loop = asyncio.get_running_loop()
+    future = loop.run_in_executor(
+        None,
+        functools.partial(
+            cls._create_cancellable,
+            roots,
+            derivatives,
+            token,
+            validate=validate,
+            follow_links=follow_links,
+            extra_entities=extra_entities,
+            ignore_key_case=ignore_key_case,
+            ignore_dirs=ignore_dirs,
+            include=include,
+            json_data_suffixes=json_data_suffixes,
+            descriptions=descriptions,
+            quiet=quiet,
+            on_progress=on_progress,
+            progress_every=progress_every,
+        ),
+    )
+    try:
+        # Shield the walk so it can be stopped and awaited if this task is cancelled
+        return await asyncio.shield(future)
+    except asyncio.CancelledError:
+        token.cancel()
+        with contextlib.suppress(asyncio.CancelledError, Exception):
+            await future
+        raise
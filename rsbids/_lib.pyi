@@ -1,6 +1,8 @@
 from os import PathLike
 from pathlib import Path
-from typing import Iterable, Mapping
+from re import Pattern
+from types import EllipsisType
+from typing import Any, Callable, Iterable, Literal, Mapping, TypedDict, overload
 from typing_extensions import Self
 
 from rsbids.bidspath import BidsPath
@@ -9,7 +11,28 @@ StrPath = str | PathLike[str]
 
 DerivPathList = StrPath | Iterable[StrPath]
 
-FilterType = str | bool | None | Iterable[str | bool]
+GlobList = str | Iterable[str]
+
+class Not:
+    def __init__(self, value: str) -> None: ...
+    @property
+    def value(self) -> str: ...
+
+_FilterTerm = str | int | bool | EllipsisType | Pattern[str] | Not | slice | range
+FilterType = _FilterTerm | None | Iterable[_FilterTerm]
+
+class RootDetails(TypedDict):
+    path: str
+    category: Literal["raw", "derivative"]
+    label: str | None
+    files: int
+    has_description: bool
+
+class CancelToken:
+    def __init__(self) -> None: ...
+    def cancel(self) -> None: ...
+    @property
+    def cancelled(self) -> bool: ...
 
 class BidsLayout:
     def __new__(
@@ -20,6 +43,20 @@ class BidsLayout:
         validate: bool = ...,
         cache: StrPath | None = ...,
         reset_cache: bool = ...,
+        nthreads: int = ...,
+        update_cache: bool = ...,
+        reindex_metadata: bool = ...,
+        revalidate: bool = ...,
+        follow_links: bool = ...,
+        extra_entities: Mapping[str, str] | None = ...,
+        ignore_key_case: bool = ...,
+        ignore_dirs: set[str] | frozenset[str] | None = ...,
+        include: GlobList | None = ...,
+        json_data_suffixes: set[str] | frozenset[str] | None = ...,
+        descriptions: Mapping[StrPath, DatasetDescription | str] | None = ...,
+        quiet: bool = ...,
+        on_progress: Callable[[int], Any] | None = ...,
+        progress_every: int = ...,
     ) -> Self: ...
     def __init__(
         self,
@@ -29,23 +66,67 @@ class BidsLayout:
         validate: bool = ...,
         cache: StrPath | None = ...,
         reset_cache: bool = ...,
+        nthreads: int = ...,
+        update_cache: bool = ...,
+        reindex_metadata: bool = ...,
+        revalidate: bool = ...,
+        follow_links: bool = ...,
+        extra_entities: Mapping[str, str] | None = ...,
+        ignore_key_case: bool = ...,
+        ignore_dirs: set[str] | frozenset[str] | None = ...,
+        include: GlobList | None = ...,
+        json_data_suffixes: set[str] | frozenset[str] | None = ...,
+        descriptions: Mapping[StrPath, DatasetDescription | str] | None = ...,
+        quiet: bool = ...,
+        on_progress: Callable[[int], Any] | None = ...,
+        progress_every: int = ...,
     ) -> None: ...
     @property
     def entities(self) -> dict[str, list[str]]: ...
+    def count_by(self, entity: str) -> dict[str, int]: ...
+    def crosstab(self, row: str, col: str) -> dict[str, dict[str, int]]: ...
+    def unique_combinations(
+        self, entities: list[str], *, partial: bool = ...
+    ) -> list[dict[str, str]]: ...
+    def ambiguous_entities(self) -> dict[str, list[str]]: ...
+    def tree(self, max_depth: int | None = ...) -> str: ...
+    def common_prefix(self) -> str | None: ...
+    def heads(self) -> list[str]: ...
+    def files_under(self, path: StrPath) -> list[BidsPath]: ...
+    def get_file(self, path: StrPath) -> BidsPath | None: ...
+    @property
+    def invalid_paths(self) -> list[tuple[str, str]]: ...
+    @property
+    def skipped_paths(self) -> list[str]: ...
+    def validate(self) -> list[dict[str, str]]: ...
+    @property
+    def description_errors(self) -> list[tuple[str, str]]: ...
+    @property
+    def metadata_errors(self) -> list[tuple[str, str]]: ...
     @property
     def metadata(self) -> dict[str, list[str]]: ...
     @property
     def roots(self) -> list[str]: ...
     @property
     def root(self) -> str: ...
+    def roots_detailed(self) -> list[RootDetails]: ...
+    def root_for(self, path: StrPath) -> tuple[str, str] | None: ...
+    def description_for(self, root: StrPath) -> DatasetDescription | None: ...
     @property
     def description(self) -> DatasetDescription: ...
     @property
     def derivatives(self) -> Self: ...
     @property
     def one(self) -> BidsPath: ...
+    def first(self) -> BidsPath: ...
     def get(
         self,
+        query: Mapping[str, FilterType] | None = ...,
+        /,
+        *,
+        ignore_case: bool = ...,
+        strict: bool = ...,
+        numeric: Literal["strict", "coalesce"] = ...,
         **entities: FilterType,
     ) -> Self: ...
     def filter(
@@ -53,27 +134,138 @@ class BidsLayout:
         *,
         root: StrPath | Iterable[StrPath] = ...,
         scope: str | Iterable[str] = ...,
+        root_match: Literal["exact", "glob", "prefix"] = ...,
+        root_scopes: Iterable[tuple[StrPath, str | Iterable[str]]] = ...,
     ) -> Self: ...
+    def by_datatype(self, datatype: str | Iterable[str]) -> Self: ...
+    def by_suffix(self, suffix: str | Iterable[str]) -> Self: ...
+    def by_pipeline(self, name: str) -> Self: ...
+    def without_entity(self, entity: str) -> Self: ...
     def parse(self, path: StrPath) -> BidsPath: ...
     def index_metadata(self) -> Self: ...
+    def metadata_for(self, path: StrPath) -> dict[str, str]: ...
+    def get_raw_metadata(self, path: StrPath) -> dict[str, Any]: ...
+    def sidecars_for(self, path: StrPath) -> list[str]: ...
+    def to_records(self) -> list[dict[str, str]]: ...
+    def to_json(self) -> str: ...
     def __len__(self) -> int: ...
+    @overload
+    def __getitem__(self, index: int) -> BidsPath: ...
+    @overload
+    def __getitem__(self, index: slice) -> Self: ...
+    def __contains__(self, path: StrPath) -> bool: ...
     def __repr__(self) -> str: ...
     def __iter__(self) -> LayoutIterator: ...
+    def sorted_paths(self) -> LayoutIterator: ...
+    def __or__(self, other: Self) -> Self: ...
+    def __and__(self, other: Self) -> Self: ...
+    def __sub__(self, other: Self) -> Self: ...
+    def concat(self, other: Self) -> Self: ...
+    def __hash__(self) -> int: ...
+    @property
+    def total(self) -> int: ...
+    def is_filtered(self) -> bool: ...
+    @classmethod
+    def from_paths(
+        cls,
+        paths: StrPath | Iterable[StrPath],
+        *,
+        validate: bool = ...,
+        follow_links: bool = ...,
+        extra_entities: Mapping[str, str] | None = ...,
+        ignore_key_case: bool = ...,
+        descriptions: Mapping[StrPath, DatasetDescription | str] | None = ...,
+    ) -> Self: ...
+    @classmethod
+    def from_records(
+        cls,
+        records: list[Mapping[str, str]],
+        *,
+        validate: bool = ...,
+        extra_entities: Mapping[str, str] | None = ...,
+        ignore_key_case: bool = ...,
+        descriptions: Mapping[StrPath, DatasetDescription | str] | None = ...,
+    ) -> Self: ...
+    @classmethod
+    async def create_async(
+        cls,
+        roots: None | StrPath | Iterable[StrPath] = ...,
+        derivatives: None | bool | DerivPathList | Mapping[str, DerivPathList] = ...,
+        *,
+        validate: bool = ...,
+        follow_links: bool = ...,
+        extra_entities: Mapping[str, str] | None = ...,
+        ignore_key_case: bool = ...,
+        ignore_dirs: set[str] | frozenset[str] | None = ...,
+        include: GlobList | None = ...,
+        json_data_suffixes: set[str] | frozenset[str] | None = ...,
+        descriptions: Mapping[StrPath, DatasetDescription | str] | None = ...,
+        quiet: bool = ...,
+        on_progress: Callable[[int], Any] | None = ...,
+        progress_every: int = ...,
+    ) -> Self: ...
+    @classmethod
+    def _create_cancellable(
+        cls,
+        roots: None | StrPath | Iterable[StrPath],
+        derivatives: None | bool | DerivPathList | Mapping[str, DerivPathList],
+        token: CancelToken,
+        *,
+        validate: bool = ...,
+        follow_links: bool = ...,
+        extra_entities: Mapping[str, str] | None = ...,
+        ignore_key_case: bool = ...,
+        ignore_dirs: set[str] | frozenset[str] | None = ...,
+        include: GlobList | None = ...,
+        json_data_suffixes: set[str] | frozenset[str] | None = ...,
+        descriptions: Mapping[StrPath, DatasetDescription | str] | None = ...,
+        quiet: bool = ...,
+        on_progress: Callable[[int], Any] | None = ...,
+        progress_every: int = ...,
+    ) -> Self: ...
     @classmethod
     def load(cls, path: StrPath) -> Self: ...
-    def save(self, path: StrPath) -> None: ...
+    def save(
+        self,
+        path: StrPath,
+        *,
+        format: Literal["binary", "filelist"] = ...,
+        compress: bool = ...,
+    ) -> None: ...
+    def is_stale(self) -> bool: ...
+    def filter_existing(self) -> Self: ...
+    def missing_files(self) -> list[BidsPath]: ...
+    def export(
+        self, dest: StrPath, mode: Literal["copy", "symlink", "hardlink"] = ...
+    ) -> None: ...
     def __getstate__(self) -> bytes: ...
     def __setstate__(self, state: bytes) -> None: ...
 
 def create_pybidspath(path: Path) -> BidsPath: ...
+def build_path(
+    entities: Mapping[str, str | int],
+    *,
+    root: StrPath | None = ...,
+    allow_unknown: bool = ...,
+) -> str: ...
+def read_tsv(path: StrPath) -> list[dict[str, str]]: ...
 def entity_long_to_short(e: str) -> str: ...
 def entity_short_to_long(e: str) -> str: ...
+def entity_aliases() -> dict[str, str]: ...
 
 class LayoutIterator:
     def __iter__(self) -> Self: ...
     def __next__(self) -> BidsPath: ...
 
 class GeneratedBy:
+    def __init__(
+        self,
+        name: str,
+        version: str | None = ...,
+        description: str | None = ...,
+        code_url: str | None = ...,
+        container: str | None = ...,
+    ) -> None: ...
     @property
     def name(self) -> str: ...
     @property
@@ -86,6 +278,12 @@ class GeneratedBy:
     def container(self) -> str | None: ...
 
 class SourceDataset:
+    def __init__(
+        self,
+        uri: str | None = ...,
+        doi: str | None = ...,
+        version: str | None = ...,
+    ) -> None: ...
     @property
     def uri(self) -> str | None: ...
     @property
@@ -94,35 +292,85 @@ class SourceDataset:
     def version(self) -> str | None: ...
 
 class DatasetDescription:
+    def __init__(
+        self,
+        name: str | None = ...,
+        *,
+        bids_version: str | None = ...,
+        dataset_type: str | None = ...,
+        authors: list[str] | None = ...,
+    ) -> None: ...
     @property
     def name(self) -> str | None: ...
+    @name.setter
+    def name(self, value: str | None) -> None: ...
     @property
     def bids_version(self) -> str | None: ...
+    @bids_version.setter
+    def bids_version(self, value: str | None) -> None: ...
     @property
     def hed_version(self) -> list[str] | None: ...
+    @hed_version.setter
+    def hed_version(self, value: list[str] | None) -> None: ...
     @property
     def dataset_links(self) -> dict[str, str] | None: ...
+    @dataset_links.setter
+    def dataset_links(self, value: dict[str, str] | None) -> None: ...
     @property
     def dataset_type(self) -> str | None: ...
+    @dataset_type.setter
+    def dataset_type(self, value: str | None) -> None: ...
     @property
     def license(self) -> str | None: ...
+    @license.setter
+    def license(self, value: str | None) -> None: ...
     @property
     def authors(self) -> list[str] | None: ...
+    @authors.setter
+    def authors(self, value: list[str] | None) -> None: ...
     @property
     def acknowledgements(self) -> str | None: ...
+    @acknowledgements.setter
+    def acknowledgements(self, value: str | None) -> None: ...
     @property
     def how_to_acknowledge(self) -> str | None: ...
+    @how_to_acknowledge.setter
+    def how_to_acknowledge(self, value: str | None) -> None: ...
     @property
     def funding(self) -> list[str] | None: ...
+    @funding.setter
+    def funding(self, value: list[str] | None) -> None: ...
     @property
     def ethics_approvals(self) -> list[str] | None: ...
+    @ethics_approvals.setter
+    def ethics_approvals(self, value: list[str] | None) -> None: ...
     @property
     def references_and_links(self) -> list[str] | None: ...
+    @references_and_links.setter
+    def references_and_links(self, value: list[str] | None) -> None: ...
     @property
     def dataset_doi(self) -> str | None: ...
+    @dataset_doi.setter
+    def dataset_doi(self, value: str | None) -> None: ...
     @property
     def generated_by(self) -> list[GeneratedBy] | None: ...
+    @generated_by.setter
+    def generated_by(self, value: list[GeneratedBy] | None) -> None: ...
     @property
     def source_datasets(self) -> list[SourceDataset] | None: ...
+    @source_datasets.setter
+    def source_datasets(self, value: list[SourceDataset] | None) -> None: ...
     @property
     def pipeline_description(self) -> GeneratedBy | None: ...
+    @pipeline_description.setter
+    def pipeline_description(self, value: GeneratedBy | None) -> None: ...
+    @classmethod
+    def derivative_scaffold(
+        cls,
+        source: DatasetDescription,
+        generated_by: GeneratedBy,
+        *,
+        uri: str | None = ...,
+    ) -> Self: ...
+    def add_generated_by(self, generated_by: GeneratedBy) -> None: ...
+    def save(self, path: StrPath) -> None: ...
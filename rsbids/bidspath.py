@@ -5,7 +5,7 @@ from typing import TYPE_CHECKING, Any, Container, Iterable
 from typing_extensions import Self
 
 from rsbids.userpath import UserPath
-from rsbids._lib import create_pybidspath, BidsLayout
+from rsbids._lib import create_pybidspath, read_tsv, BidsLayout
 
 if TYPE_CHECKING:
     from _typeshed import StrPath
@@ -13,48 +13,128 @@ if TYPE_CHECKING:
 
 class BidsPath(UserPath):
     entities: dict[str, str]
+    entities_ordered: list[tuple[str, str]]
     dataset_root: str
+    relative_path: str
+    head: str
+    root_offset: int
 
     def __init__(
         self,
         *segments: StrPath,
         _entities: dict[str, str] | None = None,
+        _entities_ordered: list[tuple[str, str]] | None = None,
         _dataset_root: str | None = None,
+        _relative_path: str | None = None,
+        _head: str | None = None,
+        _root_offset: int | None = None,
         _spec: BidsLayout | None = None,
     ):
-        if _entities is None or _dataset_root is None:
+        if (
+            _entities is None
+            or _entities_ordered is None
+            or _dataset_root is None
+            or _relative_path is None
+            or _head is None
+            or _root_offset is None
+        ):
             tpl = create_pybidspath(Path(*segments))
             _entities = tpl.entities
+            _entities_ordered = tpl.entities_ordered
             _dataset_root = tpl.dataset_root
+            _relative_path = tpl.relative_path
+            _head = tpl.head
+            _root_offset = tpl.root_offset
         super().__init__(self, *segments)
         self.entities = _entities
+        # Every entity in the order it appears in the path, including any repeated
+        # between parent directories and the filename
+        self.entities_ordered = _entities_ordered
         self.dataset_root = _dataset_root
+        # Path within the dataset root, or the full path if no root was found
+        self.relative_path = _relative_path
+        # Diagnostics for root inference: the part of the path before its first entity
+        # directory, and the length of the dataset root within the path
+        self.head = _head
+        self.root_offset = _root_offset
 
         # Eventually, this will be a "proper" spec defining how the path was parsed,
         # but for now we just use the layout it came from
         self._spec = _spec
 
     @property
-    def metadata(self):
-        result: dict[str, Any] = {}
+    def metadata(self) -> dict[str, Any]:
+        """Metadata from all sidecars applying to this path
+
+        Paths taken from a layout use its metadata index if `index_metadata()` was
+        called on the layout, and values are returned as strings. Otherwise, or if the
+        path is no longer in the layout, sidecars are read from disk.
+        """
+        if isinstance(self._spec, BidsLayout):
+            try:
+                return self._spec.metadata_for(self)
+            except (AttributeError, KeyError):
+                pass
+        return self._read_metadata()
+
+    @property
+    def sidecars(self) -> list[Path]:
+        """Json sidecars applying to this path, from most distant to nearest
+
+        Paths taken from a layout use its metadata index if `index_metadata()` was
+        called on the layout. Otherwise, or if the path is no longer in the layout,
+        sidecars are found on disk.
+        """
+        if isinstance(self._spec, BidsLayout):
+            try:
+                return [Path(p) for p in self._spec.sidecars_for(self)]
+            except (AttributeError, KeyError):
+                pass
+        return [Path(p) for p in self._find_sidecars() if p != self]
+
+    def _find_sidecars(self) -> Iterable[Self]:
+        """Find sidecars applying to this path on disk, from most distant to nearest"""
         for parent in self.parents[::-1]:
             # Crude check for when we traverse past the root
             if len(str(parent)) < len(self.dataset_root):
                 continue
             jsons = [self._parse(p) for p in parent.iterdir() if p.suffix == ".json"]
-            jsons = list(self._subset_paths(jsons, exclude="extension"))
 
             # For propery bids validity, there should only be one file at this point,
             # but don't worry about that for now
-            for path in jsons:
-                result.update(path.read_json())
+            yield from self._subset_paths(jsons, exclude="extension")
 
+    def _read_metadata(self) -> dict[str, Any]:
+        """Merge sidecars applying to this path directly from disk"""
+        result: dict[str, Any] = {}
+        for path in self._find_sidecars():
+            result.update(path.read_json())
         return result
 
+    def get_metadata(self) -> dict[str, Any]:
+        """Metadata from all sidecars applying to this path, with native json types
+
+        Paths taken from a layout use its metadata index, so `index_metadata()` must
+        first be called on the layout. Otherwise, sidecars are read from disk.
+        """
+        if isinstance(self._spec, BidsLayout):
+            return self._spec.get_raw_metadata(self)
+        return self._read_metadata()
+
     def read_json(self, encoding: str | None = None, errors: str | None = None) -> Any:
         with self.open(encoding=encoding, errors=errors) as f:
             return json.load(f)
 
+    def read_tsv(self) -> list[dict[str, str | None]]:
+        """Rows of a tab-separated file, such as an `_events.tsv`, keyed by the header
+
+        Cells holding the bids `n/a` placeholder are returned as `None`.
+        """
+        return [
+            {key: None if val == "n/a" else val for key, val in row.items()}
+            for row in read_tsv(self)
+        ]
+
     def _subset_paths(
         self, paths: Iterable[Self], exclude: Container[str] | None = None
     ):
@@ -76,34 +156,41 @@ class BidsPath(UserPath):
     def with_segments(self, *pathsegments: StrPath) -> Path:  # type: ignore
         return Path(*pathsegments)
 
-    def absolute(self) -> Self:
+    def _derive(self, new_path: Path) -> Self:
+        """Wrap a path computed from this one, keeping its parsed entities
+
+        `head` and `root_offset` index into the path string, so they are shifted when
+        only a leading part of the path was added or removed, and reparsed otherwise.
+        """
+        old, new = str(self), str(new_path)
+        if new.endswith(old):
+            prefix = new[: len(new) - len(old)]
+            head, root_offset = prefix + self.head, self.root_offset + len(prefix)
+        elif old.endswith(new):
+            removed = len(old) - len(new)
+            head, root_offset = self.head[removed:], max(self.root_offset - removed, 0)
+        else:
+            tpl = create_pybidspath(new_path)
+            head, root_offset = tpl.head, tpl.root_offset
         return BidsPath(
-            Path(self).absolute(),
+            new_path,
             _entities=self.entities,
+            _entities_ordered=self.entities_ordered,
             _dataset_root=self.dataset_root,
+            _relative_path=self.relative_path,
+            _head=head,
+            _root_offset=root_offset,
             _spec=self._spec,
         )
 
+    def absolute(self) -> Self:
+        return self._derive(Path(self).absolute())
+
     def resolve(self, strict: bool = False) -> Self:
-        return BidsPath(
-            Path(self).resolve(strict),
-            _entities=self.entities,
-            _dataset_root=self.dataset_root,
-            _spec=self._spec,
-        )
+        return self._derive(Path(self).resolve(strict))
 
     def expanduser(self) -> Self:
-        return BidsPath(
-            Path(self).expanduser(),
-            _entities=self.entities,
-            _dataset_root=self.dataset_root,
-            _spec=self._spec,
-        )
+        return self._derive(Path(self).expanduser())
 
     def relative_to(self, *other: StrPath) -> Self:
-        return BidsPath(
-            Path(self).relative_to(*other),
-            _entities=self.entities,
-            _dataset_root=self.dataset_root,
-            _spec=self._spec,
-        )
+        return self._derive(Path(self).relative_to(*other))
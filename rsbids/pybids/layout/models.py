@@ -111,7 +111,14 @@ class BIDSFile:
 
     def get_metadata(self):
         """Return all metadata associated with the current file."""
-        return self._bidspath.metadata
+        return self._metadata()
+
+    def _metadata(self):
+        # Fall back to reading sidecars when the layout's metadata is not indexed
+        try:
+            return self._bidspath.get_metadata()
+        except AttributeError:
+            return self._bidspath._read_metadata()
 
     def get_entities(
         self, metadata: bool = False, values: Literal["tags", "objects"] = "tags"
@@ -140,7 +147,7 @@ class BIDSFile:
             instances.
         """
         entities = self._bidspath.entities if metadata is not True else {}
-        md = self._bidspath.metadata if metadata is not False else {}
+        md = self._metadata() if metadata is not False else {}
         result = {**entities, **md}
         if values == "tags":
             return result
@@ -184,6 +184,30 @@ def create_dataset(root: str | Path, dataset: BidsDataset) -> None:
             path.touch()
 
 
+def create_files(root: str | Path, files: Mapping[str, str] | Iterable[str]) -> Path:
+    """Create empty files (or files with the given contents) under root
+
+    Parameters
+    ----------
+    root : str | Path
+        Directory in which to create the files
+    files : Mapping[str, str] | Iterable[str]
+        Relative paths of the files to create. If a mapping, values are written as
+        file contents
+
+    Returns
+    -------
+    Path
+        The root directory
+    """
+    root = Path(root)
+    contents = files if isinstance(files, Mapping) else dict.fromkeys(files, "")
+    for path, content in contents.items():
+        (root / path).parent.mkdir(parents=True, exist_ok=True)
+        (root / path).write_text(content)
+    return root
+
+
 def create_snakebids_config(dataset: BidsDataset) -> InputsConfig:
     """Generate a basic snakebids config dict from a dataset"""
     all_entities = set(
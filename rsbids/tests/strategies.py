@@ -35,22 +35,25 @@ valid_entities: tuple[str, ...] = (
     "task",
     "tracksys",
     "acquisition",
+    "nucleus",
+    "volume",
     "ceagent",
-    "staining",
+    "stain",
     "tracer",
     "reconstruction",
     "direction",
     "run",
-    "proc",
+    "processing",
     "modality",
     "echo",
     "flip",
-    "inv",
-    "mt",
+    "inversion",
+    "mtransfer",
     "part",
     "recording",
     "space",
     "chunk",
+    "segmentation",
     "split",
     "atlas",
     "roi",
@@ -25,6 +25,10 @@ def test_benchmark_rsbids_indexing(benchmark: Benchmark):
     benchmark(BidsLayout, "topsy")
 
 
+def test_benchmark_rsbids_parallel_indexing(benchmark: Benchmark):
+    benchmark(BidsLayout, "topsy", nthreads=4)
+
+
 def test_benchmark_ancp_indexing(benchmark: Benchmark):
     benchmark(ancpbids.load_dataset, "topsy")
 
@@ -41,6 +45,11 @@ def test_benchmark_rsbids_query(benchmark: Benchmark):
     benchmark(layout.get, subject="001")
 
 
+def test_benchmark_rsbids_repeated_query(benchmark: Benchmark):
+    layout = BidsLayout("topsy")
+    benchmark(lambda: [layout.get(subject="001") for _ in range(1000)])
+
+
 def test_benchmark_ancp_query(benchmark: Benchmark):
     layout = ancpbids.load_dataset("topsy")
     benchmark(layout.query, subject="001")
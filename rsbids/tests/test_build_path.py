@@ -0,0 +1,101 @@
+from __future__ import annotations
+
+from pathlib import Path
+
+import pytest
+
+from rsbids import BidsLayout, BidsPath, build_path
+from rsbids.tests.helpers import create_files
+
+
+def test_build_canonical_path(tmp_path: Path):
+    path = build_path(
+        {
+            "extension": ".nii.gz",
+            "task": "rest",
+            "suffix": "bold",
+            "session": "1",
+            "datatype": "func",
+            "subject": "01",
+        },
+        root=tmp_path,
+    )
+    assert Path(path) == tmp_path / "sub-01/ses-1/func/sub-01_ses-1_task-rest_bold.nii.gz"
+
+
+def test_built_path_round_trips_through_layout(tmp_path: Path):
+    entities = {"subject": "01", "run": 2, "suffix": "T1w", "datatype": "anat"}
+    path = build_path(entities, root=tmp_path)
+    create_files(tmp_path, ["dataset_description.json", str(Path(path).relative_to(tmp_path))])
+    assert BidsLayout(tmp_path).get(suffix="T1w").one.entities["run"] == "2"
+
+
+def test_unknown_entity_requires_flag():
+    with pytest.raises(ValueError, match="Unknown entity 'foo'"):
+        build_path({"sub": "01", "foo": "bar", "suffix": "T1w"})
+    assert (
+        build_path({"sub": "01", "foo": "bar", "suffix": "T1w"}, allow_unknown=True)
+        == "sub-01/sub-01_foo-bar_T1w"
+    )
+
+
+@pytest.mark.parametrize(
+    "entities",
+    [
+        {"sub": "01_02", "suffix": "T1w"},
+        {"sub": "01", "acq": "a.b", "suffix": "T1w"},
+        {"sub": "01", "suffix": "T1w_bold"},
+        {"sub": "01", "suffix": "anat/T1w"},
+        {"sub": "01", "suffix": ""},
+        {"sub": "01", "datatype": "anat/extra", "suffix": "T1w"},
+        {"sub": "01", "datatype": "an_at", "suffix": "T1w"},
+        {"sub": "01", "suffix": "T1w", "extension": ".nii/gz"},
+        {"sub": "01", "suffix": "T1w", "extension": "_T2w.nii"},
+        {"sub": "01", "suffix": "T1w", "extension": ".nii..gz"},
+    ],
+)
+def test_invalid_value_raises(entities: dict[str, str]):
+    with pytest.raises(ValueError, match="Invalid value"):
+        build_path(entities)
+
+
+def test_entities_follow_schema_order():
+    path = build_path(
+        {"seg": "a", "voi": "b", "nuc": "c", "acq": "d", "sub": "01", "suffix": "x"}
+    )
+    assert path == "sub-01/sub-01_acq-d_nuc-c_voi-b_seg-a_x"
+
+
+def test_entities_ordered_follow_filename(tmp_path: Path):
+    create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/ses-1/func/sub-01_ses-1_task-rest_run-2_bold.nii.gz",
+        ],
+    )
+    path = BidsLayout(tmp_path).get(suffix="bold").one
+    assert path.entities_ordered == [
+        ("subject", "01"),
+        ("session", "1"),
+        ("datatype", "func"),
+        ("subject", "01"),
+        ("session", "1"),
+        ("task", "rest"),
+        ("run", "2"),
+        ("suffix", "bold"),
+        ("extension", ".nii.gz"),
+    ]
+
+
+def test_entities_ordered_of_standalone_path():
+    path = BidsPath("sub-01/anat/sub-01_acq-mprage_T1w.nii.gz")
+    assert [key for key, _ in path.entities_ordered] == [
+        "subject",
+        "datatype",
+        "subject",
+        "acquisition",
+        "suffix",
+        "extension",
+    ]
+    assert path.absolute().entities_ordered == path.entities_ordered
@@ -0,0 +1,317 @@
+from __future__ import annotations
+
+import os
+import pickle
+from pathlib import Path
+
+import pytest
+
+from rsbids import BidsLayout
+from rsbids.tests.helpers import create_files
+
+
+@pytest.fixture
+def dataset(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/anat/sub-01_T1w.nii.gz",
+            "sub-01/func/sub-01_task-rest_bold.nii.gz",
+            "sub-01/func/sub-01_task-rest_bold.json",
+            "derivatives/pipeline/dataset_description.json",
+            "derivatives/pipeline/sub-01/anat/sub-01_desc-preproc_T1w.nii.gz",
+        ],
+    )
+
+
+@pytest.mark.parametrize("validate", [True, False])
+def test_filelist_cache_roundtrip(dataset: Path, tmp_path: Path, validate: bool):
+    layout = BidsLayout(
+        dataset,
+        derivatives={"pipeline": dataset / "derivatives" / "pipeline"},
+        validate=validate,
+    )
+    cache = tmp_path / "layout.cache"
+    layout.save(cache, format="filelist")
+    assert 'format="filelist"' in cache.read_text().splitlines()[0]
+
+    loaded = BidsLayout.load(cache)
+    assert loaded == layout
+    assert set(loaded.roots) == set(layout.roots)
+    assert loaded.derivatives.get(desc="preproc") == layout.derivatives.get(desc="preproc")
+
+
+@pytest.mark.parametrize("fmt", ["binary", "filelist"])
+def test_cache_of_relative_root(
+    dataset: Path, tmp_path: Path, monkeypatch: pytest.MonkeyPatch, fmt: str
+):
+    monkeypatch.chdir(dataset.parent)
+    root = dataset.name
+    layout = BidsLayout(root)
+    cache = tmp_path / "layout.cache"
+    layout.save(cache, format=fmt)  # type: ignore
+
+    assert BidsLayout.load(cache) == layout
+    assert BidsLayout(root, cache=cache) == layout
+
+
+def test_cache_format_is_detected_on_load(dataset: Path, tmp_path: Path):
+    layout = BidsLayout(dataset)
+    for fmt in ["binary", "filelist"]:
+        cache = tmp_path / f"{fmt}.cache"
+        layout.save(cache, format=fmt)  # type: ignore
+        assert BidsLayout(dataset, cache=cache) == layout
+
+
+def test_unknown_cache_format(dataset: Path, tmp_path: Path):
+    with pytest.raises(ValueError, match="Unknown cache format"):
+        BidsLayout(dataset).save(tmp_path / "layout.cache", format="xml")  # type: ignore
+
+
+@pytest.mark.parametrize("fmt", ["binary", "filelist"])
+@pytest.mark.parametrize("validate", [True, False])
+def test_update_cache_matches_rebuild(
+    dataset: Path, tmp_path: Path, fmt: str, validate: bool
+):
+    cache = tmp_path / "layout.cache"
+    BidsLayout(dataset, validate=validate).save(cache, format=fmt)  # type: ignore
+
+    create_files(
+        dataset,
+        ["sub-02/anat/sub-02_T1w.nii.gz", "sub-02/func/sub-02_task-nback_bold.nii.gz"],
+    )
+    (dataset / "sub-01" / "func" / "sub-01_task-rest_bold.json").unlink()
+
+    updated = BidsLayout(dataset, validate=validate, cache=cache, update_cache=True)
+    rebuilt = BidsLayout(dataset, validate=validate)
+    assert updated == rebuilt
+    assert updated.get(subject="02", suffix="bold") == rebuilt.get(
+        subject="02", suffix="bold"
+    )
+    assert set(updated.entities["subject"]) == {"01", "02"}
+    assert dataset / "sub-01" / "func" / "sub-01_task-rest_bold.json" not in updated
+
+    # The cache file itself is rewritten with the update
+    assert BidsLayout.load(cache) == rebuilt
+
+
+def test_stale_cache_is_used_without_update(dataset: Path, tmp_path: Path):
+    cache = tmp_path / "layout.cache"
+    original = BidsLayout(dataset, cache=cache)
+    create_files(dataset, ["sub-02/anat/sub-02_T1w.nii.gz"])
+    assert BidsLayout(dataset, cache=cache) == original
+
+
+def _touch_future(path: Path):
+    future = path.stat().st_mtime + 60
+    os.utime(path, (future, future))
+
+
+@pytest.mark.parametrize("fmt", ["binary", "filelist"])
+def test_touched_root_makes_cache_stale(dataset: Path, tmp_path: Path, fmt: str):
+    # Writing the cache within the root would itself modify the root
+    (tmp_path / "cache").mkdir()
+    cache = tmp_path / "cache" / "layout.cache"
+    BidsLayout(dataset).save(cache, format=fmt)
+    assert not BidsLayout.load(cache).is_stale()
+    _touch_future(dataset)
+    assert BidsLayout.load(cache).is_stale()
+
+
+def test_changes_below_roots_are_not_stale(dataset: Path):
+    layout = BidsLayout(dataset)
+    _touch_future(dataset / "sub-01" / "anat")
+    assert not layout.is_stale()
+
+
+def _rewrite_declaration(cache: Path, declaration: str):
+    contents = cache.read_bytes()
+    body = contents[contents.index(b"\n") + 1 :]
+    cache.write_bytes(declaration.encode() + b"\n" + body)
+
+
+@pytest.mark.parametrize(
+    "declaration,writer",
+    [
+        ('<?rsbids version="1.0">', "an older rsbids"),
+        ('<?rsbids version="0.9" format="binary" rsbids="0.0.1">', "rsbids 0.0.1"),
+        ('<?rsbids version="0.9" format="filelist" rsbids="0.0.1">', "rsbids 0.0.1"),
+        ('<?rsbids version="1.99" format="filelist" rsbids="9.0.0">', "rsbids 9.0.0"),
+    ],
+)
+def test_incompatible_cache_version_is_rejected(
+    dataset: Path, tmp_path: Path, declaration: str, writer: str
+):
+    cache = tmp_path / "layout.cache"
+    BidsLayout(dataset).save(cache)
+    _rewrite_declaration(cache, declaration)
+    with pytest.raises(IOError, match=f"written by {writer}.*Delete the cache and rebuild"):
+        BidsLayout.load(cache)
+
+
+def test_old_filelist_cache_is_migrated(dataset: Path, tmp_path: Path):
+    layout = BidsLayout(dataset)
+    cache = tmp_path / "layout.cache"
+    layout.save(cache, format="filelist")
+    _rewrite_declaration(cache, '<?rsbids version="1.0" format="filelist">')
+    assert BidsLayout.load(cache) == layout
+
+
+def test_malformed_declaration_is_rejected(tmp_path: Path):
+    cache = tmp_path / "layout.cache"
+    cache.write_text("<?rsbids format=\"binary\">\n")
+    with pytest.raises(IOError, match="not a valid rsbids cache file"):
+        BidsLayout.load(cache)
+
+
+@pytest.mark.parametrize("fmt", ["binary", "filelist"])
+def test_compressed_cache_roundtrip(tmp_path: Path, fmt: str):
+    dataset = create_files(
+        tmp_path / "dataset",
+        ["dataset_description.json"]
+        + [
+            f"sub-{sub:03d}/func/sub-{sub:03d}_task-{task}_run-{run}_bold.nii.gz"
+            for sub in range(100)
+            for task in ["rest", "nback"]
+            for run in range(1, 6)
+        ],
+    )
+    layout = BidsLayout(dataset)
+    plain = tmp_path / "plain.cache"
+    compressed = tmp_path / "compressed.cache"
+    layout.save(plain, format=fmt)  # type: ignore
+    layout.save(compressed, format=fmt, compress=True)  # type: ignore
+
+    assert BidsLayout.load(compressed) == layout
+    assert compressed.stat().st_size < plain.stat().st_size / 4
+
+
+@pytest.fixture
+def with_sidecars(tmp_path: Path):
+    return create_files(
+        tmp_path / "dataset",
+        {
+            "dataset_description.json": "{}",
+            "task-rest_bold.json": '{"RepetitionTime": 2, "SliceTiming": [0, 1]}',
+            "sub-01/func/sub-01_task-rest_bold.nii.gz": "",
+        },
+    )
+
+
+@pytest.mark.parametrize("compress", [True, False])
+def test_metadata_index_is_cached(with_sidecars: Path, tmp_path: Path, compress: bool):
+    cache = tmp_path / "layout.cache"
+    layout = BidsLayout(with_sidecars).index_metadata()
+    layout.save(cache, compress=compress)
+
+    # Sidecars are no longer readable, so metadata can only come from the cache
+    (with_sidecars / "task-rest_bold.json").write_text("not json")
+    loaded = BidsLayout(with_sidecars, cache=cache)
+    assert loaded.metadata == {"RepetitionTime": ["2"]}
+    bold = loaded.get(suffix="bold", extension=".nii.gz").one
+    assert bold.get_metadata() == {"RepetitionTime": 2, "SliceTiming": [0, 1]}
+
+
+def test_reindex_metadata(with_sidecars: Path, tmp_path: Path):
+    cache = tmp_path / "layout.cache"
+    BidsLayout(with_sidecars).index_metadata().save(cache)
+    (with_sidecars / "task-rest_bold.json").write_text('{"RepetitionTime": 3}')
+
+    assert BidsLayout(with_sidecars, cache=cache).metadata == {"RepetitionTime": ["2"]}
+    reindexed = BidsLayout(with_sidecars, cache=cache, reindex_metadata=True)
+    assert reindexed.metadata == {"RepetitionTime": ["3"]}
+    # The rebuilt index is written back to the cache
+    assert BidsLayout(with_sidecars, cache=cache).metadata == {"RepetitionTime": ["3"]}
+
+
+def test_update_cache_reindexes_metadata(with_sidecars: Path, tmp_path: Path):
+    cache = tmp_path / "layout.cache"
+    BidsLayout(with_sidecars).index_metadata().save(cache)
+    create_files(with_sidecars, ["sub-02/func/sub-02_task-rest_bold.nii.gz"])
+    updated = BidsLayout(with_sidecars, cache=cache, update_cache=True)
+    assert {Path(p).name for p in updated.get(RepetitionTime="2")} == {
+        "task-rest_bold.json",
+        "sub-01_task-rest_bold.nii.gz",
+        "sub-02_task-rest_bold.nii.gz",
+    }
+
+
+@pytest.mark.parametrize("fmt", ["binary", "filelist"])
+def test_revalidate_drops_deleted_files(dataset: Path, tmp_path: Path, fmt: str):
+    cache = tmp_path / "layout.cache"
+    BidsLayout(dataset).save(cache, format=fmt)  # type: ignore
+    deleted = dataset / "sub-01" / "anat" / "sub-01_T1w.nii.gz"
+    deleted.unlink()
+    create_files(dataset, ["sub-02/anat/sub-02_T1w.nii.gz"])
+
+    layout = BidsLayout(dataset, cache=cache, revalidate=True)
+    assert deleted not in layout
+    assert not layout.get(subject="01", suffix="T1w", datatype="anat")
+    # Revalidation only checks cached files, it does not look for new ones
+    assert dataset / "sub-02" / "anat" / "sub-02_T1w.nii.gz" not in layout
+    assert dataset / "sub-01" / "func" / "sub-01_task-rest_bold.nii.gz" in layout
+
+
+@pytest.mark.parametrize("fmt", ["binary", "filelist"])
+def test_revalidate_reparses_modified_files(
+    with_sidecars: Path, tmp_path: Path, fmt: str
+):
+    cache = tmp_path / "layout.cache"
+    BidsLayout(with_sidecars).index_metadata().save(cache, format=fmt)  # type: ignore
+    saved = cache.read_bytes()
+    sidecar = with_sidecars / "task-rest_bold.json"
+    sidecar.write_text('{"RepetitionTime": 3}')
+    stat = sidecar.stat()
+    os.utime(sidecar, ns=(stat.st_atime_ns, stat.st_mtime_ns + 10**9))
+
+    layout = BidsLayout(with_sidecars, cache=cache, revalidate=True)
+    assert len(layout) == 3
+    bold = layout.get(suffix="bold", extension=".nii.gz").one
+    assert layout.metadata_for(bold) == {"RepetitionTime": "3"}
+    # The cache on disk is not modified
+    assert cache.read_bytes() == saved
+
+
+def test_filter_existing_drops_missing_records(dataset: Path, tmp_path: Path):
+    gone = tmp_path / "sub-02" / "anat" / "sub-02_T1w.nii.gz"
+    broken = dataset / "sub-01" / "anat" / "sub-01_T2w.nii.gz"
+    broken.symlink_to(tmp_path / "nowhere.nii.gz")
+    records = BidsLayout(dataset).to_records() + [{"path": str(gone)}]
+    layout = BidsLayout.from_records(records)
+    assert gone in layout
+
+    existing = layout.filter_existing()
+    assert len(existing) == len(layout) - 2
+    assert gone not in existing and broken not in existing
+    assert "02" not in existing.entities["subject"]
+    missing = {str(path) for path in layout.missing_files()}
+    assert missing == {str(gone), str(broken)}
+    assert not existing.missing_files()
+
+
+def test_filter_existing_keeps_view(dataset: Path):
+    layout = BidsLayout(dataset).get(suffix="bold")
+    (dataset / "sub-01" / "anat" / "sub-01_T1w.nii.gz").unlink()
+    assert layout.filter_existing() == layout
+    assert not layout.missing_files()
+
+
+def test_pickle_roundtrip(dataset: Path):
+    layout = BidsLayout(dataset, derivatives=True)
+    unpickled = pickle.loads(pickle.dumps(layout))
+    assert unpickled == layout
+    assert set(unpickled.roots) == set(layout.roots)
+
+
+def test_pickle_preserves_view(dataset: Path):
+    layout = BidsLayout(dataset).get(suffix="bold", extension=".json")
+    unpickled = pickle.loads(pickle.dumps(layout))
+    assert unpickled == layout
+    assert len(unpickled) == 1
+
+
+def test_pickle_preserves_metadata(with_sidecars: Path):
+    layout = BidsLayout(with_sidecars).index_metadata()
+    unpickled = pickle.loads(pickle.dumps(layout))
+    assert unpickled.metadata == layout.metadata
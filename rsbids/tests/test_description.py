@@ -0,0 +1,160 @@
+from __future__ import annotations
+
+import json
+from pathlib import Path
+
+import pytest
+
+from rsbids import BidsLayout, DatasetDescription, GeneratedBy
+from rsbids.tests.helpers import create_files
+
+
+def test_description_save_roundtrip(tmp_path: Path):
+    dataset = create_files(
+        tmp_path / "dataset",
+        {
+            "dataset_description.json": json.dumps(
+                {
+                    "Name": "example",
+                    "Authors": ["A", "B"],
+                    "GeneratedBy": [{"Name": "tool", "Version": "1.0"}],
+                }
+            ),
+            "sub-01/anat/sub-01_T1w.nii.gz": "",
+        },
+    )
+    out = tmp_path / "out"
+    out.mkdir()
+    BidsLayout(dataset).description.save(out)
+
+    saved = json.loads((out / "dataset_description.json").read_text())
+    # Fields that were never set are left out rather than written as null
+    assert saved == {
+        "Name": "example",
+        "Authors": ["A", "B"],
+        "GeneratedBy": [{"Name": "tool", "Version": "1.0"}],
+    }
+    reopened = BidsLayout(out).description
+    assert reopened.name == "example"
+    assert reopened.authors == ["A", "B"]
+    assert reopened.generated_by is not None
+    assert reopened.generated_by[0].version == "1.0"
+
+
+def test_description_from_scratch(tmp_path: Path):
+    desc = DatasetDescription("pipeline", bids_version="1.8.0", dataset_type="derivative")
+    desc.authors = ["A"]
+    desc.add_generated_by(GeneratedBy("pipeline", version="0.1.0"))
+    desc.add_generated_by(GeneratedBy("other"))
+    desc.save(tmp_path)
+
+    assert json.loads((tmp_path / "dataset_description.json").read_text()) == {
+        "Name": "pipeline",
+        "BIDSVersion": "1.8.0",
+        "DatasetType": "derivative",
+        "Authors": ["A"],
+        "GeneratedBy": [{"Name": "pipeline", "Version": "0.1.0"}, {"Name": "other"}],
+    }
+
+
+def test_derivative_scaffold(tmp_path: Path):
+    source = DatasetDescription("raw", bids_version="1.8.0")
+    source.dataset_doi = "doi:10.0/example"
+    desc = DatasetDescription.derivative_scaffold(
+        source, GeneratedBy("pipeline", version="0.1.0"), uri="bids:raw:."
+    )
+    assert desc.dataset_type == "derivative"
+    assert desc.bids_version == "1.8.0"
+    assert desc.generated_by is not None
+    assert [g.name for g in desc.generated_by] == ["pipeline"]
+    assert desc.source_datasets is not None
+    [src] = desc.source_datasets
+    assert (src.uri, src.doi) == ("bids:raw:.", "doi:10.0/example")
+
+    desc.save(tmp_path)
+    layout = BidsLayout(create_files(tmp_path, ["sub-01/anat/sub-01_T1w.nii.gz"]))
+    assert layout.description.dataset_type == "derivative"
+
+
+def test_description_setters(tmp_path: Path):
+    desc = DatasetDescription()
+    desc.name = "example"
+    desc.license = "CC0"
+    desc.license = None
+    desc.generated_by = [GeneratedBy("tool")]
+    assert desc.name == "example"
+    assert desc.license is None
+    assert desc.generated_by is not None
+    assert [g.name for g in desc.generated_by] == ["tool"]
+
+
+@pytest.mark.parametrize(
+    "field,value",
+    [("authors", "A, B"), ("authors", [1, 2]), ("name", 1), ("generated_by", ["tool"])],
+)
+def test_description_setters_validate_types(field: str, value: object):
+    with pytest.raises(TypeError):
+        setattr(DatasetDescription(), field, value)
+
+
+def test_editing_layout_description_does_not_modify_layout(tmp_path: Path):
+    dataset = create_files(
+        tmp_path,
+        {"dataset_description.json": '{"Name": "example"}', "sub-01/sub-01_T1w.nii.gz": ""},
+    )
+    layout = BidsLayout(dataset)
+    desc = layout.description
+    desc.name = "edited"
+    assert desc.name == "edited"
+    assert layout.description.name == "example"
+
+
+def test_description_for_root(tmp_path: Path):
+    create_files(
+        tmp_path,
+        {
+            "dataset_description.json": json.dumps({"Name": "raw"}),
+            "sub-01/anat/sub-01_T1w.nii.gz": "",
+            "derivatives/proc/dataset_description.json": json.dumps({"Name": "proc"}),
+            "derivatives/proc/sub-01/anat/sub-01_desc-x_T1w.nii.gz": "",
+        },
+    )
+    layout = BidsLayout(tmp_path, derivatives={"proc": tmp_path / "derivatives/proc"})
+    assert layout.description_for(tmp_path).name == "raw"
+    assert layout.description_for(tmp_path / "derivatives/proc").name == "proc"
+    assert layout.description_for(f"{tmp_path}/derivatives/proc/").name == "proc"
+    assert layout.description_for(tmp_path / "derivatives") is None
+    assert layout.description_for(tmp_path / "sub-01") is None
+
+
+def test_injected_description_for_records(tmp_path: Path):
+    deriv = tmp_path / "derivatives" / "fmriprep"
+    layout = BidsLayout.from_records(
+        [
+            {"path": str(tmp_path / "sub-01" / "anat" / "sub-01_T1w.nii.gz")},
+            {"path": str(deriv / "sub-01" / "anat" / "sub-01_desc-preproc_T1w.nii.gz")},
+            {"path": str(deriv / "sub-01" / "func" / "sub-01_desc-preproc_bold.nii.gz")},
+        ],
+        descriptions={
+            deriv: json.dumps({"Name": "fmriprep", "GeneratedBy": [{"Name": "fMRIPrep"}]})
+        },
+    )
+    scoped = layout.filter(scope="fMRIPrep")
+    assert len(scoped) == 2
+    assert scoped.roots == [str(deriv)]
+    assert layout.description_for(deriv).name == "fmriprep"
+
+
+def test_injected_description_replaces_missing_file(tmp_path: Path):
+    dataset = create_files(tmp_path, ["sub-01/anat/sub-01_T1w.nii.gz"])
+    description = DatasetDescription("synthetic")
+    description.generated_by = [GeneratedBy("simulator")]
+    layout = BidsLayout(dataset, descriptions={dataset: description})
+    assert layout.description.name == "synthetic"
+    assert len(layout.filter(scope="simulator")) == 1
+
+
+def test_invalid_injected_description_raises(tmp_path: Path):
+    dataset = create_files(tmp_path, ["sub-01/anat/sub-01_T1w.nii.gz"])
+    with pytest.raises(ValueError):
+        BidsLayout(dataset, descriptions={dataset: "{not json"})
@@ -0,0 +1,138 @@
+from __future__ import annotations
+
+import json
+import os
+from pathlib import Path
+
+import pytest
+
+from rsbids import BidsLayout
+from rsbids.tests.helpers import create_files
+
+
+@pytest.fixture
+def dataset(tmp_path: Path):
+    return create_files(
+        tmp_path / "dataset",
+        {
+            "dataset_description.json": '{"Name": "example"}',
+            "sub-01/anat/sub-01_T1w.nii.gz": "",
+            "sub-01/func/sub-01_task-rest_bold.nii.gz": "",
+            "sub-02/anat/sub-02_T1w.nii.gz": "",
+            "derivatives/pipeline/dataset_description.json": '{"Name": "pipeline"}',
+            "derivatives/pipeline/sub-01/anat/sub-01_T1w.nii.gz": "",
+        },
+    )
+
+
+def _tree(root: Path):
+    return {p.relative_to(root) for p in root.rglob("*") if not p.is_dir()}
+
+
+def test_export_symlinks_filtered_view(dataset: Path, tmp_path: Path):
+    dest = tmp_path / "out"
+    BidsLayout(dataset).get(subject="01").export(dest)
+    assert _tree(dest) == {
+        Path("dataset_description.json"),
+        Path("sub-01/anat/sub-01_T1w.nii.gz"),
+        Path("sub-01/func/sub-01_task-rest_bold.nii.gz"),
+    }
+    for path in _tree(dest):
+        link = dest / path
+        assert link.is_symlink()
+        target = Path(os.readlink(link))
+        assert target.is_absolute()
+        assert target == (dataset / path).resolve()
+
+    exported = BidsLayout(dest)
+    assert exported.description.name == "example"
+    assert exported.entities["subject"] == ["01"]
+
+
+@pytest.mark.parametrize("mode", ["copy", "hardlink"])
+def test_export_files(dataset: Path, tmp_path: Path, mode: str):
+    dest = tmp_path / "out"
+    BidsLayout(dataset, derivatives=True).derivatives.export(dest, mode=mode)  # type: ignore
+    assert _tree(dest) == {
+        Path("dataset_description.json"),
+        Path("sub-01/anat/sub-01_T1w.nii.gz"),
+    }
+    assert not (dest / "dataset_description.json").is_symlink()
+    assert BidsLayout(dest).description.name == "pipeline"
+
+
+def test_export_raw_and_derivative_roots(dataset: Path, tmp_path: Path):
+    dest = tmp_path / "out"
+    BidsLayout(dataset, derivatives=True).get(suffix="T1w", subject="01").export(dest)
+    assert _tree(dest) == {
+        Path("dataset_description.json"),
+        Path("sub-01/anat/sub-01_T1w.nii.gz"),
+        Path("derivatives/pipeline/dataset_description.json"),
+        Path("derivatives/pipeline/sub-01/anat/sub-01_T1w.nii.gz"),
+    }
+    exported = BidsLayout(dest, derivatives=True)
+    assert exported.description.name == "example"
+    assert exported.derivatives.description.name == "pipeline"  # type: ignore
+    assert len(exported.get(suffix="T1w")) == 2
+
+
+def test_export_does_not_overwrite(dataset: Path, tmp_path: Path):
+    dest = tmp_path / "out"
+    create_files(dest, ["sub-01/func/sub-01_task-rest_bold.nii.gz"])
+    with pytest.raises(FileExistsError, match="already exists"):
+        BidsLayout(dataset).get(subject="01").export(dest)
+    # Nothing is written when the export cannot be completed
+    assert _tree(dest) == {Path("sub-01/func/sub-01_task-rest_bold.nii.gz")}
+
+
+def test_export_unknown_mode(dataset: Path, tmp_path: Path):
+    with pytest.raises(ValueError, match="Unknown export mode"):
+        BidsLayout(dataset).export(tmp_path / "out", mode="move")  # type: ignore
+
+
+def test_to_json_lists_roots_and_files(dataset: Path):
+    doc = json.loads(BidsLayout(dataset, derivatives=True).to_json())
+    roots = {Path(r["path"]): r for r in doc["roots"]}
+    assert roots.keys() == {dataset, dataset / "derivatives/pipeline"}
+    assert roots[dataset]["category"] == "raw"
+    assert roots[dataset]["description"]["Name"] == "example"
+    assert roots[dataset / "derivatives/pipeline"]["category"] == "derivative"
+    files = {Path(f["path"]): f for f in doc["files"]}
+    bold = files[dataset / "sub-01/func/sub-01_task-rest_bold.nii.gz"]
+    assert Path(bold["root"]) == dataset
+    assert bold["entities"] == {
+        "subject": "01",
+        "task": "rest",
+        "datatype": "func",
+        "suffix": "bold",
+        "extension": ".nii.gz",
+    }
+
+
+def test_to_json_respects_filters(dataset: Path):
+    layout = BidsLayout(dataset, derivatives=True).get(subject="02")
+    doc = json.loads(layout.to_json())
+    assert [Path(r["path"]) for r in doc["roots"]] == [dataset]
+    assert [Path(f["path"]) for f in doc["files"]] == [
+        dataset / "sub-02/anat/sub-02_T1w.nii.gz"
+    ]
+
+
+def test_to_json_rejects_paths_that_are_not_unicode(tmp_path: Path):
+    dataset = create_files(
+        tmp_path / os.fsdecode(b"dataset-\xff"),
+        ["dataset_description.json", "sub-01/anat/sub-01_T1w.nii.gz"],
+    )
+    with pytest.raises(ValueError):
+        BidsLayout(dataset).to_json()
+
+
+def test_to_json_round_trips_through_records(dataset: Path):
+    layout = BidsLayout(dataset)
+    doc = json.loads(layout.to_json())
+    # Records are never read from disk, so descriptions are given alongside them
+    rebuilt = BidsLayout.from_records(
+        [{"path": f["path"], **f["entities"]} for f in doc["files"]],
+        descriptions={r["path"]: json.dumps(r["description"]) for r in doc["roots"]},
+    )
+    assert json.loads(rebuilt.to_json()) == doc
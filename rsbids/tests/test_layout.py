@@ -0,0 +1,1313 @@
+from __future__ import annotations
+
+import asyncio
+import json
+import os
+import shutil
+import threading
+import warnings
+from pathlib import Path
+
+import pytest
+
+from rsbids import BidsLayout, BidsPath
+from rsbids._lib import entity_aliases, entity_long_to_short, entity_short_to_long
+from rsbids.tests.helpers import create_files
+
+
+@pytest.fixture
+def malformed(tmp_path: Path):
+    create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/anat/sub-01_T1w.nii.gz",
+            "sub-01/func/sub-01_task-rest_bold.nii.gz",
+        ],
+    )
+    # Filenames that are not valid unicode cannot be parsed
+    anat = os.fsencode(tmp_path / "sub-01" / "anat")
+    (Path(os.fsdecode(anat + b"/sub-01_acq-\xff_T1w.nii.gz"))).touch()
+    return tmp_path
+
+
+@pytest.mark.parametrize("validate", [True, False])
+def test_invalid_paths_are_collected(malformed: Path, validate: bool):
+    layout = BidsLayout(malformed, validate=validate)
+    assert len(layout.invalid_paths) == 1
+    path, reason = layout.invalid_paths[0]
+    assert Path(path).name.startswith("sub-01_acq-")
+    assert "unicode" in reason
+    assert len(layout) == 3
+
+
+def test_unparsed_paths_raise_warning(malformed: Path):
+    with pytest.warns(UserWarning, match="1 file could not be parsed as BIDS"):
+        layout = BidsLayout(malformed)
+    assert len(layout.invalid_paths) == 1
+
+
+def test_quiet_suppresses_unparsed_paths_warning(malformed: Path):
+    with warnings.catch_warnings():
+        warnings.simplefilter("error")
+        BidsLayout(malformed, quiet=True)
+        asyncio.run(BidsLayout.create_async(malformed, quiet=True))
+
+
+def test_create_async_warns_for_unparsed_paths(malformed: Path):
+    with pytest.warns(UserWarning, match="could not be parsed"):
+        asyncio.run(BidsLayout.create_async(malformed))
+
+
+def test_invalid_paths_persist_through_queries(malformed: Path):
+    layout = BidsLayout(malformed, validate=True)
+    assert layout.get(suffix="T1w").invalid_paths == layout.invalid_paths
+
+
+@pytest.mark.skipif(os.name != "posix", reason="requires non-unicode filenames")
+def test_non_unicode_paths_are_skipped(malformed: Path):
+    create_files(malformed, ["sub-01/func/sub-01_run-1_task-rest_bold.nii.gz"])
+    layout = BidsLayout(malformed, validate=True)
+    assert len(layout.invalid_paths) == 2
+    assert layout.skipped_paths == [
+        str(malformed / "sub-01" / "anat" / "sub-01_acq-\ufffd_T1w.nii.gz")
+    ]
+    # Only the paths left out for not being unicode are skipped
+    assert [path for path, _ in layout.invalid_paths if path not in layout.skipped_paths] == [
+        str(malformed / "sub-01" / "func" / "sub-01_run-1_task-rest_bold.nii.gz")
+    ]
+
+
+def test_valid_dataset_has_no_invalid_paths(tmp_path: Path):
+    create_files(tmp_path, ["dataset_description.json", "sub-01/anat/sub-01_T1w.nii.gz"])
+    with warnings.catch_warnings():
+        warnings.simplefilter("error")
+        assert BidsLayout(tmp_path, validate=True).invalid_paths == []
+
+
+@pytest.fixture
+def dataset(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/anat/sub-01_T1w.nii.gz",
+            "sub-01/func/sub-01_task-rest_bold.nii.gz",
+            "sub-02/anat/sub-02_T1w.nii.gz",
+            "sub-02/func/sub-02_task-nback_bold.nii.gz",
+        ],
+    )
+
+
+def test_union(dataset: Path):
+    layout = BidsLayout(dataset)
+    combined = layout.get(task="rest") | layout.get(subject="02", suffix="T1w")
+    assert len(combined) == 2
+    assert set(combined.entities["subject"]) == {"01", "02"}
+    assert combined.entities["task"] == ["rest"]
+    assert set(combined.entities["suffix"]) == {"bold", "T1w"}
+
+
+def test_intersection(dataset: Path):
+    layout = BidsLayout(dataset)
+    combined = layout.get(subject="01") & layout.get(suffix="bold")
+    assert combined.one.entities["task"] == "rest"
+    assert combined.entities["subject"] == ["01"]
+    assert combined.entities["suffix"] == ["bold"]
+
+
+def test_difference(dataset: Path):
+    layout = BidsLayout(dataset)
+    combined = layout.get(suffix=["T1w", "bold"]) - layout.get(subject="01")
+    assert len(combined) == 2
+    assert combined.entities["subject"] == ["02"]
+    assert combined.entities["task"] == ["nback"]
+
+
+def test_combining_unrelated_layouts_raises(dataset: Path):
+    with pytest.raises(ValueError, match="same root index"):
+        BidsLayout(dataset) | BidsLayout(dataset)
+
+
+def test_concat_queries_across_datasets(tmp_path: Path):
+    first = create_files(
+        tmp_path / "first",
+        ["dataset_description.json", "sub-01/anat/sub-01_T1w.nii.gz"],
+    )
+    second = create_files(
+        tmp_path / "second",
+        [
+            "dataset_description.json",
+            "sub-02/anat/sub-02_T1w.nii.gz",
+            "sub-02/func/sub-02_task-rest_bold.nii.gz",
+        ],
+    )
+    layout = BidsLayout(first).concat(BidsLayout(second))
+    assert len(layout) == 5
+    assert set(layout.roots) == {str(first), str(second)}
+    assert set(layout.get(suffix="T1w").entities["subject"]) == {"01", "02"}
+    assert layout.get(task="rest").one.entities["subject"] == "02"
+    assert len(layout.filter(root=str(second))) == 3
+
+
+def test_concat_keeps_only_paths_in_view(dataset: Path, tmp_path: Path):
+    other = create_files(tmp_path / "other", ["sub-03/anat/sub-03_T1w.nii.gz"])
+    layout = BidsLayout(dataset)
+    combined = layout.get(subject="01").concat(BidsLayout(other))
+    assert set(combined.entities["subject"]) == {"01", "03"}
+    assert len(layout.concat(layout)) == len(layout)
+
+
+def test_concat_keeps_options_of_both_layouts(tmp_path: Path):
+    first = create_files(
+        tmp_path / "first",
+        ["dataset_description.json", "sub-01/anat/sub-01_T1w.nii.gz"],
+    )
+    second = create_files(tmp_path / "second", ["sub-02/anat/sub-02_stim-a_T1w.nii.gz"])
+    layout = BidsLayout(first).concat(
+        BidsLayout(
+            second,
+            extra_entities={"stim": "stimulus"},
+            descriptions={second: '{"Name": "second"}'},
+        )
+    )
+    assert layout.get(stimulus="a").one.entities["subject"] == "02"
+    assert layout.description_for(second).name == "second"
+
+
+def test_concat_with_conflicting_options_raises(tmp_path: Path):
+    dataset = create_files(tmp_path, ["sub-01/anat/sub-01_stim-a_T1w.nii.gz"])
+    first = BidsLayout(dataset, extra_entities={"stim": "stimulus"})
+    second = BidsLayout(dataset, extra_entities={"stim": "stimuli"})
+    with pytest.raises(ValueError, match="different extra entities for 'stim'"):
+        first.concat(second)
+
+
+def test_equal_layouts_hash_equal(dataset: Path):
+    layout = BidsLayout(dataset)
+    assert hash(layout.get(subject="01")) == hash(layout.get(subject="01"))
+    assert hash(BidsLayout(dataset)) == hash(layout)
+    assert len({layout, BidsLayout(dataset), layout.get(subject="01")}) == 2
+
+
+def test_layouts_with_same_paths_in_view_are_equal(dataset: Path):
+    layout = BidsLayout(dataset)
+    reordered = BidsLayout.from_paths(reversed([p for p in layout]))
+    assert reordered.get(subject="02") == layout.get(subject="02")
+    assert hash(reordered.get(subject="02")) == hash(layout.get(subject="02"))
+    assert reordered.get(subject="02") != layout.get(subject="01")
+
+
+def test_total_ignores_filters(dataset: Path):
+    layout = BidsLayout(dataset)
+    filtered = layout.get(subject="01")
+    assert len(filtered) < filtered.total == layout.total == len(layout)
+
+
+def test_is_filtered(dataset: Path):
+    layout = BidsLayout(dataset)
+    assert not layout.is_filtered()
+    assert layout.get(subject="01").is_filtered()
+    assert not layout[:].is_filtered()
+    assert layout[1:].is_filtered()
+
+
+def test_contains_indexed_path(dataset: Path):
+    layout = BidsLayout(dataset)
+    assert dataset / "sub-01/anat/sub-01_T1w.nii.gz" in layout
+    assert str(dataset / "sub-01/anat/sub-01_T1w.nii.gz") in layout
+    assert layout.get(subject="01", suffix="T1w").one in layout
+
+
+def test_contains_resolves_relative_paths(dataset: Path, monkeypatch: pytest.MonkeyPatch):
+    monkeypatch.chdir(dataset)
+    assert Path("sub-01/anat/sub-01_T1w.nii.gz") in BidsLayout(dataset)
+    assert dataset / "sub-01/anat/sub-01_T1w.nii.gz" in BidsLayout(".")
+
+
+def test_contains_respects_view(dataset: Path):
+    layout = BidsLayout(dataset).get(subject="02")
+    assert dataset / "sub-01/anat/sub-01_T1w.nii.gz" not in layout
+    assert dataset / "sub-02/anat/sub-02_T1w.nii.gz" in layout
+
+
+def test_contains_unindexed_path(dataset: Path):
+    assert dataset / "sub-03/anat/sub-03_T1w.nii.gz" not in BidsLayout(dataset)
+
+
+def test_slice_preserves_order(dataset: Path):
+    layout = BidsLayout(dataset)
+    paths = list(layout)
+    assert list(layout[1:4]) == paths[1:4]
+    assert list(layout[::2]) == paths[::2]
+    assert len(layout[1:4]) == 3
+
+
+def test_slice_clamps_like_list(dataset: Path):
+    layout = BidsLayout(dataset)
+    paths = list(layout)
+    assert list(layout[-2:]) == paths[-2:]
+    assert len(layout[3:100]) == len(paths[3:100])
+    assert not layout[100:]
+
+
+def test_negative_slice_step_raises(dataset: Path):
+    layout = BidsLayout(dataset)
+    with pytest.raises(ValueError, match="negative step"):
+        layout[::-1]
+    with pytest.raises(ValueError, match="negative step"):
+        layout[3:0:-2]
+
+
+def test_out_of_range_index_raises_index_error(dataset: Path):
+    layout = BidsLayout(dataset)
+    assert layout[-1] == list(layout)[-1]
+    with pytest.raises(IndexError):
+        layout[len(layout)]
+    with pytest.raises(IndexError):
+        layout[-len(layout) - 1]
+
+
+def test_slice_of_view(dataset: Path):
+    layout = BidsLayout(dataset).get(suffix="T1w")
+    sliced = layout[1:]
+    assert len(sliced) == 1
+    assert sliced.entities["subject"] == [list(layout)[1].entities["subject"]]
+
+
+def test_negative_index(dataset: Path):
+    layout = BidsLayout(dataset)
+    assert layout[-1] == list(layout)[-1]
+
+
+def test_first_returns_lowest_index_path(dataset: Path):
+    layout = BidsLayout(dataset)
+    assert layout.first() == layout[0]
+    bold = layout.get(suffix="bold")
+    assert bold.first() == list(bold)[0]
+
+
+def test_first_of_single_path(dataset: Path):
+    layout = BidsLayout(dataset).get(subject="01", suffix="T1w")
+    assert layout.first() == layout.one
+
+
+def test_first_of_empty_layout(dataset: Path):
+    with pytest.raises(ValueError, match="empty"):
+        BidsLayout(dataset).get(subject="03").first()
+
+
+def test_empty_query_is_falsy(dataset: Path):
+    layout = BidsLayout(dataset)
+    empty = layout.get(subject="03")
+    assert len(empty) == 0
+    assert not empty
+    assert not layout - layout
+    assert list(empty) == []
+    assert repr(empty) == "<BidsLayout (len = 0)>\n[]"
+    with pytest.raises(ValueError, match="empty"):
+        empty.one
+
+
+def test_empty_layout_can_be_queried_again(dataset: Path):
+    empty = BidsLayout(dataset).get(subject="03")
+    assert len(empty.get(suffix="T1w")) == 0
+    assert len(empty.filter(root=str(dataset))) == 0
+    assert len(empty[0:2]) == 0
+    with pytest.raises(IndexError):
+        empty[0]
+
+
+def test_root_filter_keeps_current_view(dataset: Path):
+    layout = BidsLayout(dataset).get(subject="01").filter(root=str(dataset))
+    assert layout.entities["subject"] == ["01"]
+    assert len(layout) == 2
+
+
+def test_sorted_paths_are_alphabetical(dataset: Path):
+    forward = BidsLayout([dataset / "sub-01", dataset / "sub-02"])
+    backward = BidsLayout([dataset / "sub-02", dataset / "sub-01"])
+    assert list(forward) != list(backward)
+    assert list(forward.sorted_paths()) == list(backward.sorted_paths())
+    assert list(forward.sorted_paths()) == sorted(forward, key=str)
+
+
+def test_to_records(dataset: Path, monkeypatch: pytest.MonkeyPatch):
+    monkeypatch.chdir(dataset)
+    records = BidsLayout(".").get(subject=True).to_records()
+    assert len(records) == 4
+    assert {Path(r["path"]).resolve() for r in records} == {
+        (dataset / p).resolve()
+        for p in [
+            "sub-01/anat/sub-01_T1w.nii.gz",
+            "sub-01/func/sub-01_task-rest_bold.nii.gz",
+            "sub-02/anat/sub-02_T1w.nii.gz",
+            "sub-02/func/sub-02_task-nback_bold.nii.gz",
+        ]
+    }
+    for record in records:
+        assert {"subject", "datatype", "suffix", "extension"} <= record.keys()
+    # Entities missing from a file are left out rather than set to None
+    assert {"task" in r for r in records} == {True, False}
+    assert all(r["task"] for r in records if "task" in r)
+
+
+def test_parallel_walk_matches_serial(tmp_path: Path):
+    create_files(
+        tmp_path,
+        ["dataset_description.json", ".hidden/sub-99_T1w.nii.gz"]
+        + [
+            f"sub-{sub:02}/ses-{ses}/{dt}/sub-{sub:02}_ses-{ses}_{suffix}.nii.gz"
+            for sub in range(20)
+            for ses in range(3)
+            for dt, suffix in [("anat", "T1w"), ("func", "task-rest_bold")]
+        ]
+        + ["derivatives/pipeline/sub-01/anat/sub-01_desc-x_T1w.nii.gz"],
+    )
+    serial = BidsLayout(tmp_path)
+    parallel = BidsLayout(tmp_path, nthreads=4)
+    assert len(parallel) == len(serial) == 121
+    assert parallel == serial
+    assert [str(path) for path in parallel] == [str(path) for path in serial]
+    assert {k: set(v) for k, v in parallel.entities.items()} == {
+        k: set(v) for k, v in serial.entities.items()
+    }
+
+
+@pytest.fixture
+def bidsignored(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        {
+            "dataset_description.json": "",
+            ".bidsignore": "# comment\n*_scans.tsv\nsub-*/anat/extra/\n",
+            "sub-01/sub-01_scans.tsv": "",
+            "sub-01/anat/sub-01_T1w.nii.gz": "",
+            "sub-01/anat/extra/sub-01_T2w.nii.gz": "",
+            "sub-01/func/sub-01_task-rest_bold.nii.gz": "",
+            "sub-01/func/sub-01_task-rest_scans.tsv": "",
+            "extra/sub-01_T2w.nii.gz": "",
+        },
+    )
+
+
+@pytest.mark.parametrize("nthreads", [1, 4])
+def test_bidsignore_excludes_matches(bidsignored: Path, nthreads: int):
+    layout = BidsLayout(bidsignored, nthreads=nthreads)
+    assert not [p for p in layout if str(p).endswith("_scans.tsv")]
+    assert bidsignored / "sub-01/anat/extra/sub-01_T2w.nii.gz" not in layout
+    # Anchored patterns only match relative to the dataset root
+    assert bidsignored / "extra/sub-01_T2w.nii.gz" in layout
+    assert bidsignored / "sub-01/anat/sub-01_T1w.nii.gz" in layout
+
+
+def test_bidsignore_applies_to_roots_below_dataset(bidsignored: Path):
+    layout = BidsLayout(bidsignored / "sub-01")
+    assert set(layout.entities["suffix"]) == {"T1w", "bold"}
+
+
+def test_invalid_bidsignore_pattern(bidsignored: Path):
+    (bidsignored / ".bidsignore").write_text("sub-[01\n")
+    with pytest.raises(ValueError, match=".bidsignore"):
+        BidsLayout(bidsignored)
+
+
+@pytest.fixture
+def linked(tmp_path: Path):
+    create_files(
+        tmp_path / "atlas",
+        {
+            # Links back to the study are circular and should be skipped
+            "dataset_description.json": '{"Name": "atlas", "DatasetLinks": {"study": "../study"}}',
+            "tpl-MNI/tpl-MNI_T1w.nii.gz": "",
+        },
+    )
+    return create_files(
+        tmp_path / "study",
+        {
+            "dataset_description.json": json.dumps(
+                {
+                    "Name": "study",
+                    "DatasetLinks": {
+                        "atlas": "../atlas",
+                        "remote": "https://example.com/dataset",
+                        "missing": "../missing",
+                    },
+                }
+            ),
+            "sub-01/anat/sub-01_T1w.nii.gz": "",
+            "sub-01/anat/sub-01_space-MNI_T1w.json": '{"Template": "bids:atlas:tpl-MNI"}',
+        },
+    )
+
+
+def test_follow_dataset_links(linked: Path):
+    layout = BidsLayout(linked, follow_links=True)
+    atlas = layout.filter(scope="atlas")
+    assert {Path(p).name for p in atlas} == {
+        "dataset_description.json",
+        "tpl-MNI_T1w.nii.gz",
+    }
+    assert atlas.description.name == "atlas"
+    assert len(layout.filter(scope="raw")) == len(BidsLayout(linked))
+    assert len(layout) == len(BidsLayout(linked)) + 2
+
+
+def test_dataset_links_are_ignored_by_default(linked: Path):
+    layout = BidsLayout(linked)
+    assert not [p for p in layout if "tpl-MNI" in str(p)]
+
+
+@pytest.fixture
+def nested_roots(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        {
+            "dataset_description.json": "{}",
+            "sub-01/anat/sub-01_T1w.nii.gz": "",
+            "derivatives/unlabelled/dataset_description.json": "{}",
+            "derivatives/unlabelled/sub-01/anat/sub-01_desc-x_T1w.nii.gz": "",
+            "derivatives/labelled/dataset_description.json": "{}",
+            "derivatives/labelled/sub-01/anat/sub-01_desc-y_T1w.nii.gz": "",
+        },
+    )
+
+
+@pytest.fixture
+def pipelines(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        {
+            "dataset_description.json": json.dumps({"Name": "raw"}),
+            "sub-01/anat/sub-01_T1w.nii.gz": "",
+            "derivatives/out-a/dataset_description.json": json.dumps(
+                {"GeneratedBy": [{"Name": "prep"}]}
+            ),
+            "derivatives/out-a/sub-01/anat/sub-01_desc-prep_T1w.nii.gz": "",
+            "derivatives/out-b/dataset_description.json": json.dumps(
+                {"GeneratedBy": [{"Name": "seg"}]}
+            ),
+            "derivatives/out-b/sub-01/anat/sub-01_desc-seg_T1w.nii.gz": "",
+        },
+    )
+
+
+def test_by_pipeline_selects_generated_datasets(pipelines: Path):
+    layout = BidsLayout(pipelines, derivatives=True)
+    prep = layout.by_pipeline("prep")
+    assert [Path(p).name for p in prep.get(suffix="T1w")] == [
+        "sub-01_desc-prep_T1w.nii.gz"
+    ]
+    assert all(Path(p).is_relative_to(pipelines / "derivatives/out-a") for p in prep)
+    assert [Path(r) for r in prep.roots] == [pipelines / "derivatives/out-a"]
+
+
+def test_by_pipeline_unknown_lists_pipelines(pipelines: Path):
+    layout = BidsLayout(pipelines, derivatives=True)
+    with pytest.raises(ValueError, match=r"'fmriprep'.*prep.*seg"):
+        layout.by_pipeline("fmriprep")
+
+
+def test_root_for_prefers_most_specific_root(nested_roots: Path):
+    deriv = nested_roots / "derivatives"
+    cases = {
+        "unlabelled": (None, "derivative"),
+        "labelled": ({"proc": deriv / "labelled"}, "proc"),
+    }
+    for name, (derivatives, category) in cases.items():
+        layout = BidsLayout(nested_roots, derivatives=derivatives or deriv / name)
+        roots = {Path(r).resolve(): r for r in layout.roots}
+        for path, root, cat in [
+            ("sub-01/anat/sub-01_T1w.nii.gz", nested_roots, "raw"),
+            (f"derivatives/{name}/sub-01/anat/x.nii.gz", deriv / name, category),
+            (f"derivatives/{name}", deriv / name, category),
+            ("derivatives/other/sub-01_T1w.nii.gz", nested_roots, "raw"),
+        ]:
+            assert layout.root_for(nested_roots / path) == (roots[root.resolve()], cat)
+
+
+def test_roots_detailed(tmp_path: Path):
+    create_files(
+        tmp_path,
+        {
+            "raw/dataset_description.json": json.dumps(
+                {"DatasetLinks": {"proc": "../linked"}}
+            ),
+            "raw/sub-01/anat/sub-01_T1w.nii.gz": "",
+            "raw/sub-02/anat/sub-02_T1w.nii.gz": "",
+            "raw/derivatives/prep/dataset_description.json": "{}",
+            "raw/derivatives/prep/sub-01/anat/sub-01_desc-x_T1w.nii.gz": "",
+            "linked/dataset_description.json": "{}",
+            "linked/sub-01/anat/sub-01_desc-y_T1w.nii.gz": "",
+        },
+    )
+    layout = BidsLayout(tmp_path / "raw", derivatives=True, follow_links=True)
+    details = {Path(r.pop("path")).resolve(): r for r in layout.roots_detailed()}
+    assert details == {
+        (tmp_path / "raw").resolve(): {
+            "category": "raw",
+            "label": None,
+            "files": 3,
+            "has_description": True,
+        },
+        (tmp_path / "raw/derivatives/prep").resolve(): {
+            "category": "derivative",
+            "label": None,
+            "files": 2,
+            "has_description": True,
+        },
+        (tmp_path / "linked").resolve(): {
+            "category": "derivative",
+            "label": "proc",
+            "files": 2,
+            "has_description": True,
+        },
+    }
+    filtered = layout.get(subject="02").roots_detailed()
+    assert [(Path(r["path"]), r["files"]) for r in filtered] == [(tmp_path / "raw", 1)]
+
+
+def test_roots_detailed_without_description(small: Path):
+    [root] = BidsLayout(small).roots_detailed()
+    assert (root["files"], root["has_description"]) == (3, False)
+
+
+def test_root_for_path_outside_layout(nested_roots: Path):
+    assert BidsLayout(nested_roots).root_for("/elsewhere/sub-01_T1w.nii.gz") is None
+
+
+@pytest.mark.parametrize("suffix", ["/", "//", "/.", "/sub-01/.."])
+def test_unnormalized_root(dataset: Path, suffix: str):
+    layout = BidsLayout(str(dataset) + suffix)
+    assert layout.roots == [str(dataset)]
+    t1w = layout.get(subject="01", suffix="T1w").one
+    assert t1w.entities["datatype"] == "anat"
+    assert t1w.relative_path == "sub-01/anat/sub-01_T1w.nii.gz"
+    assert set(layout.entities["subject"]) == {"01", "02"}
+
+
+@pytest.mark.parametrize("suffix", ["", "/"])
+def test_symlinked_root(tmp_path: Path, suffix: str):
+    dataset = create_files(
+        tmp_path / "ds",
+        [
+            "dataset_description.json",
+            "sub-01/func/sub-01_task-rest_bold.nii.gz",
+        ],
+    )
+    link = tmp_path / "link"
+    link.symlink_to(dataset, target_is_directory=True)
+    layout = BidsLayout(str(link) + suffix)
+    assert layout.roots == [str(link)]
+    bold = layout.get(subject="01", suffix="bold").one
+    assert bold.relative_path == "sub-01/func/sub-01_task-rest_bold.nii.gz"
+    assert bold.entities["task"] == "rest"
+
+
+def test_from_paths_infers_roots(tmp_path: Path):
+    create_files(
+        tmp_path,
+        [
+            "ds1/dataset_description.json",
+            "ds1/sub-01/anat/sub-01_T1w.nii.gz",
+            "ds1/sub-02/anat/sub-02_T1w.nii.gz",
+            "ds2/dataset_description.json",
+            "ds2/sub-03/func/sub-03_task-rest_bold.nii.gz",
+        ],
+    )
+    paths = [
+        tmp_path / "ds1/sub-01/anat/sub-01_T1w.nii.gz",
+        str(tmp_path / "ds2/sub-03/func/sub-03_task-rest_bold.nii.gz"),
+        tmp_path / "ds1/sub-02/anat/sub-02_T1w.nii.gz",
+    ]
+    layout = BidsLayout.from_paths(iter(paths))
+    assert len(layout) == 3
+    assert set(layout.entities["subject"]) == {"01", "02", "03"}
+    for path in paths:
+        root, category = layout.root_for(path)
+        assert Path(root) == Path(path).parents[2]
+        assert category == "raw"
+    assert len(layout.filter(root=str(tmp_path / "ds1"))) == 2
+
+
+def test_from_paths_without_description(tmp_path: Path):
+    create_files(tmp_path, ["sub-01/anat/sub-01_T1w.nii.gz"])
+    layout = BidsLayout.from_paths([tmp_path / "sub-01/anat/sub-01_T1w.nii.gz"])
+    assert layout.one.entities["suffix"] == "T1w"
+
+
+def test_from_paths_missing_path_raises(tmp_path: Path):
+    with pytest.raises(OSError, match="does not exist"):
+        BidsLayout.from_paths([tmp_path / "sub-01_T1w.nii.gz"])
+
+
+def test_from_records_matches_walk(dataset: Path):
+    walked = BidsLayout(dataset)
+    layout = BidsLayout.from_records(walked.to_records())
+    assert sorted(map(str, layout)) == sorted(map(str, walked))
+    assert {k: set(v) for k, v in layout.entities.items()} == {
+        k: set(v) for k, v in walked.entities.items()
+    }
+    assert {p.entities["subject"] for p in layout.get(suffix="T1w")} == {"01", "02"}
+    root, category = layout.root_for(dataset / "sub-01/anat/sub-01_T1w.nii.gz")
+    assert Path(root) == dataset.resolve()
+    assert category == "raw"
+
+
+def test_from_records_parses_paths_alone(dataset: Path):
+    records = [{"path": str(p)} for p in BidsLayout(dataset)]
+    layout = BidsLayout.from_records(records)
+    assert set(layout.entities["subject"]) == {"01", "02"}
+    assert set(layout.entities["task"]) == {"rest", "nback"}
+
+
+def test_from_records_without_disk(tmp_path: Path):
+    layout = BidsLayout.from_records(
+        [
+            {"path": str(tmp_path / "ds/dataset_description.json")},
+            {
+                "path": str(tmp_path / "ds/sub-01/anat/sub-01_T1w.nii.gz"),
+                "subject": "01",
+                "datatype": "anat",
+                "suffix": "T1w",
+                "extension": ".nii.gz",
+                "acq": "custom",
+            },
+        ]
+    )
+    # Provided entities are indexed even if absent from the filename
+    assert layout.entities["acquisition"] == ["custom"]
+    assert layout.get(acquisition="custom").one.entities["suffix"] == "T1w"
+    root, _ = layout.root_for(tmp_path / "ds/sub-01/anat/sub-01_T1w.nii.gz")
+    assert Path(root) == tmp_path / "ds"
+
+
+def test_from_records_does_not_read_disk(dataset: Path):
+    records = BidsLayout(dataset).to_records()
+    layout = BidsLayout.from_records(records)
+    assert layout.description_for(dataset) is None
+    assert layout.get(subject="01", task="rest").one.entities["suffix"] == "bold"
+
+
+def test_from_records_requires_path():
+    with pytest.raises(ValueError, match="'path'"):
+        BidsLayout.from_records([{"subject": "01"}])
+
+
+def test_relative_path_within_root(nested_roots: Path):
+    layout = BidsLayout(nested_roots, derivatives=True)
+    raw = layout.filter(scope="raw").get(suffix="T1w").one
+    assert raw.relative_path == "sub-01/anat/sub-01_T1w.nii.gz"
+    for desc in ["x", "y"]:
+        path = layout.get(desc=desc).one
+        assert path.relative_path == f"sub-01/anat/sub-01_desc-{desc}_T1w.nii.gz"
+        assert Path(path.dataset_root, path.relative_path) == Path(path)
+
+
+@pytest.mark.parametrize("validate", [True, False])
+def test_relative_path_of_top_level_sidecars(tmp_path: Path, validate: bool):
+    root = create_files(
+        tmp_path / "ds",
+        [
+            "dataset_description.json",
+            "task-rest_bold.json",
+            "sub-01/func/sub-01_task-rest_bold.nii.gz",
+            "derivatives/pipeline/dataset_description.json",
+            "derivatives/pipeline/task-rest_bold.json",
+        ],
+    )
+    layout = BidsLayout(root, derivatives=True, validate=validate)
+    for path in layout.get(suffix="bold", extension=".json"):
+        expected, _ = layout.root_for(path)
+        assert path.dataset_root == expected
+        assert path.relative_path == "task-rest_bold.json"
+        assert "datatype" not in path.entities
+
+
+def test_relative_path_without_root():
+    path = BidsPath("sub-01/anat/sub-01_T1w.nii.gz")
+    assert path.relative_path == "sub-01/anat/sub-01_T1w.nii.gz"
+
+
+@pytest.fixture
+def extra_dir(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/anat/sub-01_T1w.nii.gz",
+            "extra/sub-02/anat/sub-02_T1w.nii.gz",
+        ],
+    )
+
+
+def test_head_directly_under_root(extra_dir: Path):
+    path = BidsLayout(extra_dir).get(subject="01").one
+    assert path.head == str(extra_dir)
+    assert path.root_offset == len(str(extra_dir))
+
+
+def test_head_under_extra_directory(extra_dir: Path):
+    path = BidsLayout(extra_dir).get(subject="02").one
+    assert path.head == str(extra_dir / "extra")
+    assert path.dataset_root == str(extra_dir)
+    assert path.root_offset == len(str(extra_dir))
+
+
+def test_relative_to_recomputes_head(extra_dir: Path):
+    path = BidsLayout(extra_dir).get(subject="02").one
+    relative = path.relative_to(extra_dir)
+    assert relative.head == "extra"
+    assert relative.root_offset == 0
+    assert relative.entities == path.entities
+
+
+def test_absolute_recomputes_head(extra_dir: Path, monkeypatch: pytest.MonkeyPatch):
+    monkeypatch.chdir(extra_dir.parent)
+    path = BidsLayout(extra_dir).get(subject="02").one.relative_to(extra_dir.parent)
+    absolute = path.absolute()
+    assert absolute.head == str(extra_dir / "extra")
+    assert absolute.root_offset == len(str(extra_dir))
+
+
+def test_heads_of_layout(extra_dir: Path):
+    layout = BidsLayout(extra_dir).get(suffix="T1w")
+    assert layout.heads() == [str(extra_dir), str(extra_dir / "extra")]
+    assert layout.get(subject="01").heads() == [str(extra_dir)]
+
+
+def test_malformed_description_is_reported(tmp_path: Path):
+    dataset = create_files(
+        tmp_path,
+        {
+            "dataset_description.json": '{"Name": "broken",',
+            "sub-01/anat/sub-01_T1w.nii.gz": "",
+        },
+    )
+    layout = BidsLayout(dataset)
+    assert len(layout.get(suffix="T1w")) == 1
+    [(root, err)] = layout.description_errors
+    assert Path(root).resolve() == dataset.resolve()
+    assert "EOF" in err
+
+
+def test_missing_description_is_not_an_error(tmp_path: Path):
+    dataset = create_files(tmp_path, ["sub-01/anat/sub-01_T1w.nii.gz"])
+    assert BidsLayout(dataset).description_errors == []
+
+
+@pytest.fixture
+def scrambled(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/func/sub-01_task-rest_run-1_bold.nii.gz",
+            "sub-01/func/sub-01_run-2_task-rest_bold.nii.gz",
+            "sub-01/anat/sub-01_desc-preproc_foo-bar_T1w.nii.gz",
+        ],
+    )
+
+
+def test_validate_rejects_entities_out_of_order(scrambled: Path):
+    layout = BidsLayout(scrambled, validate=True)
+    assert [(Path(p).name, reason) for p, reason in layout.invalid_paths] == [
+        (
+            "sub-01_run-2_task-rest_bold.nii.gz",
+            f"'{scrambled / 'sub-01/func/sub-01_run-2_task-rest_bold.nii.gz'}' has entities"
+            " out of bids order",
+        )
+    ]
+    assert layout.get(suffix="bold").one.entities["run"] == "1"
+    assert len(layout.get(suffix="T1w")) == 1
+
+
+def test_entity_order_is_not_checked_without_validate(scrambled: Path):
+    layout = BidsLayout(scrambled)
+    assert layout.invalid_paths == []
+    assert set(layout.get(suffix="bold").entities["run"]) == {"1", "2"}
+
+
+def test_extra_entities_are_recognized_as_parents(tmp_path: Path):
+    dataset = create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/cohort-a/anat/sub-01_T1w.nii.gz",
+            "sub-02/cohort-b/anat/sub-02_T1w.nii.gz",
+        ],
+    )
+    layout = BidsLayout(dataset, extra_entities={"cohort": "cohort"})
+    assert set(layout.entities["cohort"]) == {"a", "b"}
+    assert len(layout.get(cohort="a")) == 1
+
+
+def test_extra_entities_accept_long_names(tmp_path: Path):
+    dataset = create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/func/sub-01_task-rest_stim-flash_bold.nii.gz",
+        ],
+    )
+    layout = BidsLayout(dataset, extra_entities={"stim": "stimulus"})
+    assert layout.get(stimulus="flash").one.entities["stimulus"] == "flash"
+
+
+def test_extra_entities_conflicting_with_existing_raise(tmp_path: Path):
+    dataset = create_files(tmp_path, ["sub-01/anat/sub-01_T1w.nii.gz"])
+    with pytest.raises(ValueError):
+        BidsLayout(dataset, extra_entities={"sub": "cohort"})
+
+
+@pytest.fixture
+def problems(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "participants.tsv",
+            "task-rest_bold.json",
+            "sub-01/sub-01_scans.tsv",
+            "sub-01/anat/sub-01_T1w.nii.gz",
+            "sub-01/foo/sub-01_T1w.nii.gz",
+            "sub-01/func/sub-01_task-rest.nii.gz",
+            "sub-01/anat/sub-01_run-1_run-2_T1w.nii.gz",
+        ],
+    )
+
+
+def _issues(layout: BidsLayout, issue: str):
+    return [r for r in layout.validate() if r["issue"] == issue]
+
+
+def test_validate_unknown_datatype(problems: Path):
+    (issue,) = _issues(BidsLayout(problems), "unknown_datatype")
+    assert issue["path"] == str(problems / "sub-01/foo/sub-01_T1w.nii.gz")
+    assert "datatype" in issue["message"]
+
+
+def test_validate_missing_suffix(problems: Path):
+    (issue,) = _issues(BidsLayout(problems), "missing_suffix")
+    assert issue["path"] == str(problems / "sub-01/func/sub-01_task-rest.nii.gz")
+
+
+def test_validate_duplicate_entity(problems: Path):
+    (issue,) = _issues(BidsLayout(problems), "duplicate_entity")
+    assert issue["path"] == str(problems / "sub-01/anat/sub-01_run-1_run-2_T1w.nii.gz")
+    assert issue["entity"] == "run"
+
+
+def test_validate_agreeing_entities(problems: Path):
+    assert _issues(BidsLayout(problems), "entity_mismatch") == []
+
+
+def test_validate_entity_mismatch(tmp_path: Path):
+    create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/ses-1/anat/sub-01_ses-1_T1w.nii.gz",
+            "sub-01/ses-1/anat/sub-02_ses-2_T1w.nii.gz",
+        ],
+    )
+    issues = _issues(BidsLayout(tmp_path), "entity_mismatch")
+    path = str(tmp_path / "sub-01/ses-1/anat/sub-02_ses-2_T1w.nii.gz")
+    assert {(i["path"], i["entity"], i["parent_value"], i["file_value"]) for i in issues} == {
+        (path, "subject", "01", "02"),
+        (path, "session", "1", "2"),
+    }
+
+
+def test_validate_respects_view(problems: Path):
+    assert BidsLayout(problems).get(suffix="scans").validate() == []
+    assert BidsLayout(problems).get(datatype="anat").validate() != []
+
+
+def test_numeric_entity_values_sort_naturally(tmp_path: Path):
+    dataset = create_files(
+        tmp_path,
+        [
+            f"sub-01/func/sub-01_task-rest_run-{run}_bold.nii.gz"
+            for run in ["10", "2", "1", "02"]
+        ],
+    )
+    assert BidsLayout(dataset).entities["run"] == ["1", "02", "2", "10"]
+
+
+def test_alphanumeric_entity_values_sort_alphabetically(tmp_path: Path):
+    dataset = create_files(
+        tmp_path,
+        [f"sub-{sub}/anat/sub-{sub}_T1w.nii.gz" for sub in ["10", "2", "b1", "a2"]],
+    )
+    assert BidsLayout(dataset).entities["subject"] == ["10", "2", "a2", "b1"]
+
+
+@pytest.fixture
+def small(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        [
+            "sub-01/anat/sub-01_T1w.nii.gz",
+            "sub-01/func/sub-01_task-rest_bold.nii.gz",
+            "sub-02/anat/sub-02_T1w.nii.gz",
+        ],
+    )
+
+
+def test_tree_shows_nesting(small: Path):
+    lines = BidsLayout(small).tree().splitlines()
+    assert lines[0] == f"{small}{os.sep}"
+    assert lines[1:] == [
+        f"    sub-01{os.sep}",
+        f"        anat{os.sep}",
+        "            sub-01_T1w.nii.gz",
+        f"        func{os.sep}",
+        "            sub-01_task-rest_bold.nii.gz",
+        f"    sub-02{os.sep}",
+        f"        anat{os.sep}",
+        "            sub-02_T1w.nii.gz",
+    ]
+
+
+def test_tree_limits_depth(small: Path):
+    lines = BidsLayout(small).tree(max_depth=1).splitlines()
+    assert lines[1:] == [f"    sub-01{os.sep}", f"    sub-02{os.sep}"]
+
+
+def test_tree_respects_view(small: Path):
+    tree = BidsLayout(small).get(datatype="anat").tree()
+    assert "func" not in tree
+    assert "sub-02_T1w.nii.gz" in tree
+
+
+def test_files_under_subject(small: Path):
+    files = BidsLayout(small).files_under(small / "sub-01")
+    assert sorted(str(f) for f in files) == [
+        str(small / "sub-01/anat/sub-01_T1w.nii.gz"),
+        str(small / "sub-01/func/sub-01_task-rest_bold.nii.gz"),
+    ]
+
+
+def test_files_under_respects_view(small: Path):
+    files = BidsLayout(small).get(datatype="anat").files_under(small / "sub-01")
+    assert [str(f) for f in files] == [str(small / "sub-01/anat/sub-01_T1w.nii.gz")]
+
+
+def test_files_under_relative_path(small: Path, monkeypatch: pytest.MonkeyPatch):
+    monkeypatch.chdir(small)
+    assert len(BidsLayout(small).files_under("sub-02")) == 1
+
+
+def test_files_under_missing_directory(small: Path):
+    assert BidsLayout(small).files_under(small / "sub-03") == []
+
+
+def test_get_file(small: Path):
+    path = small / "sub-01/func/sub-01_task-rest_bold.nii.gz"
+    found = BidsLayout(small).get_file(path)
+    assert found is not None
+    assert str(found) == str(path)
+    assert found.entities["task"] == "rest"
+    assert found.entities["datatype"] == "func"
+
+
+def test_get_file_relative_path(small: Path, monkeypatch: pytest.MonkeyPatch):
+    monkeypatch.chdir(small)
+    found = BidsLayout(small).get_file("sub-02/anat/sub-02_T1w.nii.gz")
+    assert found is not None
+    assert found.entities["subject"] == "02"
+
+
+def test_get_file_unknown_path(small: Path):
+    layout = BidsLayout(small)
+    assert layout.get_file(small / "sub-01/anat/sub-01_T2w.nii.gz") is None
+    assert layout.get_file(small / "sub-03/anat/sub-03_T1w.nii.gz") is None
+    assert layout.get(subject="02").get_file(small / "sub-01/anat/sub-01_T1w.nii.gz") is None
+
+
+@pytest.fixture
+def many(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        [
+            f"sub-{sub:03}/func/sub-{sub:03}_task-rest_run-{run}_bold.nii.gz"
+            for sub in range(100)
+            for run in range(1, 6)
+        ],
+    )
+
+
+def test_progress_is_reported_while_indexing(many: Path):
+    found: list[int] = []
+    BidsLayout(many, on_progress=found.append, progress_every=75)
+    assert found == [75, 150, 225, 300, 375, 450]
+
+
+def test_progress_is_reported_with_multiple_threads(many: Path):
+    found: list[int] = []
+    BidsLayout(many, nthreads=2, on_progress=found.append, progress_every=100)
+    assert found == sorted(found)
+    assert found[-1] == 500
+
+
+def test_errors_in_progress_callback_stop_indexing(many: Path):
+    found: list[int] = []
+
+    def on_progress(n: int):
+        found.append(n)
+        raise RuntimeError("stop")
+
+    with pytest.raises(RuntimeError, match="stop"):
+        BidsLayout(many, on_progress=on_progress, progress_every=10)
+    assert found == [10]
+
+
+def test_create_async_matches_create(many: Path):
+    layout = asyncio.run(BidsLayout.create_async(many))
+    assert layout == BidsLayout(many)
+
+
+def test_create_async_indexes_in_parallel_order(many: Path):
+    layout = asyncio.run(BidsLayout.create_async(many))
+    parallel = BidsLayout(many, nthreads=4)
+    assert [str(path) for path in layout] == [str(path) for path in parallel]
+
+
+def test_create_async_reports_progress(many: Path):
+    found: list[int] = []
+    asyncio.run(
+        BidsLayout.create_async(many, on_progress=found.append, progress_every=100)
+    )
+    assert found == [100, 200, 300, 400, 500]
+
+
+def test_create_async_can_be_cancelled(many: Path):
+    started = threading.Event()
+    resume = threading.Event()
+    found: list[int] = []
+
+    def progress(n: int):
+        found.append(n)
+        started.set()
+        resume.wait(timeout=10)
+
+    async def main():
+        task = asyncio.create_task(
+            BidsLayout.create_async(many, on_progress=progress, progress_every=10)
+        )
+        await asyncio.get_running_loop().run_in_executor(None, started.wait)
+        task.cancel()
+        # Let the task register the cancellation before the walk continues
+        await asyncio.sleep(0)
+        resume.set()
+        with pytest.raises(asyncio.CancelledError):
+            await task
+
+    asyncio.run(main())
+    assert found == [10]
+
+
+def test_root_given_with_its_own_file_is_indexed_once(tmp_path: Path):
+    dataset = create_files(
+        tmp_path / "ds",
+        ["dataset_description.json", "sub-01/anat/sub-01_T1w.nii.gz"],
+    )
+    layout = BidsLayout([dataset, dataset / "sub-01/anat/sub-01_T1w.nii.gz"])
+    assert layout.roots == [str(dataset)]
+
+
+def test_roots_resolving_to_same_dataset_are_merged(tmp_path: Path):
+    dataset = create_files(
+        tmp_path / "ds",
+        ["dataset_description.json", "sub-01/anat/sub-01_T1w.nii.gz"],
+    )
+    (tmp_path / "link").symlink_to(dataset)
+    layout = BidsLayout([dataset, tmp_path / "ds/../ds", tmp_path / "link"])
+    assert layout.roots == [str(dataset)]
+
+
+def test_files_under_repeated_roots_are_indexed_once(tmp_path: Path):
+    dataset = create_files(
+        tmp_path / "ds",
+        ["dataset_description.json", "sub-01/anat/sub-01_T1w.nii.gz"],
+    )
+    (tmp_path / "link").symlink_to(dataset)
+    layout = BidsLayout([dataset, f"{dataset}/", tmp_path / "link"])
+    assert len(layout) == 2
+    assert len(set(map(str, layout))) == 2
+
+
+@pytest.mark.parametrize("labelled", [False, True])
+def test_overlapping_derivative_is_indexed_once(tmp_path: Path, labelled: bool):
+    dataset = create_files(
+        tmp_path / "ds",
+        [
+            "dataset_description.json",
+            "sub-01/anat/sub-01_T1w.nii.gz",
+            "derivatives/prep/dataset_description.json",
+            "derivatives/prep/sub-01/anat/sub-01_desc-preproc_T1w.nii.gz",
+        ],
+    )
+    deriv = dataset / "derivatives/prep"
+    derivatives = {"a": deriv, "b": f"{deriv}/"} if labelled else [deriv, deriv]
+    layout = BidsLayout(dataset, derivatives=derivatives)
+    assert len(layout) == 4
+    assert len(set(map(str, layout))) == 4
+    assert len(layout.filter(root=deriv)) == 2
+    assert len(layout.derivatives) == 2
+
+
+def test_common_prefix_of_dataset(small: Path):
+    assert BidsLayout(small).common_prefix() == str(small)
+
+
+def test_common_prefix_of_single_path(small: Path):
+    layout = BidsLayout(small).get(subject="01", datatype="anat")
+    assert layout.common_prefix() == str(small / "sub-01" / "anat")
+
+
+def test_common_prefix_of_empty_layout(small: Path):
+    assert BidsLayout(small).get(subject="03").common_prefix() is None
+
+
+def test_common_prefix_across_roots(tmp_path: Path):
+    first = create_files(tmp_path / "data/first", ["sub-01/anat/sub-01_T1w.nii.gz"])
+    second = create_files(tmp_path / "data/second", ["sub-01/anat/sub-01_T1w.nii.gz"])
+    assert BidsLayout([first, second]).common_prefix() == str(tmp_path / "data")
+
+
+def test_parse_assigns_known_root(tmp_path: Path):
+    dataset = create_files(
+        tmp_path / "ds",
+        ["dataset_description.json", "sub-01/anat/sub-01_T1w.nii.gz"],
+    )
+    path = BidsLayout(dataset).parse(dataset / "sub-02/anat/sub-02_T1w.nii.gz")
+    assert path.dataset_root == str(dataset)
+    assert path.relative_path == "sub-02/anat/sub-02_T1w.nii.gz"
+
+
+def test_parse_does_not_need_root_on_disk(tmp_path: Path):
+    dataset = create_files(
+        tmp_path / "ds",
+        ["dataset_description.json", "sub-01/anat/sub-01_T1w.nii.gz"],
+    )
+    layout = BidsLayout(dataset)
+    shutil.rmtree(dataset)
+    path = layout.parse(dataset / "sub-02/anat/sub-02_T1w.nii.gz")
+    assert path.dataset_root == str(dataset)
+    assert path.entities["subject"] == "02"
+
+
+def test_entity_aliases_map_short_to_long():
+    aliases = entity_aliases()
+    assert aliases["sub"] == "subject"
+    assert aliases["desc"] == "desc"
+    for short, long in aliases.items():
+        assert entity_short_to_long(short) == long
+        assert entity_long_to_short(long) == short
+
+
+def test_extra_entities_are_kept_with_their_layout(tmp_path: Path):
+    dataset = create_files(tmp_path, ["sub-01/anat/sub-01_track-left_T1w.nii.gz"])
+    tracking = BidsLayout(dataset, extra_entities={"track": "tracking"})
+    tracker = BidsLayout(dataset, extra_entities={"track": "tracker"})
+    assert "track" not in entity_aliases()
+    assert tracking.get(tracking="left").one.entities["tracking"] == "left"
+    assert tracker.get(tracker="left").one.entities["tracker"] == "left"
+    with pytest.raises(KeyError):
+        BidsLayout(dataset).get(tracking="left")
+
+
+@pytest.fixture
+def with_sourcedata(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/anat/sub-01_T1w.nii.gz",
+            "sourcedata/sub-01/anat/sub-01_T1w.dcm",
+            "code/run.sh",
+            "derivatives/pipeline/dataset_description.json",
+            "derivatives/pipeline/sub-01/anat/sub-01_desc-preproc_T1w.nii.gz",
+        ],
+    )
+
+
+def _top_dirs(layout: BidsLayout, root: Path):
+    return {Path(path).relative_to(root).parts[0] for path in layout}
+
+
+def test_sourcedata_ignored_by_default(with_sourcedata: Path):
+    layout = BidsLayout(with_sourcedata)
+    assert _top_dirs(layout, with_sourcedata) == {"dataset_description.json", "sub-01"}
+
+
+def test_ignore_dirs_replaces_defaults(with_sourcedata: Path):
+    layout = BidsLayout(with_sourcedata, ignore_dirs={"derivatives", "code"})
+    assert _top_dirs(layout, with_sourcedata) == {
+        "dataset_description.json",
+        "sub-01",
+        "sourcedata",
+    }
+
+
+def test_empty_ignore_dirs_indexes_everything(with_sourcedata: Path):
+    layout = BidsLayout(with_sourcedata, ignore_dirs=set())
+    assert _top_dirs(layout, with_sourcedata) == {
+        "dataset_description.json",
+        "sub-01",
+        "sourcedata",
+        "code",
+        "derivatives",
+    }
+
+
+def test_ignore_dirs_keeps_derivatives_separate(with_sourcedata: Path):
+    layout = BidsLayout(with_sourcedata, derivatives=True, ignore_dirs=set())
+    raw = layout.filter(scope="raw")
+    assert _top_dirs(raw, with_sourcedata) == {
+        "dataset_description.json",
+        "sub-01",
+        "sourcedata",
+        "code",
+    }
+    assert len(layout.filter(scope="derivatives")) == 2
+
+
+@pytest.mark.parametrize("nthreads", [1, 2])
+def test_include_indexes_only_matching_files(small: Path, nthreads: int):
+    layout = BidsLayout(small, include="**/*_bold.*", nthreads=nthreads)
+    assert [Path(path).name for path in layout] == ["sub-01_task-rest_bold.nii.gz"]
+
+
+def test_include_accepts_multiple_globs(small: Path):
+    layout = BidsLayout(small, include=["sub-01/**", "**/*_bold.*"])
+    assert len(layout) == 2
+    assert layout.entities["subject"] == ["01"]
+
+
+def test_include_still_discovers_roots(tmp_path: Path):
+    dataset = create_files(
+        tmp_path,
+        {
+            "dataset_description.json": json.dumps({"Name": "included"}),
+            "sub-01/anat/sub-01_T1w.nii.gz": "",
+            "sub-01/func/sub-01_task-rest_bold.nii.gz": "",
+        },
+    )
+    layout = BidsLayout(dataset, include="**/*_bold.*")
+    assert layout.roots == [str(dataset)]
+    assert layout.description.name == "included"
+    assert len(layout) == 1
+
+
+def test_include_composes_with_bidsignore(small: Path):
+    (small / ".bidsignore").write_text("sub-02/\n")
+    layout = BidsLayout(small, include="**/*_T1w.*")
+    assert layout.entities["subject"] == ["01"]
+
+
+def test_invalid_include_raises(small: Path):
+    with pytest.raises(ValueError, match="Invalid include pattern"):
+        BidsLayout(small, include="sub-{01")
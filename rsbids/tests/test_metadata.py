@@ -0,0 +1,381 @@
+from __future__ import annotations
+
+import json
+from concurrent.futures import ThreadPoolExecutor
+from pathlib import Path
+
+import pytest
+
+from rsbids import BidsLayout, BidsPath
+from rsbids.tests.helpers import create_files
+
+
+@pytest.fixture
+def inheritance(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        {
+            "dataset_description.json": "{}",
+            "task-rest_bold.json": json.dumps({"RepetitionTime": 2, "Level": "top"}),
+            "task-nback_bold.json": json.dumps({"RepetitionTime": 3}),
+            "sub-01/func/sub-01_task-rest_bold.json": json.dumps({"RepetitionTime": 1}),
+            "sub-01/func/sub-01_task-rest_bold.nii.gz": "",
+            "sub-01/func/sub-01_task-nback_bold.nii.gz": "",
+            "sub-02/sub-02_task-rest_bold.json": json.dumps({"Level": "subject"}),
+            "sub-02/func/sub-02_task-rest_bold.nii.gz": "",
+            "sub-02/func/sub-02_task-rest_acq-fast_bold.nii.gz": "",
+        },
+    )
+
+
+def _names(layout: BidsLayout):
+    return {Path(p).name for p in layout if not str(p).endswith(".json")}
+
+
+def test_top_level_sidecars_apply_by_task(inheritance: Path):
+    layout = BidsLayout(inheritance).index_metadata()
+    assert _names(layout.get(RepetitionTime="3")) == {"sub-01_task-nback_bold.nii.gz"}
+    assert _names(layout.get(RepetitionTime="2")) == {
+        "sub-02_task-rest_bold.nii.gz",
+        "sub-02_task-rest_acq-fast_bold.nii.gz",
+    }
+
+
+def test_nearer_sidecar_overrides(inheritance: Path):
+    layout = BidsLayout(inheritance).index_metadata()
+    assert _names(layout.get(RepetitionTime="1")) == {"sub-01_task-rest_bold.nii.gz"}
+    # Keys missing from the nearer sidecar are still inherited
+    assert _names(layout.get(Level="top")) == {"sub-01_task-rest_bold.nii.gz"}
+    assert _names(layout.get(Level="subject")) == {
+        "sub-02_task-rest_bold.nii.gz",
+        "sub-02_task-rest_acq-fast_bold.nii.gz",
+    }
+
+
+def test_sidecars_do_not_cross_subjects(inheritance: Path):
+    layout = BidsLayout(inheritance).index_metadata()
+    assert not _names(layout.get(RepetitionTime="1")) & _names(layout.get(subject="02"))
+    assert not _names(layout.get(Level="subject")) & _names(layout.get(subject="01"))
+
+
+def test_metadata_keys_named_like_entities(tmp_path: Path):
+    dataset = create_files(
+        tmp_path,
+        {
+            "dataset_description.json": "{}",
+            "sub-01/anat/sub-01_mod-T1w_defacemask.json": json.dumps({"Modality": "MR"}),
+            "sub-01/anat/sub-01_mod-T1w_defacemask.nii.gz": "",
+        },
+    )
+    layout = BidsLayout(dataset).index_metadata()
+    assert _names(layout.get(Modality="MR")) == {"sub-01_mod-T1w_defacemask.nii.gz"}
+    assert not layout.get(Modality="T1w")
+
+
+def test_filename_and_metadata_terms_intersect(inheritance: Path):
+    layout = BidsLayout(inheritance).index_metadata()
+    sub02 = layout.get(subject="02", RepetitionTime="2", extension=".nii.gz")
+    assert _names(sub02) == {
+        "sub-02_task-rest_bold.nii.gz",
+        "sub-02_task-rest_acq-fast_bold.nii.gz",
+    }
+    assert sub02.metadata["RepetitionTime"] == ["2"]
+    assert sub02.metadata["Level"] == ["subject"]
+    assert sub02.entities["subject"] == ["02"]
+    assert len(layout.get(subject="01", RepetitionTime="2")) == 0
+
+
+def test_metadata_reflects_filtered_view(inheritance: Path):
+    layout = BidsLayout(inheritance).index_metadata()
+    sub01 = layout.get(subject="01")
+    assert set(sub01.metadata["RepetitionTime"]) == {"1", "3"}
+    assert sub01.metadata["Level"] == ["top"]
+    sub02 = layout.get(subject="02")
+    assert sub02.metadata["RepetitionTime"] == ["2"]
+    assert sub02.metadata["Level"] == ["subject"]
+
+
+def test_index_metadata_after_filtering(inheritance: Path):
+    # Sidecars outside the filtered view still apply to the paths within it
+    sub01 = BidsLayout(inheritance).get(subject="01", extension=".nii.gz")
+    sub01.index_metadata()
+    assert set(sub01.metadata["RepetitionTime"]) == {"1", "3"}
+    assert sub01.metadata["Level"] == ["top"]
+    assert _names(sub01.get(RepetitionTime="3")) == {"sub-01_task-nback_bold.nii.gz"}
+
+
+@pytest.mark.parametrize("indexed", ["left", "right", "both"])
+def test_union_keeps_metadata_of_both_sides(inheritance: Path, indexed: str):
+    layout = BidsLayout(inheritance)
+    sub01 = layout.get(subject="01", extension=".nii.gz")
+    sub02 = layout.get(subject="02", extension=".nii.gz")
+    if indexed in {"left", "both"}:
+        sub01.index_metadata()
+    if indexed in {"right", "both"}:
+        sub02.index_metadata()
+    combined = sub01 | sub02
+    assert set(combined.metadata["Level"]) == {"top", "subject"}
+    assert set(combined.metadata["RepetitionTime"]) == {"1", "2", "3"}
+
+
+@pytest.fixture
+def structured(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        {
+            "dataset_description.json": "{}",
+            "task-rest_bold.json": json.dumps(
+                {
+                    "SliceTiming": [0.0, 0.5, 1.0],
+                    "Manufacturer": {"Name": "Siemens", "Coils": [32]},
+                    "RepetitionTime": 2,
+                }
+            ),
+            "sub-01/func/sub-01_task-rest_bold.json": json.dumps(
+                {"RepetitionTime": 1.5, "MultibandAccelerationFactor": None}
+            ),
+            "sub-01/func/sub-01_task-rest_bold.nii.gz": "",
+        },
+    )
+
+
+def test_raw_metadata_keeps_json_types(structured: Path):
+    layout = BidsLayout(structured).index_metadata()
+    bold = layout.get(extension=".nii.gz").one
+    assert bold.get_metadata() == {
+        "SliceTiming": [0.0, 0.5, 1.0],
+        "Manufacturer": {"Name": "Siemens", "Coils": [32]},
+        "RepetitionTime": 1.5,
+        "MultibandAccelerationFactor": None,
+    }
+    assert layout.get_raw_metadata(bold) == bold.get_metadata()
+
+
+def test_metadata_strings_come_from_raw_values(structured: Path):
+    layout = BidsLayout(structured).index_metadata()
+    bold = layout.get(extension=".nii.gz").one
+    # Arrays and objects can't be queried, so are only found in the raw values
+    assert bold.metadata == {
+        "RepetitionTime": "1.5",
+        "MultibandAccelerationFactor": "null",
+    }
+
+
+def test_raw_metadata_requires_index(structured: Path):
+    layout = BidsLayout(structured)
+    with pytest.raises(AttributeError, match="index_metadata"):
+        layout.get(extension=".nii.gz").one.get_metadata()
+
+
+def test_metadata_for_path(inheritance: Path):
+    layout = BidsLayout(inheritance).index_metadata()
+    sub01 = layout.get(subject="01", task="rest", extension=".nii.gz").one
+    # Nearer sidecar overrides the top level, while other keys are inherited
+    assert layout.metadata_for(sub01) == {"RepetitionTime": "1", "Level": "top"}
+    # The top level value is inherited as is
+    for path in layout.get(subject="02", extension=".nii.gz"):
+        assert layout.metadata_for(path) == {"RepetitionTime": "2", "Level": "subject"}
+
+
+def test_metadata_accessor(inheritance: Path):
+    layout = BidsLayout(inheritance).index_metadata()
+    for path in layout:
+        if path.entities["extension"] == ".nii.gz":
+            assert path.metadata == layout.metadata_for(path)
+
+
+def test_metadata_without_index_is_read_from_disk(inheritance: Path):
+    layout = BidsLayout(inheritance)
+    sub01 = layout.get(subject="01", task="rest", extension=".nii.gz").one
+    assert sub01.metadata == {"RepetitionTime": 1, "Level": "top"}
+
+
+def test_metadata_of_path_outside_layout_is_read_from_disk(
+    inheritance: Path, tmp_path: Path, monkeypatch: pytest.MonkeyPatch
+):
+    elsewhere = tmp_path / "elsewhere"
+    elsewhere.mkdir()
+    monkeypatch.chdir(elsewhere)
+    layout = BidsLayout(inheritance).index_metadata()
+    sub01 = layout.get(subject="01", task="rest", extension=".nii.gz").one
+    # Relative to the working directory, the derived path is no longer in the layout
+    derived = sub01.relative_to(inheritance)
+    assert derived not in layout
+    assert derived.metadata == derived._read_metadata()
+
+
+def test_sidecars_are_listed_nearest_last(inheritance: Path):
+    layout = BidsLayout(inheritance).index_metadata()
+    sub01 = layout.get(subject="01", task="rest", extension=".nii.gz").one
+    expected = [
+        inheritance / "task-rest_bold.json",
+        inheritance / "sub-01/func/sub-01_task-rest_bold.json",
+    ]
+    assert sub01.sidecars == expected
+    assert [Path(p) for p in layout.sidecars_for(sub01)] == expected
+    # A sidecar does not apply to itself
+    assert layout.get(subject="01", task="rest", extension=".json").one.sidecars == [
+        inheritance / "task-rest_bold.json"
+    ]
+
+
+def test_sidecars_of_standalone_path(inheritance: Path):
+    path = BidsPath(inheritance / "sub-02/func/sub-02_task-rest_bold.nii.gz")
+    assert path.sidecars == [
+        inheritance / "task-rest_bold.json",
+        inheritance / "sub-02/sub-02_task-rest_bold.json",
+    ]
+
+
+def test_sidecars_without_index_are_found_on_disk(inheritance: Path):
+    layout = BidsLayout(inheritance)
+    sub01 = layout.get(subject="01", task="rest", extension=".nii.gz").one
+    assert hasattr(sub01, "sidecars")
+    assert sub01.sidecars == [
+        inheritance / "task-rest_bold.json",
+        inheritance / "sub-01/func/sub-01_task-rest_bold.json",
+    ]
+
+
+def test_index_metadata_from_threads(inheritance: Path):
+    layout = BidsLayout(inheritance)
+    with ThreadPoolExecutor(max_workers=8) as pool:
+        results = list(pool.map(lambda _: layout.index_metadata(), range(16)))
+    assert all(result is layout for result in results)
+    assert _names(layout.get(RepetitionTime="1")) == {"sub-01_task-rest_bold.nii.gz"}
+    expected = BidsLayout(inheritance).index_metadata().to_records()
+    assert layout.to_records() == expected
+
+
+def test_metadata_for_unknown_path(inheritance: Path):
+    layout = BidsLayout(inheritance).index_metadata()
+    with pytest.raises(KeyError):
+        layout.metadata_for(inheritance / "sub-03_task-rest_bold.nii.gz")
+
+
+@pytest.fixture
+def participants(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        {
+            "dataset_description.json": "{}",
+            "participants.tsv": (
+                "participant_id\tage\tgroup\n"
+                "sub-01\t25\tcontrol\n"
+                "sub-02\tn/a\tpatient\n"
+                "sub-03\t40\n"
+                "03\t40\tcontrol\n"
+            ),
+            "sub-01/anat/sub-01_T1w.nii.gz": "",
+            "sub-01/func/sub-01_task-rest_bold.nii.gz": "",
+            "sub-02/anat/sub-02_T1w.nii.gz": "",
+        },
+    )
+
+
+def test_participants_are_queryable(participants: Path):
+    layout = BidsLayout(participants).index_metadata()
+    assert _names(layout.get(group="control")) == {
+        "sub-01_T1w.nii.gz",
+        "sub-01_task-rest_bold.nii.gz",
+    }
+    assert _names(layout.get(group="patient")) == {"sub-02_T1w.nii.gz"}
+    assert set(layout.metadata["age"]) == {"25"}
+
+
+def test_participants_columns_are_path_metadata(participants: Path):
+    layout = BidsLayout(participants).index_metadata()
+    bold = layout.get(task="rest").one
+    assert bold.metadata == {"age": "25", "group": "control"}
+    assert bold.get_metadata() == {"age": "25", "group": "control"}
+    t1w = layout.get(subject="02").one
+    assert t1w.get_metadata() == {"group": "patient"}
+
+
+def test_malformed_participants_rows_are_reported(participants: Path):
+    layout = BidsLayout(participants).index_metadata()
+    errors = [err for _, err in layout.metadata_errors]
+    assert errors == [
+        "Line 4: expected 3 columns, found 2",
+        "Line 5: invalid participant_id '03'",
+    ]
+
+
+def test_malformed_sidecars_are_reported(tmp_path: Path):
+    root = create_files(
+        tmp_path,
+        {
+            "dataset_description.json": "{}",
+            "task-rest_bold.json": "{not json",
+            "sub-01/func/sub-01_task-rest_bold.json": '{"RepetitionTime": 2}',
+            "sub-01/func/sub-01_task-rest_bold.nii.gz": "",
+        },
+    )
+    layout = BidsLayout(root).index_metadata()
+    assert [Path(path) for path, _ in layout.metadata_errors] == [
+        root / "task-rest_bold.json"
+    ]
+    # Readable sidecars are still indexed
+    assert layout.get(extension=".nii.gz").one.metadata == {"RepetitionTime": "2"}
+
+
+def test_missing_participants_file(inheritance: Path):
+    assert BidsLayout(inheritance).index_metadata().metadata_errors == []
+
+
+@pytest.fixture
+def coordsystem(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        {
+            "dataset_description.json": "{}",
+            "sub-01/ieeg/sub-01_coordsystem.json": json.dumps(
+                {"iEEGCoordinateSystem": "ACPC"}
+            ),
+            "sub-01/ieeg/sub-01_task-rest_ieeg.json": json.dumps({"SamplingFrequency": 1000}),
+            "sub-01/ieeg/sub-01_task-rest_ieeg.edf": "",
+        },
+    )
+
+
+def test_json_data_is_not_metadata(coordsystem: Path):
+    layout = BidsLayout(coordsystem).index_metadata()
+    assert "iEEGCoordinateSystem" not in layout.metadata
+    assert layout.metadata["SamplingFrequency"] == ["1000"]
+    assert len(layout.get(SamplingFrequency="1000", extension=".edf")) == 1
+
+
+def test_json_data_suffixes_are_configurable(coordsystem: Path):
+    layout = BidsLayout(coordsystem, json_data_suffixes={"ieeg"}).index_metadata()
+    assert layout.metadata["iEEGCoordinateSystem"] == ["ACPC"]
+    assert "SamplingFrequency" not in layout.metadata
+
+
+@pytest.fixture
+def events(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        {
+            "dataset_description.json": "{}",
+            "sub-01/func/sub-01_task-rest_events.tsv": (
+                "onset\tduration\ttrial_type\n0.5\t1.0\tgo\n2.0\tn/a\tstop\n"
+            ),
+            "sub-01/func/sub-01_task-rest_bold.nii.gz": "",
+        },
+    )
+
+
+def test_read_tsv(events: Path):
+    layout = BidsLayout(events)
+    path = layout.get(suffix="events").one
+    assert path.read_tsv() == [
+        {"onset": "0.5", "duration": "1.0", "trial_type": "go"},
+        {"onset": "2.0", "duration": None, "trial_type": "stop"},
+    ]
+
+
+def test_read_tsv_with_ragged_row(events: Path):
+    path = events / "sub-01/func/sub-01_task-rest_events.tsv"
+    path.write_text("onset\tduration\n0.5\n")
+    with pytest.raises(ValueError, match="line 2"):
+        BidsLayout(events).get(suffix="events").one.read_tsv()
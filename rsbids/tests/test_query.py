@@ -0,0 +1,768 @@
+from __future__ import annotations
+
+import json
+import re
+from pathlib import Path
+
+import pytest
+
+from rsbids import BidsLayout, Not
+from rsbids.tests.helpers import create_files
+
+
+@pytest.fixture
+def dataset(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        {
+            "dataset_description.json": json.dumps({"Name": "test"}),
+            "sub-control01/anat/sub-control01_T1w.nii.gz": "",
+            "sub-control01/func/sub-control01_task-rest_bold.nii.gz": "",
+            "sub-control01/func/sub-control01_task-rest_bold.json": json.dumps(
+                {"RepetitionTime": 2.5}
+            ),
+            "sub-control02/anat/sub-control02_T1w.nii.gz": "",
+            "sub-patient01/anat/sub-patient01_T1w.nii.gz": "",
+            "sub-patient01/func/sub-patient01_task-nback_bold.nii.gz": "",
+            "sub-patient01/func/sub-patient01_task-nback_bold.json": json.dumps(
+                {"RepetitionTime": 0.8}
+            ),
+        },
+    )
+
+
+def test_regex_matches_subject(dataset: Path):
+    layout = BidsLayout(dataset).get(subject=re.compile("^control"))
+    assert set(layout.entities["subject"]) == {"control01", "control02"}
+
+
+def test_regex_matches_suffix(dataset: Path):
+    layout = BidsLayout(dataset).get(suffix=re.compile("T1"), extension=".nii.gz")
+    assert len(layout) == 3
+
+
+def test_regex_matches_metadata(dataset: Path):
+    layout = BidsLayout(dataset).index_metadata()
+    layout = layout.get(RepetitionTime=re.compile(r"^2\.\d+$")).get(extension=".nii.gz")
+    assert layout.one.entities["subject"] == "control01"
+
+
+def test_regex_flags_are_respected(dataset: Path):
+    layout = BidsLayout(dataset).get(subject=re.compile("^CONTROL", re.IGNORECASE))
+    assert set(layout.entities["subject"]) == {"control01", "control02"}
+
+
+def test_invalid_regex_raises(dataset: Path):
+    with pytest.raises(ValueError, match="Invalid regex"):
+        BidsLayout(dataset).get(subject=re.compile("(?<=control)01"))
+
+
+def test_get_accepts_dict(dataset: Path):
+    layout = BidsLayout(dataset)
+    assert layout.get({"subject": "control01"}) == layout.get(subject="control01")
+    assert layout.get({"subject": "control01"}, suffix="T1w") == layout.get(
+        subject="control01", suffix="T1w"
+    )
+
+
+def test_get_kwargs_override_dict(dataset: Path):
+    layout = BidsLayout(dataset)
+    expected = layout.get(subject="control02")
+    assert layout.get({"subject": "control01"}, subject="control02") == expected
+    assert layout.get({"subject": "control01"}, sub="control02") == expected
+
+
+def test_get_dict_with_key_that_is_not_an_identifier(tmp_path: Path):
+    dataset = create_files(
+        tmp_path,
+        {
+            "dataset_description.json": json.dumps({"Name": "test"}),
+            "sub-01/anat/sub-01_T1w.nii.gz": "",
+            "sub-01/anat/sub-01_T1w.json": json.dumps({"Coil-Name": "head32"}),
+            "sub-02/anat/sub-02_T1w.nii.gz": "",
+        },
+    )
+    layout = BidsLayout(dataset).index_metadata()
+    found = layout.get({"Coil-Name": "head32"}, extension=".nii.gz")
+    assert found.one.entities["subject"] == "01"
+
+
+@pytest.fixture
+def descriptions(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/anat/sub-01_desc-preproc_T1w.nii.gz",
+            "sub-01/anat/sub-01_desc-brain_T1w.nii.gz",
+            "sub-01/anat/sub-01_tk-1_T1w.nii.gz",
+        ],
+    )
+
+
+@pytest.mark.parametrize("key", ["desc", "description", "desc_", "description_"])
+def test_desc_query_aliases(descriptions: Path, key: str):
+    layout = BidsLayout(descriptions)
+    assert layout.get(**{key: "preproc"}) == layout.get(desc="preproc")
+    assert len(layout.get(**{key: "preproc"})) == 1
+
+
+@pytest.mark.parametrize("key", ["subject", "subject_", "sub", "sub_"])
+def test_trailing_underscore_with_long_names(descriptions: Path, key: str):
+    assert len(BidsLayout(descriptions).get(**{key: "01"})) == 3
+
+
+def test_trailing_underscore_kept_on_entity_named_with_one(descriptions: Path):
+    layout = BidsLayout(descriptions, extra_entities={"tk": "track_"})
+    assert len(layout.get(track_="1")) == 1
+
+
+@pytest.mark.parametrize(
+    "long,short",
+    [
+        ("ceagent", "ce"),
+        ("tracer", "trc"),
+        ("stain", "stain"),
+        ("staining", "stain"),
+        ("modality", "mod"),
+        ("inversion", "inv"),
+        ("mtransfer", "mt"),
+        ("processing", "proc"),
+    ],
+)
+def test_query_by_long_name(tmp_path: Path, long: str, short: str):
+    dataset = create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            f"sub-01/anat/sub-01_{short}-a_T1w.nii.gz",
+            f"sub-01/anat/sub-01_{short}-b_T1w.nii.gz",
+        ],
+    )
+    layout = BidsLayout(dataset)
+    assert len(layout.get(**{long: "a"})) == 1
+    assert layout.get(**{long: "a"}) == layout.get(**{short: "a"})
+
+
+def test_query_keys_ignore_case(tmp_path: Path):
+    dataset = create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/anat/sub-01_T1w.nii.gz",
+            "sub-02/anat/sub-02_T1w.nii.gz",
+        ],
+    )
+    layout = BidsLayout(dataset, ignore_key_case=True)
+    expected = layout.get(subject="01")
+    assert layout.get(Sub="01") == expected
+    assert layout.get(SUB="01") == expected
+    # Values are still matched exactly
+    assert not layout.get(Sub="01", suffix="t1w")
+
+
+def test_query_keys_are_case_sensitive_by_default(tmp_path: Path):
+    dataset = create_files(
+        tmp_path,
+        ["dataset_description.json", "sub-01/anat/sub-01_T1w.nii.gz"],
+    )
+    with pytest.raises(KeyError, match="Sub"):
+        BidsLayout(dataset).get(Sub="01")
+
+
+@pytest.mark.parametrize("ignore_key_case", [True, False])
+def test_keys_differing_in_case_are_indexed_apart(tmp_path: Path, ignore_key_case: bool):
+    dataset = create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/func/sub-01_Acq-x_task-rest_bold.nii.gz",
+            "sub-01/func/sub-01_acq-y_task-rest_bold.nii.gz",
+        ],
+    )
+    layout = BidsLayout(dataset, ignore_key_case=ignore_key_case)
+    assert layout.entities["Acq"] == ["x"]
+    assert layout.entities["acquisition"] == ["y"]
+    assert len(layout.get(Acq="x")) == 1
+    assert len(layout.get(acquisition="y")) == 1
+    assert not layout.get(acquisition="x")
+
+
+@pytest.fixture
+def mixed_case(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/func/sub-01_task-Rest_acq-MB_bold.nii.gz",
+            "sub-02/func/sub-02_task-rest_acq-mb_bold.nii.gz",
+            "sub-03/func/sub-03_task-nback_acq-mb_bold.nii.gz",
+        ],
+    )
+
+
+def test_string_match_is_case_sensitive_by_default(mixed_case: Path):
+    layout = BidsLayout(mixed_case).get(task="rest")
+    assert layout.entities["subject"] == ["02"]
+
+
+def test_ignore_case_matches_all_casings(mixed_case: Path):
+    layout = BidsLayout(mixed_case).get(task="REST", ignore_case=True)
+    assert set(layout.entities["subject"]) == {"01", "02"}
+    assert set(layout.entities["task"]) == {"Rest", "rest"}
+
+
+def test_ignore_case_applies_to_every_entity(mixed_case: Path):
+    layout = BidsLayout(mixed_case).get(task="rest", acquisition="MB", ignore_case=True)
+    assert set(layout.entities["acquisition"]) == {"MB", "mb"}
+    assert len(layout) == 2
+
+
+def test_ignore_case_without_match_is_empty(mixed_case: Path):
+    assert not BidsLayout(mixed_case).get(task="stroop", ignore_case=True)
+
+
+@pytest.fixture
+def runs(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/anat/sub-01_T1w.nii.gz",
+            "sub-01/func/sub-01_task-rest_run-01_bold.nii.gz",
+            "sub-01/func/sub-01_task-rest_run-02_bold.nii.gz",
+            "sub-01/func/sub-01_task-nback_run-01_bold.nii.gz",
+            "sub-01/func/sub-01_task-nback_run-02_bold.nii.gz",
+        ],
+    )
+
+
+def test_not_excludes_value(runs: Path):
+    layout = BidsLayout(runs).get(run=Not("01"))
+    assert "01" not in layout.entities["run"]
+    # Paths without the entity are kept
+    assert len(layout) == 4
+
+
+def test_not_composes_with_positive_terms(runs: Path):
+    layout = BidsLayout(runs).get(task=["rest", "nback", Not("nback")])
+    assert layout.entities["task"] == ["rest"]
+    assert len(layout) == 2
+
+
+def test_not_combined_across_entities(runs: Path):
+    layout = BidsLayout(runs).get(task="rest", run=Not("01"))
+    assert layout.one.entities["run"] == "02"
+
+
+def test_not_nonexistent_value_is_noop(runs: Path):
+    layout = BidsLayout(runs)
+    assert len(layout.get(run=Not("99"))) == len(layout)
+
+
+@pytest.fixture
+def echoes(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        ["dataset_description.json"]
+        + [
+            f"sub-01/func/sub-01_task-rest_run-00{run}_echo-{echo}_bold.nii.gz"
+            for run in range(1, 4)
+            for echo in range(1, 5)
+        ]
+        + ["sub-01/func/sub-01_task-rest_run-abc_bold.nii.gz"],
+    )
+
+
+def test_range_with_padded_values(echoes: Path):
+    layout = BidsLayout(echoes).get(run=range(2, 4))
+    assert set(layout.entities["run"]) == {"002", "003"}
+
+
+def test_open_ended_slice(echoes: Path):
+    layout = BidsLayout(echoes).get(echo=slice(3, None))
+    assert set(layout.entities["echo"]) == {"3", "4"}
+    layout = BidsLayout(echoes).get(echo=slice(None, 2))
+    assert set(layout.entities["echo"]) == {"1"}
+
+
+def test_range_skips_non_numeric_values(echoes: Path):
+    layout = BidsLayout(echoes).get(run=slice(0, None))
+    assert "abc" not in layout.entities["run"]
+    assert len(layout) == 12
+
+
+@pytest.mark.parametrize("bound", [slice(-1, None), slice(None, -2), range(-3, 2)])
+def test_negative_range_bound_raises(echoes: Path, bound: slice | range):
+    with pytest.raises(ValueError, match="Range bounds must be non-negative"):
+        BidsLayout(echoes).get(run=bound)
+
+
+def test_stepped_range_matches_each_number(echoes: Path):
+    layout = BidsLayout(echoes).get(run=range(1, 4, 2))
+    assert set(layout.entities["run"]) == {"001", "003"}
+
+
+def test_missing_value_is_empty_by_default(dataset: Path):
+    assert not BidsLayout(dataset).get(task="rset")
+
+
+def test_strict_missing_value_raises(dataset: Path):
+    with pytest.raises(
+        ValueError, match=r"Could not find values: \[\"rset\"\] for entity: 'task'"
+    ):
+        BidsLayout(dataset).get(task="rset", strict=True)
+
+
+def test_strict_reports_every_missing_value(dataset: Path):
+    with pytest.raises(ValueError, match="Multiple Query errors") as err:
+        BidsLayout(dataset).get(task="rset", subject="control03", strict=True)
+    assert "rset" in str(err.value)
+    assert "control03" in str(err.value)
+
+
+def test_strict_passes_when_values_exist(dataset: Path):
+    assert len(BidsLayout(dataset).get(task="rest", strict=True)) == 2
+
+
+@pytest.fixture
+def extensions(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/anat/sub-01_T1w.nii.gz",
+            "sub-01/anat/sub-01_T1w.json",
+            "sub-01/func/sub-01_task-rest_physio.tsv.gz",
+            "sub-01/func/sub-01_task-rest_events.tsv",
+        ],
+    )
+
+
+def test_full_compound_extension(extensions: Path):
+    layout = BidsLayout(extensions).get(extension=".nii.gz")
+    assert layout.one.entities["suffix"] == "T1w"
+
+
+def test_last_extension_segment_matches_compound(extensions: Path):
+    layout = BidsLayout(extensions).get(extension=".gz")
+    assert set(layout.entities["extension"]) == {".nii.gz", ".tsv.gz"}
+
+
+def test_plain_extension_does_not_match_compound(extensions: Path):
+    layout = BidsLayout(extensions).get(extension=".tsv")
+    assert layout.one.entities["suffix"] == "events"
+    layout = BidsLayout(extensions).get(extension=".json", suffix="T1w")
+    assert layout.one.entities["extension"] == ".json"
+
+
+def test_last_extension_segment_matches_compound_alongside_plain(extensions: Path):
+    create_files(extensions, ["sub-01/anat/sub-01_T2w.gz"])
+    layout = BidsLayout(extensions).get(extension=".gz")
+    assert set(layout.entities["extension"]) == {".gz", ".nii.gz", ".tsv.gz"}
+
+
+def test_last_extension_segment_ignoring_case(extensions: Path):
+    layout = BidsLayout(extensions).get(extension=".GZ", ignore_case=True)
+    assert set(layout.entities["extension"]) == {".nii.gz", ".tsv.gz"}
+    layout = BidsLayout(extensions).get(extension=".NII.GZ", ignore_case=True)
+    assert layout.one.entities["suffix"] == "T1w"
+
+
+def test_not_last_extension_segment(extensions: Path):
+    layout = BidsLayout(extensions).get(extension=Not(".gz"))
+    assert set(layout.entities["extension"]) == {".json", ".tsv"}
+
+
+@pytest.fixture
+def with_derivatives(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        {
+            "dataset_description.json": json.dumps({"Name": "test"}),
+            "sub-01/anat/sub-01_T1w.nii.gz": "",
+            "derivatives/fmriprep/dataset_description.json": json.dumps(
+                {"Name": "fmriprep", "GeneratedBy": [{"Name": "fmriprep"}]}
+            ),
+            "derivatives/fmriprep/sub-01/anat/sub-01_desc-preproc_T1w.nii.gz": "",
+        },
+    )
+
+
+@pytest.mark.parametrize("scope", ["raw", "self", "derivatives", "all", "fmriprep"])
+def test_valid_scope(with_derivatives: Path, scope: str):
+    assert BidsLayout(with_derivatives, derivatives=True).filter(scope=scope)
+
+
+def test_unknown_scope_raises(with_derivatives: Path):
+    with pytest.raises(
+        ValueError, match=r"Could not find values: \[\"derivative\"\] for entity: 'scope'"
+    ):
+        BidsLayout(with_derivatives, derivatives=True).filter(scope="derivative")
+
+
+def test_unknown_scope_raises_alongside_valid_scope(with_derivatives: Path):
+    layout = BidsLayout(with_derivatives, derivatives=True)
+    for scopes in [["raw", "fmriprp"], ["all", "fmriprp"]]:
+        with pytest.raises(ValueError, match="fmriprp"):
+            layout.filter(scope=scopes)
+
+
+FMRIPREP = "derivatives/fmriprep"
+
+
+@pytest.fixture
+def two_datasets(tmp_path: Path):
+    files = {}
+    for name in ["ds-a", "ds-b"]:
+        files.update(
+            {
+                f"{name}/dataset_description.json": json.dumps({"Name": name}),
+                f"{name}/sub-01/anat/sub-01_T1w.nii.gz": "",
+                f"{name}/{FMRIPREP}/dataset_description.json": json.dumps(
+                    {"Name": "fmriprep", "GeneratedBy": [{"Name": "fmriprep"}]}
+                ),
+                f"{name}/{FMRIPREP}/sub-01/anat/sub-01_desc-preproc_T1w.nii.gz": "",
+            }
+        )
+    create_files(tmp_path, files)
+    return tmp_path / "ds-a", tmp_path / "ds-b"
+
+
+def test_root_scopes_are_resolved_per_root(two_datasets: tuple[Path, Path]):
+    ds_a, ds_b = two_datasets
+    layout = BidsLayout([ds_a, ds_b], derivatives=[ds_a / FMRIPREP, ds_b / FMRIPREP])
+    scoped = layout.filter(root_scopes=[(ds_a, "raw"), (ds_b, "fmriprep")])
+    assert set(scoped.roots) == {str(ds_a), str(ds_b / FMRIPREP)}
+    expected = [ds_a, ds_b / FMRIPREP]
+    assert scoped == layout.filter(root=expected, root_match="exact")
+    assert len(scoped.get(desc="preproc")) == 1
+
+
+def test_root_scopes_combine_with_scope(two_datasets: tuple[Path, Path]):
+    ds_a, ds_b = two_datasets
+    layout = BidsLayout([ds_a, ds_b], derivatives=[ds_a / FMRIPREP, ds_b / FMRIPREP])
+    scoped = layout.filter(scope="raw", root_scopes=[(ds_b, ["fmriprep"])])
+    assert set(scoped.roots) == {str(ds_a), str(ds_b), str(ds_b / FMRIPREP)}
+
+
+def test_root_scopes_with_unknown_scope_raise(two_datasets: tuple[Path, Path]):
+    ds_a, _ = two_datasets
+    with pytest.raises(ValueError, match="fmriprp"):
+        BidsLayout(ds_a, derivatives=True).filter(root_scopes=[(ds_a, "fmriprp")])
+
+
+@pytest.fixture
+def bracketed_roots(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        {
+            "raw/dataset_description.json": "{}",
+            "raw/sub-01/anat/sub-01_T1w.nii.gz": "",
+            "deriv/sub-[01]/dataset_description.json": "{}",
+            "deriv/sub-[01]/sub-01/anat/sub-01_desc-x_T1w.nii.gz": "",
+            "deriv/other/dataset_description.json": "{}",
+            "deriv/other/sub-01/anat/sub-01_desc-y_T1w.nii.gz": "",
+        },
+    )
+
+
+def _descs(layout: BidsLayout):
+    return set(layout.entities.get("desc", []))
+
+
+def test_filter_roots_by_prefix(bracketed_roots: Path):
+    deriv = bracketed_roots / "deriv"
+    layout = BidsLayout(
+        bracketed_roots / "raw", derivatives=[deriv / "sub-[01]", deriv / "other"]
+    )
+    assert _descs(layout.filter(root=deriv, root_match="prefix")) == {"x", "y"}
+    assert _descs(layout.filter(root=deriv / "sub-[01]", root_match="prefix")) == {"x"}
+    # As a glob, the brackets are a character class
+    assert len(layout.filter(root=f"{deriv}/sub-[01]*")) == 0
+    assert len(layout.filter(root=f"{deriv}/sub-[01]*", root_match="prefix")) == 0
+
+
+def test_filter_roots_exactly(bracketed_roots: Path):
+    deriv = bracketed_roots / "deriv"
+    layout = BidsLayout(bracketed_roots / "raw", derivatives=[deriv / "other"])
+    assert len(layout.filter(root=f"{deriv}/*", root_match="exact")) == 0
+    assert _descs(layout.filter(root=f"{deriv}/*")) == {"y"}
+    assert _descs(layout.filter(root=deriv / "other", root_match="exact")) == {"y"}
+
+
+def test_unknown_root_match_raises(bracketed_roots: Path):
+    with pytest.raises(ValueError, match="root_match"):
+        BidsLayout(bracketed_roots / "raw").filter(root="raw", root_match="regex")
+
+
+def test_by_datatype_selects_datatype(dataset: Path):
+    layout = BidsLayout(dataset).by_datatype("anat")
+    assert len(layout) == 3
+    assert layout.entities["datatype"] == ["anat"]
+
+
+def test_by_datatype_combines_datatypes(dataset: Path):
+    layout = BidsLayout(dataset).by_datatype(["anat", "func"])
+    assert len(layout) == 7
+    assert set(layout.entities["datatype"]) == {"anat", "func"}
+
+
+def test_by_datatype_absent_datatype_is_empty(dataset: Path):
+    assert len(BidsLayout(dataset).by_datatype("dwi")) == 0
+
+
+def test_by_datatype_unknown_datatype_raises(dataset: Path):
+    with pytest.raises(ValueError, match="Unknown datatype: 'anatomy'"):
+        BidsLayout(dataset).by_datatype(["anat", "anatomy"])
+
+
+def test_false_selects_paths_without_entity(tmp_path: Path):
+    create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/anat/sub-01_T1w.nii.gz",
+            "sub-02/ses-1/anat/sub-02_ses-1_T1w.nii.gz",
+        ],
+    )
+    layout = BidsLayout(tmp_path).get(session=False, suffix="T1w")
+    assert layout.one.entities["subject"] == "01"
+
+
+def test_false_for_entity_absent_from_layout(dataset: Path):
+    layout = BidsLayout(dataset)
+    assert layout.get(session=False) == layout
+    assert len(layout.get(session=[False, "1"])) == len(layout)
+
+
+def test_false_for_metadata_key(dataset: Path):
+    layout = BidsLayout(dataset).index_metadata().get(extension=".nii.gz")
+    assert len(layout.get(RepetitionTime=False)) == 3
+
+
+def test_ellipsis_selects_paths_with_entity(dataset: Path):
+    layout = BidsLayout(dataset)
+    assert set(layout.get(task=...).entities["task"]) == {"rest", "nback"}
+    assert layout.get(task=...) == layout.get(task=True)
+
+
+@pytest.fixture
+def skullstripped(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        {
+            "dataset_description.json": json.dumps({"Name": "test"}),
+            "sub-01/anat/sub-01_T1w.nii.gz": "",
+            "sub-01/anat/sub-01_T1w.json": json.dumps({"SkullStripped": "true"}),
+            "sub-02/anat/sub-02_T1w.nii.gz": "",
+            "sub-02/anat/sub-02_T1w.json": json.dumps({"SkullStripped": "no"}),
+            "sub-03/anat/sub-03_T1w.nii.gz": "",
+        },
+    )
+
+
+def test_metadata_value_true_is_distinct_from_presence(skullstripped: Path):
+    layout = BidsLayout(skullstripped).index_metadata().get(extension=".nii.gz")
+    assert layout.get(SkullStripped="true").entities["subject"] == ["01"]
+    assert set(layout.get(SkullStripped=...).entities["subject"]) == {"01", "02"}
+    assert set(layout.get(SkullStripped=True).entities["subject"]) == {"01", "02"}
+    assert layout.get(SkullStripped=False).entities["subject"] == ["03"]
+
+
+def test_count_by(dataset: Path):
+    layout = BidsLayout(dataset).get(extension=".nii.gz")
+    assert layout.count_by("subject") == {"control01": 2, "control02": 1, "patient01": 2}
+    assert layout.count_by("sub") == layout.count_by("subject")
+
+
+def test_count_by_respects_filters(dataset: Path):
+    layout = BidsLayout(dataset).get(datatype="func")
+    assert layout.count_by("subject") == {"control01": 2, "patient01": 2}
+    assert layout.count_by("task") == {"rest": 2, "nback": 2}
+
+
+def test_count_by_unknown_entity(dataset: Path):
+    with pytest.raises(KeyError):
+        BidsLayout(dataset).count_by("session")
+
+
+def test_ambiguous_entities_of_single_path(runs: Path):
+    layout = BidsLayout(runs).get(datatype="func", task="rest", run="01")
+    assert layout.ambiguous_entities() == {}
+
+
+def test_ambiguous_entities_of_partial_selection(runs: Path):
+    ambiguous = BidsLayout(runs).get(datatype="func").ambiguous_entities()
+    assert {key: set(vals) for key, vals in ambiguous.items()} == {
+        "task": {"rest", "nback"},
+        "run": {"01", "02"},
+    }
+
+
+def test_by_suffix_selects_suffix(dataset: Path):
+    layout = BidsLayout(dataset).by_suffix("T1w")
+    assert len(layout) == 3
+    assert layout.entities["suffix"] == ["T1w"]
+
+
+def test_by_suffix_combines_suffixes(dataset: Path):
+    layout = BidsLayout(dataset).by_suffix(["T1w", "bold"])
+    assert set(layout.entities["suffix"]) == {"T1w", "bold"}
+    assert len(layout) == len(BidsLayout(dataset).get(suffix=["T1w", "bold"]))
+
+
+def test_by_suffix_rejects_extension(dataset: Path):
+    with pytest.raises(ValueError, match="should not include the extension"):
+        BidsLayout(dataset).by_suffix("bold.nii.gz")
+
+
+def test_by_suffix_rejects_non_alphanumeric(dataset: Path):
+    with pytest.raises(ValueError, match="must be alphanumeric"):
+        BidsLayout(dataset).by_suffix("T1w-run")
+
+
+def test_crosstab_counts_pairs(dataset: Path):
+    layout = BidsLayout(dataset)
+    table = layout.crosstab("subject", "datatype")
+    assert table == {
+        "control01": {"anat": 1, "func": 2},
+        "control02": {"anat": 1},
+        "patient01": {"anat": 1, "func": 2},
+    }
+    for sub, counts in table.items():
+        for datatype, count in counts.items():
+            assert count == len(layout.get(subject=sub, datatype=datatype))
+
+
+def test_crosstab_respects_filters(dataset: Path):
+    table = BidsLayout(dataset).get(extension=".nii.gz").crosstab("sub", "task")
+    assert table == {"control01": {"rest": 1}, "patient01": {"nback": 1}}
+
+
+def test_crosstab_unknown_entity(dataset: Path):
+    with pytest.raises(KeyError, match="session"):
+        BidsLayout(dataset).crosstab("subject", "session")
+
+
+@pytest.fixture
+def sessions(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        [
+            "dataset_description.json",
+            "sub-01/ses-1/func/sub-01_ses-1_task-rest_run-1_bold.nii.gz",
+            "sub-01/ses-1/func/sub-01_ses-1_task-rest_run-2_bold.nii.gz",
+            "sub-01/ses-1/func/sub-01_ses-1_task-nback_bold.nii.gz",
+            "sub-01/ses-2/func/sub-01_ses-2_task-rest_bold.nii.gz",
+            "sub-02/ses-1/func/sub-02_ses-1_task-rest_bold.nii.gz",
+            "sub-02/ses-1/anat/sub-02_ses-1_T1w.nii.gz",
+        ],
+    )
+
+
+def test_unique_combinations(sessions: Path):
+    combos = BidsLayout(sessions).unique_combinations(["subject", "ses", "task"])
+    assert combos == [
+        {"subject": "01", "session": "1", "task": "nback"},
+        {"subject": "01", "session": "1", "task": "rest"},
+        {"subject": "01", "session": "2", "task": "rest"},
+        {"subject": "02", "session": "1", "task": "rest"},
+    ]
+
+
+def test_unique_combinations_partial(sessions: Path):
+    layout = BidsLayout(sessions).get(subject="02")
+    assert layout.unique_combinations(["session", "task"], partial=True) == [
+        {"session": "1"},
+        {"session": "1", "task": "rest"},
+    ]
+
+
+def test_unique_combinations_unknown_entity(sessions: Path):
+    with pytest.raises(KeyError, match="acquisition"):
+        BidsLayout(sessions).unique_combinations(["subject", "acquisition"])
+
+
+@pytest.fixture
+def padded_runs(tmp_path: Path):
+    return create_files(
+        tmp_path,
+        {
+            "dataset_description.json": json.dumps({"Name": "test"}),
+            "sub-01/func/sub-01_task-rest_run-1_bold.nii.gz": "",
+            "sub-01/func/sub-01_task-rest_run-01_bold.nii.gz": "",
+            "sub-01/func/sub-01_task-rest_run-2_bold.nii.gz": "",
+        },
+    )
+
+
+@pytest.mark.parametrize("strict", [True, False])
+def test_numeric_strict_raises_with_candidates(padded_runs: Path, strict: bool):
+    with pytest.raises(ValueError, match="multiple possible values") as err:
+        BidsLayout(padded_runs).get(run=1, strict=strict)
+    assert '"1"' in str(err.value) and '"01"' in str(err.value)
+
+
+def test_unambiguous_number_in_padded_layout(padded_runs: Path):
+    assert BidsLayout(padded_runs).get(run=2).one.entities["run"] == "2"
+
+
+def test_numeric_coalesce_matches_all_paddings(padded_runs: Path):
+    layout = BidsLayout(padded_runs).get(run=1, numeric="coalesce", strict=True)
+    assert sorted(layout.entities["run"]) == ["01", "1"]
+    assert len(layout) == 2
+
+
+def test_numeric_coalesce_leaves_strings_exact(padded_runs: Path):
+    layout = BidsLayout(padded_runs).get(run="01", numeric="coalesce")
+    assert layout.entities["run"] == ["01"]
+
+
+def test_numeric_invalid_mode(padded_runs: Path):
+    with pytest.raises(ValueError, match="Unknown numeric mode"):
+        BidsLayout(padded_runs).get(run=1, numeric="loose")
+
+
+def test_without_entity_restores_runs(runs: Path):
+    layout = BidsLayout(runs)
+    relaxed = layout.get(task="rest", run=1).without_entity("run")
+    assert relaxed.entities["run"] == ["01", "02"]
+    assert relaxed.entities["task"] == ["rest"]
+    assert set(relaxed) == set(layout.get(task="rest"))
+
+
+def test_without_entity_keeps_other_filters(runs: Path):
+    layout = BidsLayout(runs)
+    relaxed = layout.get(task="nback", run=1).without_entity("run")
+    assert set(relaxed) == set(layout.get(task="nback"))
+    assert relaxed.entities["task"] == ["nback"]
+
+
+def test_without_entity_accepts_short_names(dataset: Path):
+    layout = BidsLayout(dataset)
+    relaxed = layout.get(subject="control01", suffix="T1w").without_entity("sub")
+    assert set(relaxed) == set(layout.get(suffix="T1w"))
+
+
+def test_without_entity_reindexes_metadata(tmp_path: Path):
+    create_files(
+        tmp_path,
+        {
+            "dataset_description.json": "{}",
+            "sub-01/func/sub-01_run-1_bold.nii.gz": "",
+            "sub-01/func/sub-01_run-1_bold.json": json.dumps({"RepetitionTime": 2}),
+            "sub-01/func/sub-01_run-2_bold.nii.gz": "",
+            "sub-01/func/sub-01_run-2_bold.json": json.dumps({"RepetitionTime": 3}),
+        },
+    )
+    layout = BidsLayout(tmp_path).index_metadata()
+    first = layout.get(run=1, extension=".nii.gz")
+    assert first.metadata["RepetitionTime"] == ["2"]
+    relaxed = first.without_entity("run")
+    assert set(relaxed.metadata["RepetitionTime"]) == {"2", "3"}
+
+
+def test_without_entity_unknown(runs: Path):
+    with pytest.raises(KeyError, match="session"):
+        BidsLayout(runs).without_entity("session")
@@ -1,6 +1,6 @@
 use std::{
     collections::HashMap,
-    fs::File,
+    fs::{self, File},
     io::Read,
     iter,
     path::{Path, PathBuf}, sync::Arc,
@@ -13,10 +13,10 @@ use serde_with::{apply, serde_as};
 use crate::errors::DatasetDescriptionErr;
 
 #[apply(
-    Option => #[serde_as(deserialize_as="serde_with::DefaultOnError")] #[serde(default)]
+    Option => #[serde_as(deserialize_as="serde_with::DefaultOnError")] #[serde(default, skip_serializing_if="Option::is_none")]
 )]
 #[serde_with::serde_as]
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
 pub struct GeneratedBy {
     #[serde(rename = "Name")]
     pub name: String,
@@ -31,10 +31,10 @@ pub struct GeneratedBy {
 }
 
 #[apply(
-    Option => #[serde_as(deserialize_as="serde_with::DefaultOnError")] #[serde(default)]
+    Option => #[serde_as(deserialize_as="serde_with::DefaultOnError")] #[serde(default, skip_serializing_if="Option::is_none")]
 )]
 #[serde_with::serde_as]
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
 pub struct SourceDataset {
     #[serde(rename = "URI")]
     pub uri: Option<String>,
@@ -45,15 +45,15 @@ pub struct SourceDataset {
 }
 
 #[apply(
-    Option => #[serde_as(deserialize_as="serde_with::DefaultOnError")] #[serde(default)]
+    Option => #[serde_as(deserialize_as="serde_with::DefaultOnError")] #[serde(default, skip_serializing_if="Option::is_none")]
 )]
 #[serde_with::serde_as]
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
 pub struct DatasetDescription {
     // #[serde_as(deserialize_as="serde_with::DefaultOnError")]
     #[serde(rename = "Name")]
     pub name: Option<String>,
-    #[serde(rename = "BidsVersion")]
+    #[serde(rename = "BIDSVersion", alias = "BidsVersion")]
     pub bids_version: Option<String>,
     #[serde(rename = "HEDVersion")]
     pub hed_version: Option<Vec<String>>,
@@ -101,6 +101,43 @@ impl DatasetDescription {
         serde_json::from_str(&contents).map_err(DatasetDescriptionErr::JsonErr)
     }
 
+    /// Write the description as json. If `path` is a directory, the description is written
+    /// to the `dataset_description.json` within it.
+    pub fn save(&self, path: &Path) -> Result<(), DatasetDescriptionErr> {
+        // An empty path joins to just the filename, in the working directory
+        let path = if path.as_os_str().is_empty() || path.is_dir() {
+            path.join("dataset_description.json")
+        } else {
+            path.to_path_buf()
+        };
+        let contents =
+            serde_json::to_string_pretty(self).map_err(DatasetDescriptionErr::JsonErr)?;
+        fs::write(path, contents).map_err(DatasetDescriptionErr::IoErr)
+    }
+
+    /// Description of a derivative of `source` generated by `generated_by`
+    ///
+    /// The derivative is named after its pipeline and keeps the bids version of the source,
+    /// which is recorded in `SourceDatasets` by `uri` and its DOI, if any.
+    pub fn derivative_scaffold(
+        source: &DatasetDescription,
+        generated_by: GeneratedBy,
+        uri: Option<String>,
+    ) -> DatasetDescription {
+        DatasetDescription {
+            name: Some(generated_by.name.clone()),
+            bids_version: source.bids_version.clone(),
+            dataset_type: Some(String::from("derivative")),
+            generated_by: Some(vec![generated_by]),
+            source_datasets: Some(vec![SourceDataset {
+                uri,
+                doi: source.dataset_doi.clone(),
+                version: None,
+            }]),
+            ..Default::default()
+        }
+    }
+
     pub fn pipeline_names(&self) -> impl Iterator<Item = &String> {
         vec![
             self.generated_by
@@ -256,4 +293,30 @@ impl From<GeneratedByBin> for GeneratedBy {
             container: value.container,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDataset;
+
+    #[test]
+    fn save_roundtrip() {
+        let dataset = TempDataset::new(
+            "desc",
+            &[(
+                "dataset_description.json",
+                r#"{"Name": "original", "GeneratedBy": [{"Name": "tool"}]}"#,
+            )],
+        );
+
+        let mut desc = DatasetDescription::open(&dataset.root).unwrap();
+        desc.name = Some("edited".to_string());
+        desc.save(&dataset.root).unwrap();
+        let reopened = DatasetDescription::open(&dataset.root).unwrap();
+
+        assert_eq!(reopened, desc);
+        assert_eq!(reopened.name.as_deref(), Some("edited"));
+        assert_eq!(reopened.generated_by.unwrap()[0].name, "tool");
+    }
+}
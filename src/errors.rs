@@ -2,30 +2,55 @@ use itertools::Itertools;
 use std::{io, path::PathBuf};
 
 use pyo3::PyErr;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::layout::bidspath::BidsPath;
 
-#[derive(Debug)]
+#[derive(Error, Debug)]
 pub enum DatasetDescriptionErr {
+    #[error("{0}")]
     IoErr(io::Error),
+    #[error("{0}")]
     JsonErr(serde_json::Error),
 }
 
+#[derive(Error, Debug)]
+pub enum EntityErr {
+    #[error(
+        "Cannot define entity '{short}' as '{long}': conflicts with existing entity '{existing}'"
+    )]
+    Conflict {
+        short: String,
+        long: String,
+        existing: String,
+    },
+}
+
 #[derive(Error, Debug)]
 pub enum IterdirErr {
     #[error("{0}")]
     Io(io::Error),
     #[error("{0}")]
     Interrupt(PyErr),
+    #[error("Indexing was cancelled")]
+    Cancelled,
+    #[error("Invalid pattern in '{}': {1}", .0.to_string_lossy())]
+    BidsIgnore(PathBuf, globset::Error),
+    #[error("Invalid include pattern: {0}")]
+    Include(globset::Error),
+    #[error(transparent)]
+    Entity(#[from] EntityErr),
 }
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, Serialize, Deserialize)]
 pub enum BidsPathErr {
     #[error("'{0}' is not valid unicode")]
     Encoding(PathBuf),
     #[error("'{}' is not a valid bids path", .0.as_str())]
     Validation(BidsPath),
+    #[error("'{}' has entities out of bids order", .0.as_str())]
+    EntityOrder(BidsPath),
 }
 
 impl From<PathBuf> for BidsPathErr {
@@ -38,11 +63,64 @@ impl BidsPathErr {
     pub fn get_bidspath(self) -> Result<BidsPath, Self> {
         match self {
             Self::Encoding(..) => Err(self),
-            Self::Validation(p) => Ok(p),
+            Self::Validation(p) | Self::EntityOrder(p) => Ok(p),
+        }
+    }
+
+    /// The offending path, lossily converted if it is not valid unicode
+    pub fn path_str(&self) -> String {
+        match self {
+            Self::Encoding(p) => p.to_string_lossy().to_string(),
+            Self::Validation(p) | Self::EntityOrder(p) => p.as_str().to_string(),
+        }
+    }
+}
+
+/// A common problem found in an indexed path. Unlike [`BidsPathErr`], these do not stop
+/// a path from being indexed.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ValidationIssue {
+    #[error("'{0}' is not in a recognized datatype directory")]
+    UnknownDatatype(String),
+    #[error("'{0}' has entities but no suffix")]
+    MissingSuffix(String),
+    #[error("'{0}' has entity '{1}' more than once")]
+    DuplicateEntity(String, String),
+    #[error("'{0}' has '{1}' of '{2}' in its directories but '{3}' in its filename")]
+    EntityMismatch(String, String, String, String),
+}
+
+impl ValidationIssue {
+    /// Short name of the kind of issue
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::UnknownDatatype(..) => "unknown_datatype",
+            Self::MissingSuffix(..) => "missing_suffix",
+            Self::DuplicateEntity(..) => "duplicate_entity",
+            Self::EntityMismatch(..) => "entity_mismatch",
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            Self::UnknownDatatype(p)
+            | Self::MissingSuffix(p)
+            | Self::DuplicateEntity(p, _)
+            | Self::EntityMismatch(p, ..) => p,
         }
     }
 }
 
+#[derive(Error, Debug)]
+pub enum BuildErr {
+    #[error("Cannot build a bids path without '{0}'")]
+    MissingEntity(&'static str),
+    #[error("Unknown entity '{0}'. Set allow_unknown to include custom entities")]
+    UnknownEntity(String),
+    #[error("Invalid value for '{0}': '{1}'")]
+    InvalidValue(String, String),
+}
+
 #[derive(Error, Debug)]
 pub enum GlobErr {
     #[error(transparent)]
@@ -57,12 +135,28 @@ pub enum QueryErr {
     MissingEntity(Vec<String>),
     #[error("Could not find values: {1:?} for entity: '{0}'")]
     MissingVal(String, Vec<String>),
-    #[error("Query '{0}={1}' matched multiple possible values: {2:?}. Please use a string query to be more specific")]
+    #[error("Query '{0}={1}' matched multiple possible values: {2:?}. Please use a string query to be more specific, or coalesce numbers to match them all")]
     AmbiguousQuery(String, u64, Vec<String>),
     #[error("Multiple Query errors:\n{}", .0.iter().map(|err| format!("{}", err)).join("\n"))]
     MutliErr(Vec<QueryErr>),
     #[error(transparent)]
     GlobErr(#[from] GlobErr),
+    #[error("Invalid regex: {0}")]
+    RegexErr(#[from] regex::Error),
+    #[error("Layouts are not derived from the same root index and cannot be combined")]
+    UnrelatedLayouts,
+    #[error("Unknown datatype: '{0}'")]
+    UnknownDatatype(String),
+    #[error("Invalid suffix: '{0}'. Suffixes must be alphanumeric")]
+    InvalidSuffix(String),
+    #[error("No dataset generated by pipeline '{0}'. Pipelines found: {1:?}")]
+    UnknownPipeline(String, Vec<String>),
+    #[error("Invalid suffix: '{0}'. Suffixes should not include the extension, which can be queried with `extension`")]
+    SuffixWithExtension(String),
+    #[error("Range bounds must be non-negative integers, got {0}")]
+    NegativeRangeBound(i64),
+    #[error("Layouts have different {0} for '{1}' and cannot be combined")]
+    ConflictingOptions(&'static str, String),
 }
 
 #[derive(Error, Debug)]
@@ -73,6 +167,8 @@ pub enum MetadataReadErr {
     Json(String, serde_json::Error),
     #[error("Error parsing {0}: Json must have an object as root")]
     Format(String),
+    #[error("Error parsing {0}: line {1} has {2} columns, but the header has {3}")]
+    Tsv(String, usize, usize, usize),
 }
 
 #[derive(Error, Debug)]
@@ -89,4 +185,42 @@ pub enum CacheErr {
     Io(#[from] std::io::Error),
     #[error(transparent)]
     Serde(#[from] bincode::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Index(#[from] IterdirErr),
+    #[error(
+        "Cache was written by {} (cache version {found}), which is incompatible with cache \
+        version {expected} used by rsbids {}. Delete the cache and rebuild it",
+        .writer.as_ref().map(|v| format!("rsbids {}", v)).unwrap_or("an older rsbids".to_string()),
+        env!("CARGO_PKG_VERSION")
+    )]
+    Version {
+        found: String,
+        writer: Option<String>,
+        expected: &'static str,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum ExportErr {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Paths that aren't valid unicode cannot be written to json
+    #[error("Cannot write layout as json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error(
+        "Cannot export '{}' and '{}' to the same destination '{}'",
+        .first.to_string_lossy(),
+        .second.to_string_lossy(),
+        .dest.to_string_lossy()
+    )]
+    Collision {
+        dest: PathBuf,
+        first: PathBuf,
+        second: PathBuf,
+    },
+    /// Exports never overwrite, so a failed export can be cleaned up and retried
+    #[error("Cannot export to '{}', which already exists", .0.to_string_lossy())]
+    Exists(PathBuf),
 }
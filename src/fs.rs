@@ -1,69 +1,242 @@
 use std::{
     collections::HashSet,
-    ffi::{OsStr, OsString},
-    io,
-    path::PathBuf,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::SystemTime,
 };
 
 use futures_lite::{future::block_on, StreamExt};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::{WalkBuilder, WalkState};
 use pyo3::Python;
 use walkdir::WalkDir;
 
-use crate::errors::IterdirErr;
+use crate::{errors::IterdirErr, utils::modified};
 
-fn unicode_decode_err(os_string: &OsStr) -> io::Error {
-    io::Error::new(
-        io::ErrorKind::InvalidData,
-        format!(
-            "Unable to convert path to unicode: {}",
-            os_string.to_string_lossy()
-        ),
-    )
+/// Number of paths passed to the callback between checks for python signals
+const SIGNAL_BATCH_SIZE: usize = 10000;
+
+/// Convert a `.bidsignore` line into a glob relative to the dataset root
+///
+/// Follows gitignore conventions: patterns without a slash match at any depth, while
+/// patterns containing one are anchored to the root. Negations are not supported.
+fn bidsignore_glob(line: &str) -> Option<String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+        return None;
+    }
+    let line = line.trim_end_matches('/');
+    Some(match line.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None if line.contains('/') => line.to_string(),
+        None => format!("**/{}", line),
+    })
 }
 
+/// Paths skipped while walking a directory, and patterns that files must match
+///
+/// Usually built with [`crate::layout::Layout::root_ignore`]. Building is not free when
+/// `.bidsignore` files are present, so an ignore can be shared between walks of the same
+/// roots.
+#[derive(Clone, Default)]
 pub struct IterIgnore {
     pub paths: HashSet<PathBuf>,
     pub names: HashSet<OsString>,
+    /// Patterns read from `.bidsignore` files, paired with the root they are relative to
+    pub globs: Vec<(PathBuf, GlobSet)>,
+    /// Patterns files must match, relative to the walked root, to be passed on. `None`
+    /// includes every file
+    include: Option<GlobSet>,
+    /// Patterns `include` was built from
+    include_patterns: Option<Vec<String>>,
 }
 
 impl IterIgnore {
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only pass on files matching one of `patterns`
+    pub fn set_include(&mut self, patterns: &[String]) -> Result<(), IterdirErr> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(
+                GlobBuilder::new(pattern)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(IterdirErr::Include)?,
+            );
+        }
+        self.include = Some(builder.build().map_err(IterdirErr::Include)?);
+        self.include_patterns = Some(patterns.to_vec());
+        Ok(())
+    }
+
+    /// Patterns given to [`IterIgnore::set_include`], if any
+    pub fn include_patterns(&self) -> Option<&[String]> {
+        self.include_patterns.as_deref()
+    }
+
+    /// Check if a file found while walking `root` matches the include patterns. A root
+    /// that is itself a file is matched by its name.
+    pub fn is_included(&self, path: &Path, root: &Path) -> bool {
+        match &self.include {
+            Some(include) => match path.strip_prefix(root) {
+                Ok(rel) if !rel.as_os_str().is_empty() => include.is_match(rel),
+                _ => path
+                    .file_name()
+                    .map(|name| include.is_match(name))
+                    .unwrap_or(false),
+            },
+            None => true,
+        }
+    }
+
+    /// Read the `.bidsignore` file in `root`, if present, and skip any matching paths
+    pub fn add_bidsignore(&mut self, root: &Path) -> Result<(), IterdirErr> {
+        if self.globs.iter().any(|(r, _)| r == root) {
+            return Ok(());
+        }
+        let path = root.join(".bidsignore");
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(IterdirErr::Io(err)),
+        };
+        let mut builder = GlobSetBuilder::new();
+        for pattern in contents.lines().filter_map(bidsignore_glob) {
+            builder.add(
+                GlobBuilder::new(&pattern)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|err| IterdirErr::BidsIgnore(path.clone(), err))?,
+            );
+        }
+        let globs = builder
+            .build()
+            .map_err(|err| IterdirErr::BidsIgnore(path.clone(), err))?;
+        self.globs.push((root.to_path_buf(), globs));
+        Ok(())
+    }
+
+    /// Check if a path found while walking `root` should be skipped. Hidden files are
+    /// always skipped, except for the root itself.
+    pub fn is_ignored(&self, path: &Path, root: &Path) -> bool {
+        if path == root {
+            false
+        } else if let Some(true) = path.file_name().map(|f| {
+            self.names.contains(f) || f.to_str().map(|s| s.starts_with('.')).unwrap_or(false)
+        }) {
+            true
+        } else if self.paths.contains(path) {
+            true
+        } else {
+            self.globs.iter().any(|(root, globs)| {
+                path.strip_prefix(root)
+                    .map(|rel| globs.is_match(rel))
+                    .unwrap_or(false)
+            })
+        }
+    }
+}
+
+/// Running count of the files found by walks, reported to a callback every `every`
+/// files
+///
+/// Files are counted as they are found, before they are sorted and passed on, so reports
+/// arrive while the walk is still in progress. One progress can be shared between walks,
+/// in which case the count spans all of them.
+pub struct WalkProgress<'a> {
+    found: usize,
+    every: usize,
+    report: Box<dyn FnMut(usize) -> Result<(), IterdirErr> + 'a>,
+}
+
+impl<'a> WalkProgress<'a> {
+    /// Call `report` with the number of files found after every `every` files. An `every`
+    /// of 0 never reports
+    pub fn new<R: FnMut(usize) -> Result<(), IterdirErr> + 'a>(every: usize, report: R) -> Self {
         Self {
-            paths: HashSet::new(),
-            names: HashSet::new(),
+            found: 0,
+            every,
+            report: Box::new(report),
+        }
+    }
+
+    /// Count one file found, reporting the total if it is due
+    fn add(&mut self) -> Result<(), IterdirErr> {
+        self.found += 1;
+        if self.every > 0 && self.found % self.every == 0 {
+            (self.report)(self.found)?;
         }
+        Ok(())
+    }
+}
+
+/// Count a file found by a walk, if progress is being tracked
+fn add_progress(progress: &mut Option<&mut WalkProgress>) -> Result<(), IterdirErr> {
+    match progress {
+        Some(progress) => progress.add(),
+        None => Ok(()),
+    }
+}
+
+/// Check if an entry found while walking is a directory, using the file type read by the
+/// walk. Symlinks are followed.
+fn is_walked_dir(path: &Path, file_type: Option<fs::FileType>) -> bool {
+    match file_type {
+        Some(file_type) if !file_type.is_symlink() => file_type.is_dir(),
+        _ => path.is_dir(),
     }
 }
 
-pub fn iterdir<F: FnMut(PathBuf)>(
+/// Modification time of a file found while walking, taken from the metadata read by the
+/// walk. Symlinks are followed, so the time matches that given by [`modified`].
+fn walked_mtime<E>(path: &Path, metadata: Result<fs::Metadata, E>) -> Option<SystemTime> {
+    match metadata {
+        Ok(metadata) if !metadata.file_type().is_symlink() => metadata.modified().ok(),
+        _ => modified(path).ok().flatten(),
+    }
+}
+
+/// Walk a directory, passing each file to `callback` along with its modification time
+///
+/// Files are passed in sorted order, matching [`iterdir_parallel`] and [`iterdir_async`].
+/// Each is counted in `progress`, if given, before it is passed on. Errors returned by the
+/// callback or the progress report stop the walk.
+pub fn iterdir<F: FnMut(PathBuf, Option<SystemTime>) -> Result<(), IterdirErr>>(
     path: PathBuf,
     ignore: &IterIgnore,
+    mut progress: Option<&mut WalkProgress>,
     mut callback: F,
 ) -> Result<(), IterdirErr> {
     Python::with_gil(|py| {
         if path.is_file() {
-            return Ok(callback(path));
+            if ignore.is_included(&path, &path) {
+                add_progress(&mut progress)?;
+                let mtime = modified(&path).ok().flatten();
+                callback(path, mtime)
+            } else {
+                Ok(())
+            }
         } else if path.exists() {
             WalkDir::new(&path)
+                .sort_by_file_name()
                 .into_iter()
-                .filter_entry(|entry| {
-                    if entry.path() == path {
-                        true
-                    } else if let Some(true) = entry.path().file_name().map(|f| {
-                        ignore.names.contains(f)
-                            || f.to_str().map(|s| s.starts_with('.')).unwrap_or(false)
-                    }) {
-                        false
-                    } else if ignore.paths.contains(entry.path()) {
-                        false
-                    } else {
-                        true
-                    }
-                })
+                .filter_entry(|entry| !ignore.is_ignored(entry.path(), &path))
                 .map(|entry| match entry {
                     Ok(entry) => {
-                        if !entry.path().is_dir() {
-                            callback(entry.into_path());
+                        if !is_walked_dir(entry.path(), Some(entry.file_type()))
+                            && ignore.is_included(entry.path(), &path)
+                        {
+                            add_progress(&mut progress)?;
+                            let mtime = walked_mtime(entry.path(), entry.metadata());
+                            callback(entry.into_path(), mtime)?;
                         };
                         Ok(())
                     }
@@ -90,50 +263,317 @@ pub fn iterdir<F: FnMut(PathBuf)>(
     })
 }
 
-pub fn iterdir_async<F: FnMut(String)>(path: PathBuf, mut callback: F) -> Result<(), io::Error> {
+/// Walk a directory using multiple threads
+///
+/// Paths are collected in parallel with the GIL released, then passed serially to the
+/// callback in sorted order, so the callback need not be thread-safe. The walking threads
+/// count files in `progress` as they find them, and reports are made from the calling
+/// thread with the GIL held. Errors returned by the callback or the progress report stop
+/// the walk.
+pub fn iterdir_parallel<F: FnMut(PathBuf, Option<SystemTime>) -> Result<(), IterdirErr>>(
+    path: PathBuf,
+    ignore: &IterIgnore,
+    nthreads: usize,
+    mut progress: Option<&mut WalkProgress>,
+    mut callback: F,
+) -> Result<(), IterdirErr> {
     if path.is_file() {
-        return Ok(callback(
-            path.into_os_string()
-                .into_string()
-                .map_err(|e| unicode_decode_err(&e))?,
-        ));
-    } else if path.exists() {
-        block_on(async {
-            let mut entries = async_walkdir::WalkDir::new(path).filter(|entry| async move {
-                if entry.path().is_dir() {
-                    async_walkdir::Filtering::Ignore
-                } else if let Some(true) = entry
-                    .path()
-                    .file_name()
-                    .map(|f| f.to_string_lossy() == "dataset_description.json")
+        return if ignore.is_included(&path, &path) {
+            add_progress(&mut progress)?;
+            let mtime = modified(&path).ok().flatten();
+            callback(path, mtime)
+        } else {
+            Ok(())
+        };
+    } else if !path.exists() {
+        return Err(IterdirErr::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("File not found: {}", path.to_string_lossy()),
+        )));
+    }
+    Python::with_gil(|py| {
+        let files = match progress {
+            Some(progress) => walk_parallel_with_progress(py, &path, ignore, nthreads, progress)?,
+            None => py.allow_threads(|| walk_parallel(&path, ignore, nthreads, None))?,
+        };
+        for (i, (file, mtime)) in files.into_iter().enumerate() {
+            if i % SIGNAL_BATCH_SIZE == 0 {
+                py.check_signals().map_err(IterdirErr::Interrupt)?;
+            }
+            callback(file, mtime)?;
+        }
+        py.check_signals().map_err(IterdirErr::Interrupt)
+    })
+}
+
+/// Count of files found, shared between the threads of a parallel walk
+struct ParallelCount<'a> {
+    found: AtomicUsize,
+    every: usize,
+    /// Running totals due to be reported
+    reports: mpsc::Sender<usize>,
+    /// Set when a report fails, stopping the walk
+    stop: &'a AtomicBool,
+}
+
+/// Walk a directory with [`walk_parallel`] on a separate thread, reporting the counts it
+/// sends to `progress` on this one
+fn walk_parallel_with_progress(
+    py: Python,
+    path: &Path,
+    ignore: &IterIgnore,
+    nthreads: usize,
+    progress: &mut WalkProgress,
+) -> Result<Vec<(PathBuf, Option<SystemTime>)>, IterdirErr> {
+    let stop = AtomicBool::new(false);
+    let (tx, rx) = mpsc::channel();
+    // Shared with the GIL released, which needs the receiver to be `Sync`
+    let rx = Mutex::new(rx);
+    std::thread::scope(|scope| {
+        let count = ParallelCount {
+            found: AtomicUsize::new(progress.found),
+            every: progress.every,
+            reports: tx,
+            stop: &stop,
+        };
+        let walker = scope.spawn(move || {
+            let files = walk_parallel(path, ignore, nthreads, Some(&count));
+            (files, count.found.into_inner())
+        });
+        // Threads may send their totals out of order, so only larger ones are reported.
+        // The channel closes once the walker is done and has dropped its sender
+        let mut reported = progress.found;
+        let mut result = Ok(());
+        while let Ok(found) = py.allow_threads(|| rx.lock().unwrap().recv()) {
+            if found > reported && result.is_ok() {
+                reported = found;
+                result = (progress.report)(found);
+                stop.store(result.is_err(), Ordering::Relaxed);
+            }
+        }
+        let (files, found) = walker
+            .join()
+            .unwrap_or_else(|err| std::panic::resume_unwind(err));
+        result?;
+        progress.found = found;
+        files
+    })
+}
+
+fn walk_parallel(
+    path: &Path,
+    ignore: &IterIgnore,
+    nthreads: usize,
+    count: Option<&ParallelCount>,
+) -> Result<Vec<(PathBuf, Option<SystemTime>)>, IterdirErr> {
+    let filter = Arc::new(ignore.clone());
+    let root = path.to_path_buf();
+    let (tx, rx) = mpsc::channel();
+    let (walk_filter, walk_root) = (filter.clone(), root.clone());
+    WalkBuilder::new(path)
+        .standard_filters(false)
+        .threads(nthreads)
+        .filter_entry(move |entry| !walk_filter.is_ignored(entry.path(), &walk_root))
+        .build_parallel()
+        .run(|| {
+            let tx = tx.clone();
+            let (filter, root) = (&filter, &root);
+            Box::new(move |entry| match entry {
+                Ok(entry) => {
+                    if !is_walked_dir(entry.path(), entry.file_type())
+                        && filter.is_included(entry.path(), root)
+                    {
+                        if let Some(count) = count {
+                            if count.stop.load(Ordering::Relaxed) {
+                                return WalkState::Quit;
+                            }
+                            let found = count.found.fetch_add(1, Ordering::Relaxed) + 1;
+                            if count.every > 0 && found % count.every == 0 {
+                                let _ = count.reports.send(found);
+                            }
+                        }
+                        let mtime = walked_mtime(entry.path(), entry.metadata());
+                        let _ = tx.send(Ok((entry.into_path(), mtime)));
+                    }
+                    WalkState::Continue
+                }
+                Err(err) => {
+                    let _ = tx.send(Err(err));
+                    WalkState::Quit
+                }
+            })
+        });
+    drop(tx);
+    let mut files = rx
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| IterdirErr::Io(io::Error::new(io::ErrorKind::InvalidData, e.to_string())))?;
+    files.sort_unstable();
+    Ok(files)
+}
+
+/// Walk a directory with `async_walkdir`, without holding the GIL
+///
+/// Like [`iterdir_parallel`], paths are collected first and passed to the callback in
+/// sorted order, while `progress` counts them as they are collected. `cancelled` is
+/// checked before each file is collected and before each is passed to the callback,
+/// stopping the walk with [`IterdirErr::Cancelled`] once set. Errors returned by the
+/// callback or the progress report also stop the walk.
+pub fn iterdir_async<F: FnMut(PathBuf, Option<SystemTime>) -> Result<(), IterdirErr>>(
+    path: PathBuf,
+    ignore: &IterIgnore,
+    cancelled: &AtomicBool,
+    mut progress: Option<&mut WalkProgress>,
+    mut callback: F,
+) -> Result<(), IterdirErr> {
+    if path.is_file() {
+        return if ignore.is_included(&path, &path) {
+            add_progress(&mut progress)?;
+            let mtime = modified(&path).ok().flatten();
+            callback(path, mtime)
+        } else {
+            Ok(())
+        };
+    } else if !path.exists() {
+        return Err(IterdirErr::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("File not found: {}", path.to_string_lossy()),
+        )));
+    }
+    let filter = Arc::new(ignore.clone());
+    let root = Arc::new(path.clone());
+    let mut files = block_on(async {
+        let mut files = Vec::new();
+        let mut entries = async_walkdir::WalkDir::new(path).filter(move |entry| {
+            let filter = filter.clone();
+            let root = root.clone();
+            async move {
+                let path = entry.path();
+                if filter.is_ignored(&path, &root) {
+                    async_walkdir::Filtering::IgnoreDir
+                } else if is_walked_dir(&path, entry.file_type().await.ok())
+                    || !filter.is_included(&path, &root)
                 {
                     async_walkdir::Filtering::Ignore
-                } else if let Some(true) = entry
-                    .path()
-                    .file_name()
-                    .map(|f| f.to_string_lossy().starts_with('.'))
-                {
-                    return async_walkdir::Filtering::IgnoreDir;
                 } else {
                     async_walkdir::Filtering::Continue
                 }
-            });
-            loop {
-                match entries.next().await {
-                    Some(Ok(entry)) => match entry.path().into_os_string().into_string() {
-                        Ok(path) => callback(path),
-                        Err(e) => return Err(unicode_decode_err(&e)),
-                    },
-                    Some(Err(e)) => return Err(e),
-                    None => break,
-                };
             }
+        });
+        while let Some(entry) = entries.next().await {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(IterdirErr::Cancelled);
+            }
+            let entry = entry.map_err(IterdirErr::Io)?;
+            add_progress(&mut progress)?;
+            let path = entry.path();
+            let mtime = walked_mtime(&path, entry.metadata().await);
+            files.push((path, mtime));
+        }
+        Ok(files)
+    })?;
+    files.sort_unstable();
+    for (file, mtime) in files {
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(IterdirErr::Cancelled);
+        }
+        callback(file, mtime)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::test_utils::TempDataset;
+
+    /// Dataset of `n` files, to be walked with progress reported after each file
+    fn numbered_dataset(name: &str, n: usize) -> TempDataset {
+        let files = (0..n)
+            .map(|i| format!("sub-{:02}/anat/sub-{:02}_T1w.nii.gz", i, i))
+            .collect::<Vec<_>>();
+        TempDataset::new(
+            name,
+            &files.iter().map(|f| (f.as_str(), "")).collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn async_walk_reports_progress_before_passing_files() {
+        let dataset = numbered_dataset("asyncprogress", 20);
+        let events = RefCell::new(Vec::new());
+        let mut progress = WalkProgress::new(1, |found| {
+            events.borrow_mut().push(Some(found));
             Ok(())
-        })
-    } else {
-        Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            format!("File not found: {}", path.to_string_lossy()),
-        ))
+        });
+        let cancelled = AtomicBool::new(false);
+        iterdir_async(
+            dataset.root.clone(),
+            &IterIgnore::new(),
+            &cancelled,
+            Some(&mut progress),
+            |_, _| {
+                events.borrow_mut().push(None);
+                Ok(())
+            },
+        )
+        .unwrap();
+        drop(progress);
+
+        let events = events.into_inner();
+        let reports = (1..=20).map(Some).collect::<Vec<_>>();
+        assert_eq!(events[..20], reports);
+        assert!(events[20..].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn parallel_walk_reports_progress_before_passing_files() {
+        let dataset = numbered_dataset("parallelprogress", 50);
+        let events = RefCell::new(Vec::new());
+        let mut progress = WalkProgress::new(1, |found| {
+            events.borrow_mut().push(Some(found));
+            Ok(())
+        });
+        iterdir_parallel(
+            dataset.root.clone(),
+            &IterIgnore::new(),
+            4,
+            Some(&mut progress),
+            |_, _| {
+                events.borrow_mut().push(None);
+                Ok(())
+            },
+        )
+        .unwrap();
+        drop(progress);
+
+        // Reports from different threads may be skipped, but never arrive out of order
+        let events = events.into_inner();
+        let first_file = events.iter().position(Option::is_none).unwrap();
+        let reports = events[..first_file].iter().flatten().collect::<Vec<_>>();
+        assert!(reports.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(reports.last(), Some(&&50));
+        assert_eq!(events[first_file..], vec![None; 50]);
+    }
+
+    #[test]
+    fn failed_progress_report_stops_parallel_walk() {
+        let dataset = numbered_dataset("parallelstop", 50);
+        let mut passed = 0;
+        let mut progress = WalkProgress::new(10, |_| Err(IterdirErr::Cancelled));
+        let result = iterdir_parallel(
+            dataset.root.clone(),
+            &IterIgnore::new(),
+            4,
+            Some(&mut progress),
+            |_, _| {
+                passed += 1;
+                Ok(())
+            },
+        );
+        assert!(matches!(result, Err(IterdirErr::Cancelled)));
+        assert_eq!(passed, 0);
     }
 }
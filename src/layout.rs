@@ -1,41 +1,52 @@
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet, HashMap, HashSet},
     ffi::OsString,
+    fmt,
+    hash::{Hash, Hasher},
     io,
     ops::Range,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{atomic::AtomicBool, Arc},
+    time::SystemTime,
 };
 
-use itertools::Itertools;
+use itertools::{chain, Either, Itertools};
 use once_cell::sync::OnceCell;
+use path_clean::clean;
+use regex::Regex;
 
-use builders::{LayoutBuilder, RootLabel};
-pub use iterator::BidsPathViewIterator;
+use builders::{LayoutBuilder, RootLabel, RootSeed};
+pub use iterator::{BidsPathRefIterator, BidsPathViewIterator};
 use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, FromInto};
 
 use crate::{
-    dataset_description::DatasetDescription,
-    errors::{BidsPathErr, IterdirErr, QueryErr},
-    fs::{iterdir, IterIgnore},
+    dataset_description::{DatasetDescription, DatasetDescriptionBin},
+    errors::{BidsPathErr, IterdirErr, QueryErr, ValidationIssue},
+    fs::{iterdir, iterdir_async, iterdir_parallel, IterIgnore, WalkProgress},
     py::pyparams::derivatives::DerivativeSpec,
-    standards::{check_entity, deref_key_alias, get_key_alias, BIDS_DATATYPES},
+    standards::{
+        check_entity, check_extra_entities, deref_key_alias, deref_query_alias, fold_key_case,
+        get_key_alias, is_known_key, BIDS_DATATYPES,
+    },
+    utils::{absolute_clean, modified, natural_sort},
 };
 
 use self::{
-    bidspath::BidsPath,
+    bidspath::{last_extension, BidsPath},
     builders::{
         bidspath_builder::BidsPathBuilder, layout_builder::FileTree,
-        metadata_builder::MetadataIndexBuilder,
+        metadata_builder::{MetadataIndexBuilder, MetadataStore},
     },
-    entity_table::EntityTable,
-    roots::{DatasetRoot, DatasetRoots},
+    entity_table::{EntityTable, IndexSet, ValueMap},
+    roots::{DatasetRoot, DatasetRoots, RootCategory, RootMatch},
 };
 
 pub mod bidspath;
 pub mod builders;
 pub mod cache;
 pub mod entity_table;
+pub mod export;
 pub mod iterator;
 pub mod roots;
 pub mod utfpath;
@@ -44,36 +55,123 @@ pub fn check_datatype(datatype: &str) -> bool {
     BIDS_DATATYPES.contains(datatype)
 }
 
-pub fn normalize_query(
-    query: HashMap<String, Vec<QueryTerms>>,
-) -> HashMap<String, Vec<QueryTerms>> {
-    query
-        .into_iter()
-        .filter_map(|(key, vals)| {
-            if vals.len() > 0 {
-                let derefed = deref_key_alias(&key)
-                    .map(ToString::to_string)
-                    .unwrap_or(key);
-                let derefed = derefed
-                    .strip_suffix("_")
-                    .map(ToString::to_string)
-                    .unwrap_or(derefed);
-                Some((derefed, vals))
+/// Expand extension queries so that either the full compound extension (`.nii.gz`) or
+/// its final segment (`.gz`) matches
+///
+/// Compound values are added whether or not the final segment is itself a value, so the
+/// paths selected don't depend on what else is indexed.
+fn expand_extension_terms(terms: Vec<QueryTerms>, values: &ValueMap<String>) -> Vec<QueryTerms> {
+    let matching = |query: &str, ignore_case: bool| {
+        let normalize = |s: &str| {
+            if ignore_case {
+                s.to_lowercase()
             } else {
-                None
+                s.to_string()
+            }
+        };
+        let query = normalize(query);
+        values
+            .keys()
+            .filter(|v| normalize(v) != query && normalize(last_extension(v)) == query)
+            .cloned()
+            .collect_vec()
+    };
+    // The query itself is kept if it is a value, or if nothing else matches so that it is
+    // reported as missing
+    let with_query = |query: QueryTerms, found: bool, matches: Vec<String>| {
+        let query = (found || matches.is_empty()).then_some(query);
+        query
+            .into_iter()
+            .chain(matches.into_iter().map(QueryTerms::String))
+            .collect_vec()
+    };
+    terms
+        .into_iter()
+        .flat_map(|term| match term {
+            QueryTerms::String(query) => {
+                let matches = matching(&query, false);
+                let found = values.contains_key(&query);
+                with_query(QueryTerms::String(query), found, matches)
+            }
+            QueryTerms::StringCI(query) => {
+                let matches = matching(&query, true);
+                let lower = query.to_lowercase();
+                let found = values.keys().any(|v| v.to_lowercase() == lower);
+                with_query(QueryTerms::StringCI(query), found, matches)
             }
+            QueryTerms::Not(query) => {
+                let mut matches = matching(&query, false);
+                matches.push(query);
+                matches.into_iter().map(QueryTerms::Not).collect()
+            }
+            term => vec![term],
         })
         .collect()
 }
 
-#[derive(Eq, PartialEq, Hash, Debug)]
+#[derive(Debug)]
 pub enum QueryTerms {
-    Bool(bool),
+    /// Select every path with any value for the key. Distinct from a value of
+    /// `"true"`, which is how JSON booleans in the metadata are indexed
+    Present,
+    /// Select every path without a value for the key, including when no path in the
+    /// layout has it
+    Absent,
     String(String),
     Number(u64),
+    /// Select every value parsing to the number, so `run-1` and `run-01` are both
+    /// returned rather than raising [`QueryErr::AmbiguousQuery`]
+    NumberCoalesce(u64),
+    Regex(Regex),
+    /// Case-insensitive string match. Every value equal to the query after
+    /// lowercasing is selected, so `task-Rest` and `task-rest` are both returned
+    /// under their original spelling.
+    StringCI(String),
+    /// Exclude a single value. On its own, selects every path in the view except
+    /// those with the value; alongside positive terms, removes it from their
+    /// selection
+    Not(String),
+    /// Select values parsing to an integer within the range. Values that are not
+    /// integers are skipped.
+    NumRange {
+        start: Option<u64>,
+        end: Option<u64>,
+        inclusive: bool,
+    },
     Any,
 }
 
+impl QueryTerms {
+    /// Compile a regex query term, returning a QueryErr if the pattern is invalid
+    pub fn regex(pattern: &str) -> Result<Self, QueryErr> {
+        Ok(QueryTerms::Regex(Regex::new(pattern)?))
+    }
+
+    fn in_range(value: &str, start: Option<u64>, end: Option<u64>, inclusive: bool) -> bool {
+        let Ok(num) = value.parse::<u64>() else {
+            return false;
+        };
+        start.is_none_or(|start| num >= start)
+            && end.is_none_or(|end| if inclusive { num <= end } else { num < end })
+    }
+
+    /// Convert exact string terms into their case-insensitive equivalent
+    pub fn ignore_case(self) -> Self {
+        match self {
+            QueryTerms::String(string) => QueryTerms::StringCI(string),
+            other => other,
+        }
+    }
+
+    /// Convert number terms into ones matching every value parsing to the number
+    pub fn coalesce_numbers(self) -> Self {
+        match self {
+            QueryTerms::Number(num) => QueryTerms::NumberCoalesce(num),
+            other => other,
+        }
+    }
+}
+
 impl From<&'static str> for QueryTerms {
     fn from(value: &'static str) -> Self {
         QueryTerms::String(value.to_string())
@@ -82,7 +180,11 @@ impl From<&'static str> for QueryTerms {
 
 impl From<bool> for QueryTerms {
     fn from(value: bool) -> Self {
-        QueryTerms::Bool(value)
+        if value {
+            QueryTerms::Present
+        } else {
+            QueryTerms::Absent
+        }
     }
 }
 
@@ -113,18 +215,168 @@ macro_rules! construct_query {
     }};
 }
 
+/// Settings controlling how a layout is indexed by [`Layout::create`]
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayoutOptions {
+    /// Parse paths strictly according to the bids spec
+    pub validate: bool,
+    /// Number of threads used to walk directories. 1 walks serially
+    pub nthreads: usize,
+    /// Index datasets referenced by the `DatasetLinks` of raw roots
+    pub follow_links: bool,
+    /// Entities recognized in addition to those defined by the spec, mapping short names to
+    /// long names
+    pub extra_entities: BTreeMap<String, String>,
+    /// Recognize short entity names differing only in case from a known entity (`Sub`).
+    /// Keys are still indexed as spelled
+    pub ignore_key_case: bool,
+    /// Names of directories skipped while walking. `None` skips [`DEFAULT_IGNORE_DIRS`]
+    pub ignore_dirs: Option<BTreeSet<String>>,
+    /// Globs, relative to each walked root, that files must match to be indexed. `None`
+    /// indexes every file
+    pub include: Option<Vec<String>>,
+    /// Suffixes of json files holding data rather than metadata, which are left out of the
+    /// metadata index. `None` uses [`DEFAULT_JSON_DATA_SUFFIXES`]
+    pub json_data_suffixes: Option<BTreeSet<String>>,
+    /// Descriptions used for roots in place of their `dataset_description.json`, keyed by
+    /// root. Lets datasets without a description on disk be scoped by pipeline
+    #[serde_as(as = "BTreeMap<_, FromInto<DatasetDescriptionBin>>")]
+    pub descriptions: BTreeMap<PathBuf, Arc<DatasetDescription>>,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        Self {
+            validate: false,
+            nthreads: 1,
+            follow_links: false,
+            extra_entities: BTreeMap::new(),
+            ignore_key_case: false,
+            ignore_dirs: None,
+            include: None,
+            json_data_suffixes: None,
+            descriptions: BTreeMap::new(),
+        }
+    }
+}
+
+impl LayoutOptions {
+    /// Options for a layout combining layouts indexed with `self` and `other`
+    ///
+    /// Extra entities and descriptions of both are kept, raising an error if they give
+    /// different values for the same key. Other options are taken from `self`.
+    pub fn merge(&self, other: &LayoutOptions) -> Result<LayoutOptions, QueryErr> {
+        let mut merged = self.clone();
+        for (short, long) in &other.extra_entities {
+            match merged.extra_entities.get(short) {
+                Some(existing) if existing != long => {
+                    return Err(QueryErr::ConflictingOptions(
+                        "extra entities",
+                        short.clone(),
+                    ))
+                }
+                _ => merged.extra_entities.insert(short.clone(), long.clone()),
+            };
+        }
+        for (root, description) in &other.descriptions {
+            match merged.descriptions.get(root) {
+                Some(existing) if existing != description => {
+                    return Err(QueryErr::ConflictingOptions(
+                        "descriptions",
+                        root.to_string_lossy().to_string(),
+                    ))
+                }
+                _ => merged
+                    .descriptions
+                    .insert(root.clone(), description.clone()),
+            };
+        }
+        Ok(merged)
+    }
+
+    /// Check if json files with `suffix` hold data rather than metadata
+    pub fn is_json_data(&self, suffix: &str) -> bool {
+        match &self.json_data_suffixes {
+            Some(suffixes) => suffixes.contains(suffix),
+            None => DEFAULT_JSON_DATA_SUFFIXES.contains(&suffix),
+        }
+    }
+
+    /// Description given for `root`, comparing roots by their absolute paths
+    pub fn description_for(&self, root: &Path) -> Option<&Arc<DatasetDescription>> {
+        if self.descriptions.is_empty() {
+            return None;
+        }
+        let root = absolute_clean(root);
+        self.descriptions
+            .iter()
+            .find(|(path, _)| absolute_clean(path) == root)
+            .map(|(_, description)| description)
+    }
+}
+
+/// Directories skipped while walking unless [`LayoutOptions::ignore_dirs`] is set
+pub const DEFAULT_IGNORE_DIRS: [&str; 3] = ["derivatives", "sourcedata", "code"];
+
+/// Suffixes of json files left out of the metadata index unless
+/// [`LayoutOptions::json_data_suffixes`] is set
+pub const DEFAULT_JSON_DATA_SUFFIXES: [&str; 2] = ["coordsystem", "electrodes"];
+
+/// Local targets of the `DatasetLinks` in a description, resolved against `root`
+fn dataset_links(root: &Path, description: Option<&DatasetDescription>) -> Vec<(String, PathBuf)> {
+    description
+        .and_then(|desc| desc.dataset_links.as_ref())
+        .map(|links| {
+            links
+                .iter()
+                .sorted()
+                .filter_map(|(key, uri)| {
+                    let target = match uri.strip_prefix("file://") {
+                        Some(target) => target,
+                        None if uri.contains("://") => return None,
+                        None => uri,
+                    };
+                    Some((key.clone(), root.join(target)))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn walk<F: FnMut(PathBuf, Option<SystemTime>) -> Result<(), IterdirErr>>(
+    path: PathBuf,
+    ignore: &IterIgnore,
+    options: &LayoutOptions,
+    progress: Option<&mut WalkProgress>,
+    callback: F,
+) -> Result<(), IterdirErr> {
+    if options.nthreads > 1 {
+        iterdir_parallel(path, ignore, options.nthreads, progress, callback)
+    } else {
+        iterdir(path, ignore, progress, callback)
+    }
+}
+
 fn missing_paths_err(msg: String) -> IterdirErr {
     IterdirErr::Io(io::Error::new(io::ErrorKind::NotFound, msg))
 }
 
+enum SetOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Layout {
     paths: Arc<Vec<BidsPath>>,
     entities: EntityTable<String>,
     pub roots: DatasetRoots,
-    heads: HashMap<String, HashSet<usize>>,
+    heads: HashMap<String, IndexSet>,
     filetree: Arc<FileTree>,
-    depths: Arc<BTreeMap<usize, HashSet<usize>>>,
+    depths: Arc<BTreeMap<usize, IndexSet>>,
     #[serde(
         serialize_with = "crate::serialize::serialize",
         deserialize_with = "crate::serialize::deserialize"
@@ -135,15 +387,154 @@ pub struct Layout {
         deserialize_with = "crate::serialize::deserialize"
     )]
     view: OnceCell<Vec<usize>>,
+    /// Absolute form of each path in the view mapped to its position, built on the
+    /// first lookup
+    #[serde(skip)]
+    positions: OnceCell<HashMap<PathBuf, usize>>,
+    #[serde(
+        serialize_with = "crate::serialize::serialize",
+        deserialize_with = "crate::serialize::deserialize"
+    )]
+    metadata_store: OnceCell<Arc<MetadataStore>>,
+    invalid_paths: Arc<Vec<BidsPathErr>>,
+    description_errors: Arc<Vec<(PathBuf, String)>>,
+    mtimes: Arc<Vec<Option<SystemTime>>>,
+    /// When the files of the layout were listed, compared against the roots by
+    /// [`Layout::is_stale`]
+    indexed: SystemTime,
+    seeds: Arc<Vec<RootSeed>>,
+    options: LayoutOptions,
 }
 
 impl Layout {
+    /// Index a layout from the given dataset and derivative paths
+    ///
+    /// Directories are skipped according to `ignore` if given, otherwise according to
+    /// [`Layout::root_ignore`] built from the paths and `options`. Sharing one ignore
+    /// between layouts indexed from the same tree avoids rereading `.bidsignore` files.
+    /// The directory names and include patterns of a given `ignore` replace those of
+    /// `options`, so the layout is walked the same way when updated.
     pub fn create(
         paths: Vec<PathBuf>,
         derivatives: Option<Vec<DerivativeSpec>>,
-        validate: bool,
+        options: LayoutOptions,
+        ignore: Option<&IterIgnore>,
     ) -> Result<Layout, IterdirErr> {
-        let mut dataset = LayoutBuilder::default();
+        let walk_options = options.clone();
+        Self::create_with(
+            paths,
+            derivatives,
+            options,
+            ignore,
+            |path, ignore, callback| {
+                walk(path, ignore, &walk_options, None, |path, mtime| {
+                    callback(path, mtime);
+                    Ok(())
+                })
+            },
+        )
+    }
+
+    /// Index a layout like [`Layout::create`], calling `progress` with the number of
+    /// files found after every `progress_every` files
+    ///
+    /// Errors returned by `progress` stop the walk.
+    pub fn create_with_progress<P: FnMut(usize) -> Result<(), IterdirErr>>(
+        paths: Vec<PathBuf>,
+        derivatives: Option<Vec<DerivativeSpec>>,
+        options: LayoutOptions,
+        progress_every: usize,
+        progress: P,
+    ) -> Result<Layout, IterdirErr> {
+        let walk_options = options.clone();
+        let mut progress = WalkProgress::new(progress_every, progress);
+        Self::create_with(
+            paths,
+            derivatives,
+            options,
+            None,
+            |path, ignore, callback| {
+                walk(
+                    path,
+                    ignore,
+                    &walk_options,
+                    Some(&mut progress),
+                    |path, mtime| {
+                        callback(path, mtime);
+                        Ok(())
+                    },
+                )
+            },
+        )
+    }
+
+    /// Index a layout like [`Layout::create`], walking with `async_walkdir` so that no
+    /// python resources are needed during the walk
+    ///
+    /// The walk stops with [`IterdirErr::Cancelled`] as soon as `cancelled` is set, and
+    /// `progress` is called with the number of files found after every `progress_every`
+    /// files. `options.nthreads` is ignored.
+    pub fn create_cancellable<P: FnMut(usize) -> Result<(), IterdirErr>>(
+        paths: Vec<PathBuf>,
+        derivatives: Option<Vec<DerivativeSpec>>,
+        options: LayoutOptions,
+        cancelled: &AtomicBool,
+        progress_every: usize,
+        progress: P,
+    ) -> Result<Layout, IterdirErr> {
+        let mut progress = WalkProgress::new(progress_every, progress);
+        Self::create_with(
+            paths,
+            derivatives,
+            options,
+            None,
+            |path, ignore, callback| {
+                iterdir_async(
+                    path,
+                    ignore,
+                    cancelled,
+                    Some(&mut progress),
+                    |path, mtime| {
+                        callback(path, mtime);
+                        Ok(())
+                    },
+                )
+            },
+        )
+    }
+
+    fn create_with<W>(
+        paths: Vec<PathBuf>,
+        derivatives: Option<Vec<DerivativeSpec>>,
+        options: LayoutOptions,
+        ignore: Option<&IterIgnore>,
+        mut walk: W,
+    ) -> Result<Layout, IterdirErr>
+    where
+        W: FnMut(
+            PathBuf,
+            &IterIgnore,
+            &mut dyn FnMut(PathBuf, Option<SystemTime>),
+        ) -> Result<(), IterdirErr>,
+    {
+        check_extra_entities(&options.extra_entities)?;
+        let options = match ignore {
+            Some(ignore) => Self::options_for_ignore(options, ignore),
+            None => options,
+        };
+        // Trailing or repeated separators and `.` components would otherwise shift the
+        // offsets of walked paths relative to their root
+        let paths = paths.into_iter().map(clean).collect_vec();
+        let derivatives = derivatives.map(|derivatives| {
+            derivatives
+                .into_iter()
+                .map(|mut derivative| {
+                    derivative.paths = derivative.paths.into_iter().map(clean).collect();
+                    derivative
+                })
+                .collect_vec()
+        });
+        let mut dataset = LayoutBuilder::with_options(options.clone());
         let mut invalid_paths = Vec::new();
         if let Some(deriv) = derivatives.as_ref() {
             for d in deriv.iter().flat_map(|d| &d.paths) {
@@ -170,25 +561,25 @@ impl Layout {
             )));
         }
 
-        let mut ignore = IterIgnore::new();
-        ignore.paths.extend(
-            paths
-                .iter()
-                .chain(derivatives.iter().flatten().flat_map(|d| &d.paths))
-                .map(|s| PathBuf::from(s)),
-        );
-        ignore.names = HashSet::from([
-            OsString::from("derivatives"),
-            OsString::from("sourcedata"),
-            OsString::from("code"),
-        ]);
+        let built;
+        let ignore = match ignore {
+            Some(ignore) => ignore,
+            None => {
+                built = Self::root_ignore(
+                    paths
+                        .iter()
+                        .chain(derivatives.iter().flatten().flat_map(|d| &d.paths)),
+                    &options,
+                )?;
+                &built
+            }
+        };
         for path in paths {
             let rootpos = dataset
                 .register_root(Some(&path), RootLabel::Raw)
                 .unwrap_or(0);
-            iterdir(path, &ignore, |path| {
-                // Ignoring validation errors for now
-                dataset.add_path(path, rootpos, validate).unwrap_or(())
+            walk(path, ignore, &mut |path, mtime| {
+                dataset.try_add_path(path, rootpos, options.validate, mtime)
             })?;
         }
         if let Some(derivatives) = derivatives {
@@ -201,22 +592,462 @@ impl Layout {
                     let rootpos = dataset
                         .register_root(Some(&path), label.clone())
                         .unwrap_or(0);
-                    iterdir(path, &ignore, |path| {
-                        // Ignoring validation errors for now
-                        dataset.add_path(path, rootpos, validate).unwrap_or(())
+                    walk(path, ignore, &mut |path, mtime| {
+                        dataset.try_add_path(path, rootpos, options.validate, mtime)
                     })?;
                 }
             }
         }
+        let mut layout = dataset.finalize();
+        if options.follow_links {
+            layout.resolve_links()?;
+        }
+        Ok(layout)
+    }
+
+    /// Index an explicit list of files without walking any directories
+    ///
+    /// The root of each file is the nearest parent holding a `dataset_description.json`,
+    /// or its own directory if there is none. All roots are indexed as raw datasets.
+    pub fn from_paths(
+        paths: impl IntoIterator<Item = PathBuf>,
+        options: LayoutOptions,
+    ) -> Result<Layout, IterdirErr> {
+        check_extra_entities(&options.extra_entities)?;
+        let mut dataset = LayoutBuilder::with_options(options.clone());
+        let mut current_root = None;
+        let mut rootpos = 0;
+        for path in paths {
+            let Ok(mtime) = modified(&path) else {
+                return Err(missing_paths_err(format!(
+                    "Path does not exist: {}",
+                    path.to_string_lossy(),
+                )));
+            };
+            let root = BidsPathBuilder::locate_root(&path).map(|(_, root)| root.to_path_buf());
+            if root != current_root {
+                rootpos = dataset
+                    .register_root(root.as_ref(), RootLabel::Raw)
+                    .unwrap_or(0);
+                current_root = root;
+            }
+            dataset.try_add_path(path, rootpos, options.validate, mtime);
+        }
+        let mut layout = dataset.finalize();
+        if options.follow_links {
+            layout.resolve_links()?;
+        }
+        Ok(layout)
+    }
+
+    /// Index paths listed in memory, such as those exported by `to_records`, without
+    /// walking any directories
+    ///
+    /// Entities given alongside a path are used in place of those parsed from it, keyed
+    /// by their full or short names. Paths without entities are parsed as usual. The root
+    /// of each path is the nearest parent of a listed `dataset_description.json` or of a
+    /// root given in `options.descriptions`, or its own directory if there is none. All
+    /// roots are indexed as raw datasets.
+    pub fn from_records(
+        records: Vec<(PathBuf, Option<HashMap<String, String>>)>,
+        options: LayoutOptions,
+    ) -> Result<Layout, IterdirErr> {
+        check_extra_entities(&options.extra_entities)?;
+        let description_roots: HashSet<PathBuf> = records
+            .iter()
+            .filter(|(path, _)| {
+                path.file_name()
+                    .is_some_and(|name| name == "dataset_description.json")
+            })
+            .filter_map(|(path, _)| path.parent().map(Path::to_path_buf))
+            .chain(options.descriptions.keys().map(clean))
+            .collect();
+        let mut dataset = LayoutBuilder::detached(options.clone());
+        let mut current_root = None;
+        let mut rootpos = 0;
+        for (path, entities) in records {
+            let root = path
+                .ancestors()
+                .skip(1)
+                .find(|parent| description_roots.contains(*parent))
+                .or_else(|| path.parent())
+                .map(Path::to_path_buf);
+            if root != current_root {
+                rootpos = dataset
+                    .register_root(root.as_ref(), RootLabel::Raw)
+                    .unwrap_or(0);
+                current_root = root;
+            }
+            match entities {
+                Some(entities) => {
+                    let entities = entities
+                        .iter()
+                        .map(|(key, val)| {
+                            (
+                                deref_key_alias(key, &options.extra_entities).unwrap_or(key),
+                                val.as_str(),
+                            )
+                        })
+                        .collect_vec();
+                    let is_entity = |key: &str| {
+                        entities.iter().any(|(k, _)| *k == key)
+                            || check_entity(key, &options.extra_entities, options.ignore_key_case)
+                    };
+                    let bidspath = BidsPathBuilder::new(path.clone(), rootpos)
+                        .ok()
+                        .and_then(|builder| builder.template_parse(is_entity).ok())
+                        .or_else(|| {
+                            BidsPathBuilder::new(path.clone(), rootpos)
+                                .ok()
+                                .map(BidsPathBuilder::no_parse)
+                        });
+                    match bidspath {
+                        Some(bidspath) => dataset.add_parsed_path(bidspath, &entities, None),
+                        // Only paths that aren't valid unicode fail, which are recorded
+                        // as invalid in the usual way
+                        None => dataset.try_add_path(path, rootpos, options.validate, None),
+                    }
+                }
+                None => dataset.try_add_path(path, rootpos, options.validate, None),
+            }
+        }
         Ok(dataset.finalize())
     }
 
+    /// Reindex the roots of this layout, reusing paths already parsed
+    ///
+    /// Only files not found in the current view, or modified since they were indexed, are
+    /// parsed. Files no longer on disk are dropped, and indices are renumbered in walk
+    /// order, as if created from scratch. If metadata was indexed, it is indexed again.
+    pub fn update(&self) -> Result<Layout, IterdirErr> {
+        let mut entities = self.entities_by_index();
+        let cached: HashMap<&str, usize> = self
+            .view_indices()
+            .map(|i| (self.paths[i].as_str(), i))
+            .collect();
+
+        let mut dataset = LayoutBuilder::with_options(self.options.clone());
+        let ignore = Self::root_ignore(self.seeds.iter().map(|seed| &seed.path), &self.options)?;
+        for seed in self.seeds.iter() {
+            let rootpos = dataset
+                .register_root(Some(&seed.path), seed.label.clone())
+                .unwrap_or(0);
+            walk(
+                seed.path.clone(),
+                &ignore,
+                &self.options,
+                None,
+                |path, mtime| {
+                    match path.to_str().and_then(|p| cached.get(p)) {
+                        Some(&i) if self.mtimes[i] == mtime => dataset.add_parsed_path(
+                            self.paths[i].clone(),
+                            &entities.remove(&i).unwrap_or_default(),
+                            mtime,
+                        ),
+                        _ => dataset.try_add_path(path, rootpos, self.options.validate, mtime),
+                    };
+                    Ok(())
+                },
+            )?;
+        }
+        let mut layout = self.finalize_rebuild(dataset);
+        if self.options.follow_links {
+            layout.resolve_links()?;
+        }
+        Ok(layout)
+    }
+
+    /// Check whether any root directory was modified since the layout was indexed
+    ///
+    /// Only the roots themselves are checked, so files added or removed deeper within a
+    /// dataset go unnoticed. This is a cheap hint that a cached layout should be updated,
+    /// not a replacement for [`Layout::update`]. Roots that can no longer be read count as
+    /// modified.
+    pub fn is_stale(&self) -> bool {
+        self.seeds
+            .iter()
+            .map(|seed| &seed.path)
+            .chain(self.roots.keys())
+            .any(|root| match modified(root) {
+                Ok(mtime) => mtime.is_some_and(|mtime| mtime > self.indexed),
+                Err(_) => true,
+            })
+    }
+
+    /// Check paths in view against the filesystem without searching for new files
+    ///
+    /// Paths that no longer exist are dropped. Paths modified since they were indexed are
+    /// parsed again if `reparse` is set, and dropped otherwise.
+    pub fn revalidate(&self, reparse: bool) -> Layout {
+        let mut entities = self.entities_by_index();
+        let mut dataset = LayoutBuilder::with_options(self.options.clone());
+        for (seed, ixs) in self.seed_groups() {
+            let rootpos = dataset
+                .register_root(Some(&seed.path), seed.label.clone())
+                .unwrap_or(0);
+            for i in ixs {
+                let path = &self.paths[i];
+                let Ok(mtime) = modified(path.as_path()) else {
+                    continue;
+                };
+                if self.mtimes[i] == mtime {
+                    dataset.add_parsed_path(
+                        path.clone(),
+                        &entities.remove(&i).unwrap_or_default(),
+                        mtime,
+                    );
+                } else if reparse {
+                    dataset.try_add_path(
+                        path.as_path().to_path_buf(),
+                        rootpos,
+                        self.options.validate,
+                        mtime,
+                    );
+                }
+            }
+        }
+        self.finalize_rebuild(dataset)
+    }
+
+    /// Layout limited to paths in view that still exist on disk
+    ///
+    /// Unlike [`Layout::revalidate`], nothing is parsed again: missing paths are masked out
+    /// of the view as by a query. Broken symlinks count as missing.
+    pub fn filter_existing(&self) -> Layout {
+        let existing: IndexSet = self
+            .view_indices()
+            .filter(|&i| self.paths[i].as_path().exists())
+            .collect();
+        self.query(None, None, Some(&existing), false)
+            .expect("Queries without terms cannot fail")
+    }
+
+    /// Paths in view that no longer exist on disk
+    pub fn missing_files(&self) -> Vec<BidsPath> {
+        let entity_keys = self.entity_keys().cloned().collect();
+        self.view_indices()
+            .filter(|&i| !self.paths[i].as_path().exists())
+            .map(|i| {
+                let mut path = self.paths[i].clone();
+                path.update_parents(&entity_keys);
+                path
+            })
+            .collect()
+    }
+
+    /// Index the datasets referenced by the `DatasetLinks` of raw roots
+    ///
+    /// Links are followed transitively, and each linked dataset is added as a derivative
+    /// root labelled with its link key. Remote links, missing directories, and datasets
+    /// overlapping a root already in the layout are skipped.
+    pub fn resolve_links(&mut self) -> Result<(), IterdirErr> {
+        let mut registered = self
+            .seeds
+            .iter()
+            .map(|seed| absolute_clean(&seed.path))
+            .collect_vec();
+        let mut pending = self
+            .roots
+            .raw_items()
+            .flat_map(|(root, dr)| dataset_links(root, dr.get_description().as_deref()))
+            .collect_vec();
+        let mut linked = Vec::new();
+        while let Some((key, target)) = pending.pop() {
+            let target = absolute_clean(&target);
+            if !target.is_dir()
+                || registered
+                    .iter()
+                    .any(|r| target.starts_with(r) || r.starts_with(&target))
+            {
+                continue;
+            }
+            let description = DatasetDescription::open(&target).ok();
+            pending.extend(dataset_links(&target, description.as_ref()));
+            registered.push(target.clone());
+            linked.push((key, target));
+        }
+        if linked.is_empty() {
+            return Ok(());
+        }
+
+        let mut entities = self.entities_by_index();
+        let mut dataset = LayoutBuilder::with_options(self.options.clone());
+        for (seed, ixs) in self.seed_groups() {
+            dataset.register_root(Some(&seed.path), seed.label.clone());
+            for i in ixs {
+                dataset.add_parsed_path(
+                    self.paths[i].clone(),
+                    &entities.remove(&i).unwrap_or_default(),
+                    self.mtimes[i],
+                );
+            }
+        }
+        let ignore = Self::root_ignore(
+            self.seeds
+                .iter()
+                .map(|seed| &seed.path)
+                .chain(linked.iter().map(|(_, path)| path)),
+            &self.options,
+        )?;
+        for (key, path) in linked {
+            let rootpos = dataset
+                .register_root(Some(&path), RootLabel::DerivativeLabelled(key))
+                .unwrap_or(0);
+            walk(path, &ignore, &self.options, None, |path, mtime| {
+                dataset.try_add_path(path, rootpos, self.options.validate, mtime);
+                Ok(())
+            })?;
+        }
+        drop(entities);
+        *self = self.finalize_rebuild(dataset);
+        Ok(())
+    }
+
+    /// Combine with an independently indexed layout, such as one built from other roots
+    ///
+    /// Paths in view of both layouts are indexed together, with those of `other` following
+    /// those of this layout, so the result can be queried across both. Paths of `other`
+    /// already in this layout are skipped. Options are merged as by [`LayoutOptions::merge`],
+    /// and metadata is indexed again if either layout had it indexed.
+    pub fn concat(&self, other: &Layout) -> Result<Layout, QueryErr> {
+        let existing: HashSet<PathBuf> = self
+            .view_indices()
+            .map(|i| absolute_clean(self.paths[i].as_path()))
+            .collect();
+        let mut dataset = LayoutBuilder::with_options(self.options.merge(&other.options)?);
+        for (layout, is_other) in [(self, false), (other, true)] {
+            let mut entities = layout.entities_by_index();
+            for (seed, ixs) in layout.seed_groups() {
+                dataset.register_root(Some(&seed.path), seed.label.clone());
+                for i in ixs {
+                    let path = &layout.paths[i];
+                    if is_other && existing.contains(&absolute_clean(path.as_path())) {
+                        continue;
+                    }
+                    dataset.add_parsed_path(
+                        path.clone(),
+                        &entities.remove(&i).unwrap_or_default(),
+                        layout.mtimes[i],
+                    );
+                }
+            }
+        }
+        let layout = dataset.finalize();
+        if self.metadata.get().is_some() || other.metadata.get().is_some() {
+            layout.index_metadata();
+        }
+        Ok(layout)
+    }
+
+    fn finalize_rebuild(&self, dataset: LayoutBuilder) -> Layout {
+        let layout = dataset.finalize();
+        // Indices have changed, so the metadata index must be rebuilt
+        if self.metadata.get().is_some() {
+            layout.index_metadata();
+        }
+        layout
+    }
+
+    /// Entity-value pairs of each path, keyed by path index
+    fn entities_by_index(&self) -> HashMap<usize, Vec<(&str, &str)>> {
+        let mut entities: HashMap<usize, Vec<(&str, &str)>> = HashMap::new();
+        for (entity, values) in self.entities.iter() {
+            for (value, ixs) in values {
+                for i in ixs {
+                    entities
+                        .entry(*i)
+                        .or_default()
+                        .push((entity.as_str(), value.as_str()));
+                }
+            }
+        }
+        entities
+    }
+
+    /// Indices in view grouped under the root they were originally registered with
+    fn seed_groups(&self) -> Vec<(&RootSeed, Vec<usize>)> {
+        let mut groups = Vec::with_capacity(self.seeds.len());
+        let mut seeds = self.seeds.iter().peekable();
+        let mut view = self.view_indices().peekable();
+        while let Some(seed) = seeds.next() {
+            let end = seeds.peek().map(|s| s.start).unwrap_or(usize::MAX);
+            let mut ixs = Vec::new();
+            while let Some(i) = view.next_if(|i| *i < end) {
+                ixs.push(i);
+            }
+            groups.push((seed, ixs));
+        }
+        groups
+    }
+
+    /// Build the ignore used to walk `roots`
+    ///
+    /// Each root is skipped when reached while walking another, and the `.bidsignore` of
+    /// the dataset containing it is read. Directory names and include patterns are taken
+    /// from `options`. The result can be passed to [`Layout::create`] to reuse it
+    /// across layouts indexed from the same roots.
+    pub fn root_ignore<'a>(
+        roots: impl Iterator<Item = &'a PathBuf>,
+        options: &LayoutOptions,
+    ) -> Result<IterIgnore, IterdirErr> {
+        let mut ignore = IterIgnore::new();
+        for root in roots {
+            ignore.paths.insert(root.clone());
+            if let Some((_, dataset_root)) = BidsPathBuilder::locate_root(root) {
+                ignore.add_bidsignore(dataset_root)?;
+            }
+        }
+        ignore.names = Self::ignore_names(options);
+        if let Some(include) = &options.include {
+            ignore.set_include(include)?;
+        }
+        Ok(ignore)
+    }
+
+    /// Names of directories skipped according to `options`
+    fn ignore_names(options: &LayoutOptions) -> HashSet<OsString> {
+        match &options.ignore_dirs {
+            Some(dirs) => dirs.iter().map(OsString::from).collect(),
+            None => DEFAULT_IGNORE_DIRS.iter().map(OsString::from).collect(),
+        }
+    }
+
+    /// `options` updated to describe the directory names and include patterns of `ignore`,
+    /// so that a layout walked with it is walked the same way when updated
+    fn options_for_ignore(mut options: LayoutOptions, ignore: &IterIgnore) -> LayoutOptions {
+        if ignore.names != Self::ignore_names(&options) {
+            options.ignore_dirs = Some(
+                ignore
+                    .names
+                    .iter()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .collect(),
+            );
+        }
+        options.include = ignore.include_patterns().map(<[String]>::to_vec);
+        options
+    }
+
+    /// Parse a path using the entities of this layout
+    ///
+    /// Paths under a known root are assigned that root without touching the filesystem.
+    /// Otherwise, the root is located by searching for a `dataset_description.json`.
     pub fn parse(&self, path: PathBuf) -> Result<BidsPath, BidsPathErr> {
-        let root = BidsPathBuilder::locate_root(&path)
-            .map(|r| r.0)
-            .unwrap_or(0);
+        let root = match self.roots.nearest_root(&path) {
+            Some((root, _)) if path.starts_with(root) => root.to_string_lossy().len(),
+            _ => BidsPathBuilder::locate_root(&path)
+                .map(|r| r.0)
+                .unwrap_or(0),
+        };
         let builder = BidsPathBuilder::new(path, root)?;
-        builder.template_parse(|s| self.entities.contains_key(s) || check_entity(s))
+        builder.template_parse(|s| {
+            self.entities.contains_key(s)
+                || check_entity(
+                    s,
+                    &self.options.extra_entities,
+                    self.options.ignore_key_case,
+                )
+        })
     }
 
     fn filter_root<'a>(
@@ -242,6 +1073,26 @@ impl Layout {
         }
     }
 
+    /// Roots with paths in view, sorted, along with their category and the number of
+    /// paths in view beneath them
+    pub fn roots_detailed(&self) -> Vec<(&PathBuf, &RootCategory, usize)> {
+        self.roots
+            .categories()
+            .map(|(root, category)| {
+                let count = if self.is_filtered() {
+                    self.view_indices()
+                        .filter(|i| category.root().contains(i))
+                        .count()
+                } else {
+                    category.root().get_range().len()
+                };
+                (root, category, count)
+            })
+            .filter(|(.., count)| *count > 0 || !self.is_filtered())
+            .sorted_by(|a, b| a.0.cmp(b.0))
+            .collect()
+    }
+
     fn filtered_roots<'a, I: Iterator<Item = (&'a PathBuf, &'a DatasetRoot)> + 'a>(
         &'a self,
         roots: I,
@@ -284,6 +1135,101 @@ impl Layout {
             .collect()
     }
 
+    /// Description of the root at `root`, compared after making both paths absolute.
+    /// Returns `None` if `root` is not a root of the layout or has no description.
+    pub fn description_for(&self, root: &Path) -> Option<Arc<DatasetDescription>> {
+        let root = absolute_clean(root);
+        self.roots
+            .items()
+            .find(|(r, _)| absolute_clean(r) == root)
+            .and_then(|(_, data)| data.get_description())
+    }
+
+    pub fn options(&self) -> &LayoutOptions {
+        &self.options
+    }
+
+    /// Paths found while indexing that could not be parsed, along with the reason
+    pub fn invalid_paths(&self) -> &[BidsPathErr] {
+        &self.invalid_paths
+    }
+
+    /// Paths skipped because they are not valid unicode, lossily converted for display
+    ///
+    /// These are the [`BidsPathErr::Encoding`] entries of [`Layout::invalid_paths`].
+    pub fn skipped_paths(&self) -> Vec<String> {
+        self.invalid_paths
+            .iter()
+            .filter(|err| matches!(err, BidsPathErr::Encoding(..)))
+            .map(BidsPathErr::path_str)
+            .collect()
+    }
+
+    /// Check paths in view for common problems
+    ///
+    /// This is not a full bids validator, but catches files outside of any bids datatype
+    /// directory, filenames with entities but no suffix, entities repeated within a
+    /// filename, and filename entities disagreeing with those of parent directories.
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        for i in self.view_indices() {
+            let path = &self.paths[i];
+            let template = path.as_str();
+            // Files above the subject directories need no datatype
+            let in_datatype = path.parents.is_empty()
+                || match &path.datatype {
+                    Some(datatype) => check_datatype(&template[datatype.clone()]),
+                    // Files directly within an entity directory, such as `_scans.tsv`
+                    None => {
+                        let dir = path
+                            .as_path()
+                            .parent()
+                            .and_then(|dir| dir.file_name())
+                            .and_then(|dir| dir.to_str());
+                        chain![&path.parents, path.uncertain_parents.iter().flatten()]
+                            .any(|parent| Some(&template[parent.slice.clone()]) == dir)
+                    }
+                };
+            if !in_datatype {
+                issues.push(ValidationIssue::UnknownDatatype(template.to_string()));
+            }
+            if path.suffix.is_none() && !path.entities.is_empty() {
+                issues.push(ValidationIssue::MissingSuffix(template.to_string()));
+            }
+            for key in path
+                .entities
+                .iter()
+                .map(|entity| entity.get_key(template))
+                .duplicates()
+            {
+                issues.push(ValidationIssue::DuplicateEntity(
+                    template.to_string(),
+                    get_key_alias(key, &self.options.extra_entities).to_string(),
+                ));
+            }
+            for parent in &path.parents {
+                let (key, parent_val) = parent.get(template);
+                for entity in &path.entities {
+                    let (file_key, file_val) = entity.get(template);
+                    if file_key == key && file_val != parent_val {
+                        issues.push(ValidationIssue::EntityMismatch(
+                            template.to_string(),
+                            get_key_alias(key, &self.options.extra_entities).to_string(),
+                            parent_val.to_string(),
+                            file_val.to_string(),
+                        ));
+                    }
+                }
+            }
+        }
+        issues
+    }
+
+    /// Roots with a `dataset_description.json` that could not be read, along with the reason
+    pub fn description_errors(&self) -> &[(PathBuf, String)] {
+        &self.description_errors
+    }
+
     pub fn display_root_ranges(&self) -> String {
         format!("{:?}", self.roots)
     }
@@ -292,21 +1238,146 @@ impl Layout {
         self.entities.keys()
     }
 
+    /// Values of an entity, sorted numerically if all are integers and alphabetically
+    /// otherwise
     pub fn entity_vals(&self, key: &str) -> Option<Vec<&String>> {
+        self.entity_vals_unsorted(key).map(|mut vals| {
+            natural_sort(&mut vals);
+            vals
+        })
+    }
+
+    /// Values of an entity in arbitrary order
+    pub fn entity_vals_unsorted(&self, key: &str) -> Option<Vec<&String>> {
         self.entities.get(key).map(|val| val.keys().collect_vec())
     }
 
-    pub fn entity_key_vals(&self) -> HashMap<&String, Vec<&String>> {
+    /// Number of paths in view with each value of an entity, given by its short or long
+    /// name. Returns `None` if no path has the entity.
+    pub fn count_by(&self, entity: &str) -> Option<HashMap<&String, usize>> {
+        let key = deref_key_alias(entity, &self.options.extra_entities).unwrap_or(entity);
+        // Entity tables are filtered along with the view, so their sets are already limited
+        // to paths in view
         self.entities
+            .get(key)
+            .map(|vals| vals.iter().map(|(val, ixs)| (val, ixs.len())).collect())
+    }
+
+    /// Number of paths in view with each combination of values of two entities, given by
+    /// short or long name, nested by `row` then `col`
+    ///
+    /// Combinations without any paths are left out. Returns `None` if no path has either
+    /// entity.
+    pub fn cross_tab(
+        &self,
+        row: &str,
+        col: &str,
+    ) -> Option<HashMap<&String, HashMap<&String, usize>>> {
+        let extra = &self.options.extra_entities;
+        let rows = self
+            .entities
+            .get(deref_key_alias(row, extra).unwrap_or(row))?;
+        let cols = self
+            .entities
+            .get(deref_key_alias(col, extra).unwrap_or(col))?;
+        Some(
+            rows.iter()
+                .map(|(row, row_ixs)| {
+                    let counts: HashMap<_, _> = cols
+                        .iter()
+                        .map(|(col, col_ixs)| (col, row_ixs.intersection(col_ixs).count()))
+                        .filter(|(_, count)| *count > 0)
+                        .collect();
+                    (row, counts)
+                })
+                .filter(|(_, counts)| !counts.is_empty())
+                .collect(),
+        )
+    }
+
+    /// Distinct combinations of values of `entities`, given by short or long name, among
+    /// the paths in view, keyed by long name and sorted
+    ///
+    /// Paths lacking any of the entities are skipped unless `partial` is set, in which case
+    /// the missing entities are left out of their combination. Returns `None` if no path
+    /// has one of the entities.
+    pub fn unique_combinations(
+        &self,
+        entities: &[String],
+        partial: bool,
+    ) -> Option<Vec<HashMap<String, String>>> {
+        let extra = &self.options.extra_entities;
+        let keys = entities
+            .iter()
+            .map(|entity| deref_key_alias(entity, extra).unwrap_or(entity))
+            .collect_vec();
+        let values = keys
             .iter()
-            .map(|(key, value)| (key, value.keys().collect_vec()))
+            .map(|key| {
+                let mut by_index = HashMap::new();
+                for (val, ixs) in self.entities.get(*key)? {
+                    by_index.extend(ixs.iter().map(|i| (*i, val)));
+                }
+                Some(by_index)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let combinations: BTreeSet<Vec<Option<&String>>> = self
+            .view_indices()
+            .map(|i| {
+                values
+                    .iter()
+                    .map(|vals| vals.get(&i).copied())
+                    .collect_vec()
+            })
+            .filter(|combo| {
+                combo.iter().any(Option::is_some) && (partial || combo.iter().all(Option::is_some))
+            })
+            .collect();
+        Some(
+            combinations
+                .into_iter()
+                .map(|combo| {
+                    keys.iter()
+                        .zip(combo)
+                        .filter_map(|(key, val)| {
+                            Some((get_key_alias(key, extra).to_string(), val?.clone()))
+                        })
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    /// Datatypes present among the paths in view for each value of `entity`
+    pub fn datatypes_by(&self, entity: &str) -> Option<HashMap<&String, HashSet<&String>>> {
+        self.cross_tab(entity, "datatype").map(|table| {
+            table
+                .into_iter()
+                .map(|(value, datatypes)| (value, datatypes.into_keys().collect()))
+                .collect()
+        })
+    }
+
+    pub fn entity_key_vals(&self) -> HashMap<&String, Vec<&String>> {
+        self.entities
+            .keys()
+            .filter_map(|key| Some((key, self.entity_vals(key)?)))
+            .collect()
+    }
+
+    /// Entities with more than one value among the paths in view
+    pub fn ambiguous_entities(&self) -> HashMap<&String, Vec<&String>> {
+        self.entity_key_vals()
+            .into_iter()
+            .filter(|(_, vals)| vals.len() > 1)
             .collect()
     }
 
     pub fn entity_fullkey_vals(&self) -> HashMap<&str, Vec<&String>> {
+        let extra = &self.options.extra_entities;
         self.entities
-            .iter()
-            .map(|(key, value)| (get_key_alias(key), value.keys().collect_vec()))
+            .keys()
+            .filter_map(|key| Some((get_key_alias(key, extra), self.entity_vals(key)?)))
             .collect()
     }
 
@@ -318,11 +1389,129 @@ impl Layout {
         })
     }
 
+    /// Longest directory shared by every path in view
+    ///
+    /// A single path gives its parent. Returns `None` if the view is empty or the paths
+    /// share no components, such as paths on different drives.
+    pub fn common_prefix(&self) -> Option<PathBuf> {
+        let mut parents = self
+            .view_indices()
+            .map(|i| self.paths[i].as_path().parent().unwrap_or(Path::new("")));
+        let mut prefix = parents.next()?.components().collect_vec();
+        for parent in parents {
+            let shared = prefix
+                .iter()
+                .zip(parent.components())
+                .take_while(|(a, b)| **a == *b)
+                .count();
+            prefix.truncate(shared);
+        }
+        if prefix.is_empty() {
+            None
+        } else {
+            Some(prefix.into_iter().collect())
+        }
+    }
+
+    /// Directory hierarchy of the paths in view as an indented string, optionally limited
+    /// to `max_depth` levels below the directory where paths diverge
+    pub fn tree(&self, max_depth: Option<usize>) -> String {
+        let view: IndexSet = self.view_indices().collect();
+        self.filetree.render(
+            |i| {
+                view.contains(&i).then(|| {
+                    self.paths[i]
+                        .as_path()
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_default()
+                })
+            },
+            max_depth,
+        )
+    }
+
+    /// Distinct heads of the paths in view, sorted
+    ///
+    /// The head of a path is everything before its first entity directory, and is used
+    /// to infer dataset roots.
+    pub fn heads(&self) -> Vec<&str> {
+        let view: IndexSet = self.view_indices().collect();
+        self.heads
+            .iter()
+            .filter(|(_, indices)| indices.iter().any(|i| view.contains(i)))
+            .map(|(head, _)| head.as_str())
+            .sorted()
+            .collect()
+    }
+
+    /// Node of the filetree for `dir`, which may be given relative to the current directory
+    /// even if the layout was indexed with absolute paths, or vice versa
+    fn find_dir(&self, dir: &Path) -> Option<&FileTree> {
+        self.filetree.find(&clean(dir)).or_else(|| {
+            let target = absolute_clean(dir);
+            self.roots.keys().find_map(|root| {
+                let rel = target.strip_prefix(absolute_clean(root)).ok()?;
+                self.filetree.find(&root.join(rel))
+            })
+        })
+    }
+
+    /// Path in view stored at `path`, annotated as when indexed, without reparsing
+    ///
+    /// Like [`Layout::files_under`], `path` may be relative or absolute regardless of how
+    /// the layout was indexed.
+    pub fn get_by_path(&self, path: &Path) -> Option<BidsPath> {
+        let name = path.file_name()?;
+        let tree = self.find_dir(path.parent()?)?;
+        let ix = tree
+            .files()
+            .iter()
+            .copied()
+            .find(|i| self.paths[*i].as_path().file_name() == Some(name))?;
+        if self
+            .view
+            .get()
+            .is_some_and(|view| view.binary_search(&ix).is_err())
+        {
+            return None;
+        }
+        let mut found = self.paths[ix].clone();
+        found.update_parents(&self.entity_keys().cloned().collect());
+        Some(found)
+    }
+
+    /// Paths in view anywhere beneath the directory `dir`, in index order
+    ///
+    /// `dir` may be given relative to the current directory even if the layout was
+    /// indexed with absolute paths, or vice versa. Directories not found in the layout
+    /// give an empty list.
+    pub fn files_under(&self, dir: &Path) -> Vec<BidsPath> {
+        let Some(found) = self
+            .find_dir(dir)
+            .and_then(|tree| tree.get_subfiles(Path::new("")))
+        else {
+            return Vec::new();
+        };
+        let entity_keys = self.entity_keys().cloned().collect();
+        self.view_indices()
+            .filter(|i| found.contains(i))
+            .map(|i| {
+                let mut path = self.paths[i].clone();
+                path.update_parents(&entity_keys);
+                path
+            })
+            .collect()
+    }
+
     pub fn fmt_elided_list(&self, limit: usize) -> String {
+        if self.len() == 0 {
+            return String::from("[]");
+        }
         let mut msg = String::from("[ ");
         msg.push_str(
             &self
-                .get_paths()
+                .get_paths(false)
                 .take(limit)
                 .map(|bp| format!("\"{}\"", bp.path.as_str()))
                 .join("\n  "),
@@ -344,41 +1533,148 @@ impl Layout {
         0..self.paths.len()
     }
 
-    pub fn all_entity_indices(&self, entity: &str) -> Option<HashSet<usize>> {
+    /// Iterate over the indices of the current view. Unlike `get_view`, this does not
+    /// materialize the view of an unfiltered layout.
+    pub fn view_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        match self.view.get() {
+            Some(view) => Either::Left(view.iter().copied()),
+            None => Either::Right(self.full_range()),
+        }
+    }
+
+    /// Hash of the paths in the current view, independent of their order. Equal layouts
+    /// always have the same hash, even if they were indexed separately.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for path in self.sorted_view_paths() {
+            path.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Paths in the current view, sorted so that layouts can be compared regardless of
+    /// the order they were indexed in
+    fn sorted_view_paths(&self) -> impl Iterator<Item = &str> {
+        self.view_indices()
+            .map(|i| self.paths[i].as_str())
+            .sorted_unstable()
+    }
+
+    /// Index into the full layout of the path at `index` in the current view
+    pub fn view_index(&self, index: usize) -> Option<usize> {
+        match self.view.get() {
+            Some(view) => view.get(index).copied(),
+            None => Some(index).filter(|i| *i < self.paths.len()),
+        }
+    }
+
+    pub fn all_entity_indices(&self, entity: &str) -> Option<IndexSet> {
         Some(
             self.entities
                 .get(entity)?
                 .values()
-                .fold(HashSet::<usize>::new(), |set, next| &set | next),
+                .fold(IndexSet::default(), |set, next| &set | next),
         )
     }
 
-    pub fn get_paths(&self) -> BidsPathViewIterator {
-        if let Some(_) = self.view.get() {
+    /// Iterate over the paths in the view
+    ///
+    /// Paths are yielded in index order, which depends on how the layout was built, unless
+    /// `sorted` is set, in which case they are ordered by their path string.
+    pub fn get_paths(&self, sorted: bool) -> BidsPathViewIterator {
+        if sorted {
+            let mut indices = self.get_view().clone();
+            indices.sort_by(|a, b| self.paths[*a].as_str().cmp(self.paths[*b].as_str()));
             BidsPathViewIterator::new(
                 Arc::clone(&self.paths),
                 self.entity_keys().cloned().collect(),
-                Some(self.get_view().clone()),
+                Some(indices),
             )
         } else {
             BidsPathViewIterator::new(
                 Arc::clone(&self.paths),
                 self.entity_keys().cloned().collect(),
-                None,
+                self.view.get().cloned(),
             )
         }
     }
 
+    /// Iterate over the paths in the view along with their entities, in index order
+    ///
+    /// Unlike [`Layout::get_paths`], paths are borrowed rather than cloned, so this is
+    /// cheaper for read-only access.
+    pub fn iter_entities(&self) -> BidsPathRefIterator<'_> {
+        BidsPathRefIterator::new(
+            &self.paths,
+            self.entity_keys().cloned().collect(),
+            self.view_indices(),
+        )
+    }
+
+    /// Check if a path is in the current view. Both sides are made absolute before
+    /// comparison, so relative paths are resolved against the current directory.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.position(path).is_some()
+    }
+
+    /// Index of a path within the current view
+    pub fn position(&self, path: &Path) -> Option<usize> {
+        self.positions
+            .get_or_init(|| {
+                let mut positions = HashMap::new();
+                for (pos, i) in self.view_indices().enumerate() {
+                    positions
+                        .entry(absolute_clean(self.paths[i].as_path()))
+                        .or_insert(pos);
+                }
+                positions
+            })
+            .get(&absolute_clean(path))
+            .copied()
+    }
+
+    /// Metadata of the path at `index`, merged from all sidecars applying to it and
+    /// `participants.tsv`. Values are given as indexed for queries, leaving out arrays and
+    /// objects.
+    ///
+    /// Returns `None` if metadata has not been indexed or the index is out of range
+    pub fn metadata_for(&self, index: usize) -> Option<HashMap<String, String>> {
+        let ix = self.view_index(index)?;
+        self.metadata_store.get().map(|store| store.get_strings(ix))
+    }
+
+    /// Metadata of the path at `index` with values in their original json form
+    ///
+    /// Returns `None` if metadata has not been indexed or the index is out of range
+    pub fn get_raw_metadata(&self, index: usize) -> Option<HashMap<String, serde_json::Value>> {
+        let ix = self.view_index(index)?;
+        self.metadata_store.get().map(|store| store.get(ix))
+    }
+
+    /// Sidecars contributing to the metadata of the path at `index`, from most distant to
+    /// nearest. A sidecar is not listed as applying to itself.
+    ///
+    /// Returns `None` if metadata has not been indexed or the index is out of range
+    pub fn sidecars_for(&self, index: usize) -> Option<Vec<PathBuf>> {
+        let ix = self.view_index(index)?;
+        let store = self.metadata_store.get()?;
+        Some(
+            store
+                .sidecars(ix)
+                .filter(|sidecar| *sidecar != ix)
+                .map(|sidecar| self.paths[sidecar].as_path().to_path_buf())
+                .collect(),
+        )
+    }
+
     pub fn get_path(&self, index: usize) -> Option<BidsPath> {
-        let ix = if let Some(view) = self.view.get() {
-            *view.iter().nth(index)?
-        } else {
-            index
-        };
-        self.paths.get(ix).cloned().map(|mut path| {
-            path.update_parents(&self.entity_keys().cloned().collect());
-            path
-        })
+        self.paths
+            .get(self.view_index(index)?)
+            .cloned()
+            .map(|mut path| {
+                path.update_parents(&self.entity_keys().cloned().collect());
+                path
+            })
     }
 
     /// The total number of paths in the layout, ignoring applied views
@@ -386,6 +1682,15 @@ impl Layout {
         self.paths.len()
     }
 
+    /// Whether the view excludes any paths. Views are sorted subsets of the layout, so a
+    /// view initialized over every path is treated as unfiltered.
+    pub fn is_filtered(&self) -> bool {
+        self.view
+            .get()
+            .map(|view| view.len() != self.paths.len())
+            .unwrap_or(false)
+    }
+
     /// The total number of paths in the current view of the layout
     pub fn len(&self) -> usize {
         if let Some(idx) = self.view.get() {
@@ -395,31 +1700,53 @@ impl Layout {
         }
     }
 
+    /// The most specific root of the layout containing `path`. The path need not be indexed
+    pub fn root_for(&self, path: &Path) -> Option<(&PathBuf, &RootCategory)> {
+        self.roots.nearest_root(path)
+    }
+
     pub fn get_scopes(&self, scopes: Vec<String>) -> Result<Option<Vec<PathBuf>>, QueryErr> {
         self.roots.get_scopes(scopes)
     }
 
+    /// Roots in any of `scopes` at or beneath roots matching `root`
+    pub fn get_scoped_roots(
+        &self,
+        root: (PathBuf, RootMatch),
+        scopes: Vec<String>,
+    ) -> Result<Vec<PathBuf>, QueryErr> {
+        self.roots.get_scoped_roots(root, scopes)
+    }
+
     fn query_entity(
         &self,
         query: Vec<QueryTerms>,
-        entity: &String,
-        values: &HashMap<String, HashSet<usize>>,
-        new_entities: &mut HashMap<String, HashMap<String, HashSet<usize>>>,
-    ) -> Result<HashSet<usize>, QueryErr> {
-        let mut new_entity_vals = HashMap::new();
-        let mut has_true = false;
-        let mut has_false = false;
+        entity: &str,
+        values: &ValueMap<String>,
+        new_entities: &mut HashMap<String, ValueMap<String>>,
+    ) -> Result<IndexSet, QueryErr> {
+        let mut new_entity_vals = ValueMap::default();
+        let mut has_present = false;
+        let mut has_absent = false;
         let mut queried = HashSet::new();
+        let mut patterns = Vec::new();
+        let mut queried_ci = HashMap::new();
+        let mut excluded = Vec::new();
+        let mut ranges = Vec::new();
+        let mut has_positive = false;
         for q in query {
+            if let QueryTerms::Not(string) = q {
+                excluded.push(string);
+                continue;
+            }
+            has_positive = true;
             match q {
-                QueryTerms::Bool(boolean) => match boolean {
-                    true => {
-                        has_true = true;
-                    }
-                    false => {
-                        has_false = true;
-                    }
-                },
+                QueryTerms::Present => {
+                    has_present = true;
+                }
+                QueryTerms::Absent => {
+                    has_absent = true;
+                }
                 QueryTerms::String(string) => {
                     queried.insert(string);
                 }
@@ -436,7 +1763,7 @@ impl Layout {
                         .collect();
                     if matches.len() > 1 {
                         return Err(QueryErr::AmbiguousQuery(
-                            entity.clone(),
+                            entity.to_string(),
                             num,
                             matches.into_iter().cloned().collect(),
                         ));
@@ -445,35 +1772,92 @@ impl Layout {
                         queried.insert(m.to_owned());
                     }
                 }
-                QueryTerms::Any => (),
+                QueryTerms::NumberCoalesce(num) => {
+                    queried.extend(
+                        values
+                            .keys()
+                            .filter(|v| v.parse::<u64>() == Ok(num))
+                            .cloned(),
+                    );
+                }
+                QueryTerms::Regex(pattern) => {
+                    patterns.push(pattern);
+                }
+                QueryTerms::StringCI(string) => {
+                    queried_ci.insert(string.to_lowercase(), string);
+                }
+                QueryTerms::NumRange {
+                    start,
+                    end,
+                    inclusive,
+                } => {
+                    ranges.push((start, end, inclusive));
+                }
+                QueryTerms::Not(_) | QueryTerms::Any => (),
             }
         }
-        let mut selection: HashSet<usize> = values
+        let mut found_ci = HashSet::new();
+        let mut selection: IndexSet = values
             .iter()
             .filter_map(|(label, indices)| {
-                if queried.remove(label) || has_true {
+                let matches_ci = if queried_ci.is_empty() {
+                    false
+                } else {
+                    let lower = label.to_lowercase();
+                    let matched = queried_ci.contains_key(&lower);
+                    if matched {
+                        found_ci.insert(lower);
+                    }
+                    matched
+                };
+                if queried.remove(label)
+                    || matches_ci
+                    || has_present
+                    || patterns.iter().any(|pattern| pattern.is_match(label))
+                    || ranges.iter().any(|&(start, end, inclusive)| {
+                        QueryTerms::in_range(label, start, end, inclusive)
+                    })
+                {
                     new_entity_vals.insert(label.clone(), indices.clone());
                     Some(indices)
                 } else {
                     None
                 }
             })
-            .fold(HashSet::new(), |set, next| &set | next);
-        if has_false {
-            let false_indices: HashSet<_> = self
-                .get_view()
-                .iter()
-                .cloned()
-                .collect::<HashSet<_>>()
-                .difference(&self.all_entity_indices(&entity).unwrap())
-                .cloned()
+            .fold(IndexSet::default(), |set, next| &set | next);
+        if has_absent {
+            // Taken from `values` rather than the entity table, as metadata keys are
+            // queried here too
+            let with_entity = values
+                .values()
+                .fold(IndexSet::default(), |set, next| &set | next);
+            let absent_indices: IndexSet = self
+                .view_indices()
+                .filter(|i| !with_entity.contains(i))
                 .collect();
-            selection = &selection | &false_indices;
+            selection = &selection | &absent_indices;
         }
-        new_entities.insert(entity.clone(), new_entity_vals);
+        if !excluded.is_empty() {
+            if !has_positive {
+                selection = self.view_indices().collect();
+                new_entity_vals = values.clone();
+            }
+            for value in excluded {
+                if let Some(indices) = values.get(&value) {
+                    selection = &selection - indices;
+                    new_entity_vals.remove(&value);
+                }
+            }
+        }
+        new_entities.insert(entity.to_string(), new_entity_vals);
+        queried.extend(
+            queried_ci
+                .into_iter()
+                .filter_map(|(lower, orig)| (!found_ci.contains(&lower)).then_some(orig)),
+        );
         if queried.len() > 0 {
             Err(QueryErr::MissingVal(
-                entity.clone(),
+                entity.to_string(),
                 queried.into_iter().collect(),
             ))
         } else {
@@ -481,28 +1865,90 @@ impl Layout {
         }
     }
 
+    /// Resolve the keys of a query to the short names of the entities they refer to
+    ///
+    /// With [`LayoutOptions::ignore_key_case`], keys matching a known entity ignoring case
+    /// are folded onto it, unless the layout indexes the key exactly as given.
+    pub fn normalize_query(
+        &self,
+        query: HashMap<String, Vec<QueryTerms>>,
+    ) -> HashMap<String, Vec<QueryTerms>> {
+        let extra = &self.options.extra_entities;
+        let is_indexed = |key: &str| {
+            self.entities.contains_key(key)
+                || self.metadata.get().is_some_and(|md| md.contains_key(key))
+        };
+        query
+            .into_iter()
+            .filter_map(|(key, vals)| {
+                if vals.len() > 0 {
+                    // The trailing `_` must go before resolving aliases so that long names
+                    // (`subject_`) are recognized, but is kept on any entity named with one
+                    let key = match key.strip_suffix('_') {
+                        Some(stripped) if !is_known_key(&key, extra) => stripped.to_string(),
+                        _ => key,
+                    };
+                    let derefed = deref_query_alias(&key, extra)
+                        .or_else(|| {
+                            (self.options.ignore_key_case && !is_indexed(&key))
+                                .then(|| fold_key_case(&key, extra))
+                                .flatten()
+                        })
+                        .map(ToString::to_string)
+                        .unwrap_or(key);
+                    Some((derefed, vals))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Filter the layout by entity and metadata values, dataset roots, and an index
+    /// mask. Queried values that cannot be found yield an empty selection for that
+    /// entity, unless `strict` is set, in which case they are returned as errors.
+    /// Numbers matching several values always return [`QueryErr::AmbiguousQuery`].
     pub fn query(
         &self,
         query: Option<HashMap<String, Vec<QueryTerms>>>,
-        roots: Option<Vec<PathBuf>>,
-        mask: Option<&HashSet<usize>>,
+        roots: Option<Vec<(PathBuf, RootMatch)>>,
+        mask: Option<&IndexSet>,
+        strict: bool,
     ) -> Result<Layout, QueryErr> {
         let mut new_entities = EntityTable::new();
         let mut new_metadata = EntityTable::new();
-        let queried = match query {
-            Some(query) => Some({
+        let query = query
+            .map(|query| self.normalize_query(query))
+            .map(|mut query| {
+                // Every path lacks an entity found nowhere in the layout, so querying for its
+                // absence places no restriction
+                query.retain(|key, terms| {
+                    self.entities.contains_key(key)
+                        || self.metadata.get().is_some_and(|md| md.contains_key(key))
+                        || !terms.iter().any(|t| matches!(t, QueryTerms::Absent))
+                });
+                query
+            });
+        let queried = match query.filter(|query| !query.is_empty()) {
+            Some(mut query) => Some({
                 // let not_found = Vec::new();
-                let mut query = normalize_query(query);
                 let mut missing_vals = Vec::new();
                 let mut selected = Vec::new();
                 for (entity, values) in self.entities.iter() {
                     match query.remove(entity) {
                         Some(queried) => {
+                            let queried = if entity == "extension" {
+                                expand_extension_terms(queried, values)
+                            } else {
+                                queried
+                            };
                             match self.query_entity(queried, &entity, &values, &mut new_entities) {
                                 Ok(ent) => selected.push(ent),
+                                // Only missing values are left to `strict`
+                                Err(err @ QueryErr::AmbiguousQuery(..)) => return Err(err),
                                 Err(err) => {
                                     missing_vals.push(err);
-                                    selected.push(HashSet::new());
+                                    selected.push(IndexSet::default());
                                 }
                             }
                         }
@@ -511,8 +1957,9 @@ impl Layout {
                         }
                     }
                 }
-                let md_selected = if let Some(metadata) = self.metadata.get() {
-                    let mut md_selected = Vec::new();
+                // Metadata keys narrow the same selection as filename entities, so a
+                // query mixing the two returns their intersection
+                if let Some(metadata) = self.metadata.get() {
                     for (entity, values) in metadata.iter() {
                         match query.remove(entity) {
                             Some(queried) => {
@@ -522,60 +1969,63 @@ impl Layout {
                                     &values,
                                     &mut new_metadata,
                                 ) {
-                                    Ok(ent) => md_selected.push(ent),
+                                    Ok(ent) => selected.push(ent),
+                                    Err(err @ QueryErr::AmbiguousQuery(..)) => return Err(err),
                                     Err(err) => {
                                         missing_vals.push(err);
-                                    selected.push(HashSet::new());
+                                        selected.push(IndexSet::default());
                                     }
                                 }
                             }
                             None => {
-                                new_entities.insert(entity.clone(), values.clone());
+                                new_metadata.insert(entity.clone(), values.clone());
                             }
                         }
                     }
-                    Some(md_selected)
-                } else {
-                    None
-                };
+                }
 
-                if query.len() > 0 {
+                // An empty view has no entities at all, so querying it again selects
+                // nothing rather than failing
+                if query.len() > 0 && self.len() > 0 {
                     return Err(QueryErr::MissingEntity(query.keys().cloned().collect()));
                 }
 
-                if missing_vals.len() > 0 {
-                    // For now ignore value errors
-                    // return Err(QueryErr::MutliErr(missing_vals));
+                if strict && missing_vals.len() > 0 {
+                    return Err(if missing_vals.len() == 1 {
+                        missing_vals.remove(0)
+                    } else {
+                        QueryErr::MutliErr(missing_vals)
+                    });
                 }
 
-                let selected = selected
+                selected
                     .into_iter()
                     .reduce(|set, next| &set & &next)
-                    .unwrap_or_else(|| HashSet::new());
-
-                let md_selected = md_selected.map(|m| {
-                    m.into_iter()
-                        .reduce(|set, next| &set & &next)
-                        .unwrap_or_else(|| HashSet::new())
-                });
-
-                if let Some(md_selected) = md_selected {
-                    &selected | &md_selected
-                } else {
-                    selected
-                }
+                    .unwrap_or_default()
             }),
             None => {
                 new_entities = self.entities.clone();
+                new_metadata = self.metadata.get().cloned().unwrap_or_default();
                 None
             }
         };
 
         let roots = roots
-            .map(|roots| -> Result<_, QueryErr> { Ok(self.roots.glob_roots(roots)?) })
+            .map(|roots| -> Result<_, QueryErr> { Ok(self.roots.match_roots(roots)?) })
             .transpose()?;
 
-        let root_ranges = roots.as_ref().map(|roots| roots.into_set());
+        // Root ranges span every indexed path, so must be limited to the current view
+        let root_ranges = roots.as_ref().map(|roots| {
+            let ranges = roots.into_set();
+            match self.view.get() {
+                Some(view) => view
+                    .iter()
+                    .filter(|i| ranges.contains(i))
+                    .copied()
+                    .collect(),
+                None => ranges,
+            }
+        });
 
         let selected = vec![mask, root_ranges.as_ref(), queried.as_ref()]
             .into_iter()
@@ -608,22 +2058,253 @@ impl Layout {
             } else {
                 OnceCell::with_value(filtered_metadata)
             },
+            positions: match selected {
+                Some(_) => OnceCell::new(),
+                None => self.positions.clone(),
+            },
             view: match selected {
                 Some(selected) => OnceCell::with_value(selected.into_iter().sorted().collect()),
                 None => self.view.clone(),
             },
+            metadata_store: self.metadata_store.clone(),
+            invalid_paths: Arc::clone(&self.invalid_paths),
+            description_errors: Arc::clone(&self.description_errors),
+            mtimes: Arc::clone(&self.mtimes),
+            indexed: self.indexed,
+            seeds: Arc::clone(&self.seeds),
+            options: self.options.clone(),
         })
     }
 
+    /// Layout containing paths of any of the given datatypes
+    ///
+    /// Each datatype must be one defined by the BIDS spec. Datatypes absent from the
+    /// layout simply select nothing.
+    pub fn by_datatype(&self, datatypes: Vec<String>) -> Result<Layout, QueryErr> {
+        if let Some(unknown) = datatypes.iter().find(|dt| !check_datatype(dt)) {
+            return Err(QueryErr::UnknownDatatype(unknown.clone()));
+        }
+        self.query_any("datatype", datatypes)
+    }
+
+    /// Select paths with any of the given suffixes
+    ///
+    /// Suffixes must be alphanumeric. Those containing a `.` are rejected as likely
+    /// including an extension.
+    pub fn by_suffix(&self, suffixes: Vec<String>) -> Result<Layout, QueryErr> {
+        for suffix in &suffixes {
+            if suffix.contains('.') {
+                return Err(QueryErr::SuffixWithExtension(suffix.clone()));
+            } else if suffix.is_empty() || !suffix.chars().all(char::is_alphanumeric) {
+                return Err(QueryErr::InvalidSuffix(suffix.clone()));
+            }
+        }
+        self.query_any("suffix", suffixes)
+    }
+
+    /// Select paths under roots generated by the pipeline `name`, as listed in the
+    /// `GeneratedBy` or `PipelineDescription` of their dataset description
+    pub fn by_pipeline(&self, name: &str) -> Result<Layout, QueryErr> {
+        let roots = match self.roots.find_by_pipeline(&name.to_string()) {
+            Some(roots) => roots,
+            None => {
+                let found = self
+                    .roots
+                    .items()
+                    .filter_map(|(_, data)| data.get_description())
+                    .flat_map(|desc| desc.pipeline_names().cloned().collect_vec())
+                    .unique()
+                    .sorted()
+                    .collect();
+                return Err(QueryErr::UnknownPipeline(name.to_string(), found));
+            }
+        };
+        let roots = roots
+            .into_iter()
+            .map(|root| (root.clone(), RootMatch::Exact))
+            .collect();
+        self.query(None, Some(roots), None, false)
+    }
+
+    /// Select paths whose `entity` has any of `values`, selecting nothing if no path has
+    /// the entity
+    fn query_any(&self, entity: &str, values: Vec<String>) -> Result<Layout, QueryErr> {
+        if !self.entities.contains_key(entity) {
+            return self.query(None, None, Some(&IndexSet::default()), false);
+        }
+        let terms = values.into_iter().map(QueryTerms::String).collect();
+        self.query(
+            Some(HashMap::from([(entity.to_string(), terms)])),
+            None,
+            None,
+            false,
+        )
+    }
+
+    /// Layout containing paths found in either layout
+    pub fn union(&self, other: &Layout) -> Result<Layout, QueryErr> {
+        self.combine(other, SetOp::Union)
+    }
+
+    /// Layout containing paths found in both layouts
+    pub fn intersect(&self, other: &Layout) -> Result<Layout, QueryErr> {
+        self.combine(other, SetOp::Intersection)
+    }
+
+    /// Layout containing paths found in this layout but not the other
+    pub fn difference(&self, other: &Layout) -> Result<Layout, QueryErr> {
+        self.combine(other, SetOp::Difference)
+    }
+
+    /// Widen the view to every indexed path agreeing with the current view on all entities
+    /// but `entity`, given by its short or long name
+    ///
+    /// Filters applied to a layout aren't recorded, so they are inferred from the view. A
+    /// path is restored if it falls under a root in view and, for every other entity, its
+    /// value (or lack of one) is found in the view. Filters that narrow the view beyond the
+    /// values of its entities, such as metadata queries, aren't recovered and must be
+    /// applied again. If metadata was indexed, it is indexed again for the wider view.
+    pub fn without_entity(&self, entity: &str) -> Result<Layout, QueryErr> {
+        let key = deref_key_alias(entity, &self.options.extra_entities).unwrap_or(entity);
+        if !self.entities.contains_key(key) {
+            return Err(QueryErr::MissingEntity(vec![entity.to_string()]));
+        }
+        let len = self.len();
+        let constraints: HashMap<&str, (HashSet<&str>, bool)> = self
+            .entities
+            .iter()
+            .filter(|(other, _)| other.as_str() != key)
+            .map(|(other, values)| {
+                let with_entity = values
+                    .values()
+                    .fold(IndexSet::default(), |set, next| &set | next);
+                let values = values.keys().map(String::as_str).collect();
+                (other.as_str(), (values, with_entity.len() < len))
+            })
+            .collect();
+        let roots = self
+            .filtered_roots(self.roots.items())
+            .map(|(_, data)| data)
+            .collect_vec();
+        let keys: HashSet<String> = self.entity_keys().cloned().collect();
+        let view: IndexSet = self.view_indices().collect();
+        let mut entities = self.entities.clone();
+        let mut selected = view.clone();
+        for i in 0..self.paths.len() {
+            if view.contains(&i) || !roots.iter().any(|root| root.contains(&i)) {
+                continue;
+            }
+            let mut path = self.paths[i].clone();
+            path.update_parents(&keys);
+            let path_entities = path.get_entities();
+            let matches = path_entities
+                .keys()
+                .all(|other| *other == key || constraints.contains_key(other))
+                && constraints.iter().all(|(other, (values, absent))| {
+                    match path_entities.get(other) {
+                        Some(value) => values.contains(value),
+                        None => *absent,
+                    }
+                });
+            if matches {
+                for (other, value) in path_entities {
+                    entities.insert_entity(i, other, value);
+                }
+                selected.insert(i);
+            }
+        }
+        let layout = Layout {
+            paths: Arc::clone(&self.paths),
+            entities,
+            roots: self.roots.clone(),
+            heads: self.heads.clone(),
+            filetree: Arc::clone(&self.filetree),
+            depths: Arc::clone(&self.depths),
+            metadata: OnceCell::new(),
+            view: OnceCell::with_value(selected.into_iter().sorted().collect()),
+            positions: OnceCell::new(),
+            metadata_store: self.metadata_store.clone(),
+            invalid_paths: Arc::clone(&self.invalid_paths),
+            description_errors: Arc::clone(&self.description_errors),
+            mtimes: Arc::clone(&self.mtimes),
+            indexed: self.indexed,
+            seeds: Arc::clone(&self.seeds),
+            options: self.options.clone(),
+        };
+        if self.metadata.get().is_some() {
+            layout.index_metadata();
+        }
+        Ok(layout)
+    }
+
+    fn combine(&self, other: &Layout, op: SetOp) -> Result<Layout, QueryErr> {
+        if !Arc::ptr_eq(&self.paths, &other.paths) {
+            return Err(QueryErr::UnrelatedLayouts);
+        }
+        let ours: IndexSet = self.view_indices().collect();
+        let theirs: IndexSet = other.view_indices().collect();
+        let selected = match op {
+            SetOp::Union => &ours | &theirs,
+            SetOp::Intersection => &ours & &theirs,
+            SetOp::Difference => &ours - &theirs,
+        };
+        // Each table only covers its own view, so a union needs both tables
+        let mut entities = self.entities.clone();
+        let mut metadata = self.metadata.get().cloned();
+        let mut roots = self.roots.clone();
+        // When only one side has metadata indexed, the other side's values are missing
+        // from its table, so the result is indexed afresh instead
+        let mut reindex = false;
+        if let SetOp::Union = op {
+            entities.merge(&other.entities);
+            metadata = match (metadata, other.metadata.get()) {
+                (Some(mut ours), Some(theirs)) => {
+                    ours.merge(theirs);
+                    Some(ours)
+                }
+                (None, None) => None,
+                _ => {
+                    reindex = true;
+                    None
+                }
+            };
+            roots = roots.merge(&other.roots);
+        }
+        let layout = Layout {
+            paths: Arc::clone(&self.paths),
+            entities: Self::filter_entity_table(entities, &selected),
+            roots,
+            heads: self.heads.clone(),
+            filetree: Arc::clone(&self.filetree),
+            depths: Arc::clone(&self.depths),
+            metadata: match metadata {
+                Some(metadata) => {
+                    OnceCell::with_value(Self::filter_entity_table(metadata, &selected))
+                }
+                None => OnceCell::new(),
+            },
+            view: OnceCell::with_value(selected.into_iter().sorted().collect()),
+            positions: OnceCell::new(),
+            metadata_store: self.metadata_store.clone(),
+            invalid_paths: Arc::clone(&self.invalid_paths),
+            description_errors: Arc::clone(&self.description_errors),
+            mtimes: Arc::clone(&self.mtimes),
+            indexed: self.indexed,
+            seeds: Arc::clone(&self.seeds),
+            options: self.options.clone(),
+        };
+        if reindex {
+            layout.index_metadata();
+        }
+        Ok(layout)
+    }
+
     /// Filter entity table based on a mask
-    fn filter_entity_table(
-        table: EntityTable<String>,
-        mask: &HashSet<usize>,
-    ) -> EntityTable<String> {
+    fn filter_entity_table(table: EntityTable<String>, mask: &IndexSet) -> EntityTable<String> {
         table
             .into_iter()
             .filter_map(|(entity, values)| {
-                let filtered_values: HashMap<_, _> = values
+                let filtered_values: ValueMap<_> = values
                     .into_iter()
                     .filter_map(|(value, insts)| {
                         let new = mask & &insts;
@@ -644,11 +2325,36 @@ impl Layout {
             .into()
     }
 
-    pub fn index_metadata(&mut self) {
+    /// Discard any existing metadata index and build it again from the sidecars
+    pub fn reindex_metadata(&mut self) {
+        self.metadata = OnceCell::new();
+        self.metadata_store = OnceCell::new();
+        self.index_metadata();
+    }
+
+    /// Metadata files that could not be fully read while indexing. Returns `None` if
+    /// metadata has not been indexed.
+    pub fn metadata_errors(&self) -> Option<&[(PathBuf, String)]> {
+        self.metadata_store.get().map(|store| store.errors())
+    }
+
+    /// Build the metadata index, if not already built
+    ///
+    /// Only shared access is needed, so the layout may be used from other threads while
+    /// indexing. Concurrent calls wait for the first to finish rather than indexing again.
+    pub fn index_metadata(&self) {
         self.metadata.get_or_init(|| {
             let md_builder =
                 MetadataIndexBuilder::build(self.depths.as_ref(), self.filetree.as_ref(), self);
-            md_builder.metadata
+            let _ = self.metadata_store.set(Arc::new(md_builder.store));
+            // Sidecars are read across the whole dataset, so values are narrowed back to
+            // the view, as done by `query`
+            match self.view.get() {
+                Some(view) => {
+                    Self::filter_entity_table(md_builder.metadata, &view.iter().copied().collect())
+                }
+                None => md_builder.metadata,
+            }
         });
     }
 
@@ -662,28 +2368,273 @@ impl Layout {
             depths: Arc::new(self.depths.as_ref().clone()),
             metadata: self.metadata.clone(),
             view: self.view.clone(),
+            positions: self.positions.clone(),
+            metadata_store: self.metadata_store.clone(),
+            invalid_paths: Arc::new(self.invalid_paths.as_ref().clone()),
+            description_errors: Arc::new(self.description_errors.as_ref().clone()),
+            mtimes: Arc::new(self.mtimes.as_ref().clone()),
+            indexed: self.indexed,
+            seeds: Arc::new(self.seeds.as_ref().clone()),
+            options: self.options.clone(),
         }
     }
 }
 
 impl PartialEq for Layout {
+    /// Layouts are equal if the paths in their views are, the same comparison on which
+    /// [`Layout::content_hash`] is based
     fn eq(&self, other: &Self) -> bool {
-        let same_view = || self.get_view() == other.get_view();
-        // If both have the same path pointer, check is really quick
-        if Arc::ptr_eq(&other.paths, &self.paths) {
-            if same_view() {
-                true
-            } else {
-                false
-            }
+        // If both share their paths and view, check is really quick
+        if Arc::ptr_eq(&other.paths, &self.paths) && self.view_indices().eq(other.view_indices()) {
+            return true;
+        }
         // Otherwise need exhaustive search
         // Note that root equality is implied by path equality (equal paths must have the same root)
-        } else if same_view() {
-            let ourpaths: HashSet<_> = self.paths.iter().cloned().collect();
-            let theirpaths: HashSet<_> = other.paths.iter().cloned().collect();
-            ourpaths == theirpaths
-        } else {
-            false
+        self.len() == other.len() && self.sorted_view_paths().eq(other.sorted_view_paths())
+    }
+}
+
+/// Number of entities listed in the summary, ordered by how many values they have
+const SUMMARY_ENTITIES: usize = 5;
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let roots = self.get_roots();
+        writeln!(f, "Layout (len = {}, roots = {})", self.len(), roots.len())?;
+        for root in roots.iter().sorted() {
+            writeln!(f, "    {}", root.to_string_lossy())?;
+        }
+        let entities = self
+            .entity_fullkey_vals()
+            .into_iter()
+            .map(|(key, vals)| (key, vals.len()))
+            .sorted_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)))
+            .collect_vec();
+        if !entities.is_empty() {
+            write!(
+                f,
+                "Entities: {}",
+                entities
+                    .iter()
+                    .take(SUMMARY_ENTITIES)
+                    .map(|(key, count)| format!("{} ({})", key, count))
+                    .join(", ")
+            )?;
+            if entities.len() > SUMMARY_ENTITIES {
+                write!(f, ", ...")?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "{}", self.fmt_elided_list(10))
+    }
+}
+
+impl fmt::Debug for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDataset;
+
+    #[test]
+    fn unfiltered_layout_does_not_materialize_view() {
+        let dataset = TempDataset::new(
+            "view",
+            &[
+                ("sub-01/anat/sub-01_T1w.nii.gz", ""),
+                ("sub-01/anat/sub-01_T2w.nii.gz", ""),
+            ],
+        );
+        let layout = dataset.create(LayoutOptions::default(), None);
+
+        assert_eq!(layout.get_paths(false).count(), 2);
+        assert!(layout.get_path(1).is_some());
+        assert!(layout.contains(&dataset.path("sub-01/anat/sub-01_T2w.nii.gz")));
+        assert!(layout == layout.clone());
+        assert!(layout.view.get().is_none());
+    }
+
+    #[test]
+    fn shared_ignore_matches_default() {
+        let dataset = TempDataset::new(
+            "ignore",
+            &[
+                ("dataset_description.json", "{}"),
+                (".bidsignore", "extra/\n"),
+                ("extra/sub-01_T1w.nii.gz", ""),
+                ("sub-01/anat/sub-01_T1w.nii.gz", ""),
+            ],
+        );
+        let options = LayoutOptions::default();
+        let ignore = Layout::root_ignore([&dataset.root].into_iter(), &options).unwrap();
+        let default = dataset.create(options.clone(), None);
+
+        assert_eq!(default.len(), 2);
+        for _ in 0..2 {
+            assert!(dataset.create(options.clone(), Some(&ignore)) == default);
+        }
+    }
+
+    #[test]
+    fn shared_ignore_is_kept_in_options() {
+        let dataset = TempDataset::new(
+            "ignoreoptions",
+            &[
+                ("dataset_description.json", "{}"),
+                ("extra/sub-01_T1w.nii.gz", ""),
+                ("sub-01/anat/sub-01_T1w.nii.gz", ""),
+            ],
+        );
+        let options = LayoutOptions::default();
+        let mut ignore = Layout::root_ignore([&dataset.root].into_iter(), &options).unwrap();
+        ignore.names.insert(OsString::from("extra"));
+        ignore.set_include(&["**/*.nii.gz".to_string()]).unwrap();
+        let layout = dataset.create(options, Some(&ignore));
+
+        assert_eq!(layout.len(), 1);
+        assert!(layout.update().unwrap() == layout);
+        let options = layout.options();
+        assert!(options.ignore_dirs.as_ref().unwrap().contains("extra"));
+        assert_eq!(options.include, Some(vec!["**/*.nii.gz".to_string()]));
+    }
+
+    #[test]
+    fn datatypes_by_lists_datatypes_present() {
+        let dataset = TempDataset::new(
+            "datatypes",
+            &[
+                ("dataset_description.json", "{}"),
+                ("sub-01/anat/sub-01_T1w.nii.gz", ""),
+                ("sub-01/dwi/sub-01_dwi.nii.gz", ""),
+                ("sub-02/anat/sub-02_T1w.nii.gz", ""),
+            ],
+        );
+        let layout = dataset.create(LayoutOptions::default(), None);
+
+        let datatypes = layout.datatypes_by("subject").unwrap();
+        let (anat, dwi) = (String::from("anat"), String::from("dwi"));
+        assert_eq!(datatypes[&String::from("01")], HashSet::from([&anat, &dwi]));
+        assert_eq!(datatypes[&String::from("02")], HashSet::from([&anat]));
+    }
+
+    #[test]
+    fn display_summarizes_layout() {
+        let dataset = TempDataset::new(
+            "display",
+            &[
+                ("dataset_description.json", "{}"),
+                ("sub-01/anat/sub-01_T1w.nii.gz", ""),
+            ],
+        );
+        let layout = dataset.create(LayoutOptions::default(), None);
+
+        let summary = layout.to_string();
+        assert!(summary.starts_with("Layout (len = 2, roots = 1)"));
+        assert!(summary.contains(&*dataset.root.to_string_lossy()));
+        assert!(summary.contains("subject (1)"));
+    }
+
+    #[test]
+    fn borrowed_iterator_matches_cloned_paths() {
+        // `site` is only confirmed as an entity after the first path is parsed, leaving its
+        // parents uncertain until iteration
+        let files = [
+            "site-x/sub-03/anat/sub-03_T1w.nii.gz",
+            "sub-04/anat/sub-04_site-y_T1w.nii.gz",
+            "dataset_description.json",
+            "sub-01/ses-1/anat/sub-01_ses-1_T1w.nii.gz",
+            "sub-02/anat/sub-02_acq-x_T1w.nii.gz",
+            "sub-02/run-1/notes.txt",
+            "task-rest_bold.json",
+        ];
+        let dataset = TempDataset::new("iter", &files.map(|file| (file, "")));
+        let layout = Layout::from_paths(
+            files.map(|file| dataset.path(file)),
+            LayoutOptions::default(),
+        )
+        .unwrap();
+
+        let subjects = vec![QueryTerms::from("02"), QueryTerms::from("03")];
+        let query = HashMap::from([(String::from("subject"), subjects)]);
+        let filtered = layout.query(Some(query), None, None, false).unwrap();
+        for layout in [&layout, &filtered] {
+            let cloned = layout.get_paths(false).collect_vec();
+            let borrowed = layout.iter_entities().collect_vec();
+            assert_eq!(cloned.len(), borrowed.len());
+            for (path, (path_str, entities)) in cloned.iter().zip(borrowed) {
+                assert_eq!(path.as_str(), path_str);
+                assert_eq!(path.get_entities(), entities);
+            }
+        }
+    }
+
+    #[test]
+    fn queries_select_paths_with_matching_entities() {
+        // Runs with and without the `fxhash` feature, so both hashers must give these
+        // selections
+        let mut records = vec![(PathBuf::from("/ds/dataset_description.json"), None)];
+        let mut entities = Vec::new();
+        for sub in 0..20 {
+            for run in 1..=4 {
+                for echo in 1..=3 {
+                    let path = format!(
+                        "/ds/sub-{sub:02}/func/sub-{sub:02}_task-rest_run-{run}_echo-{echo}_bold.nii.gz"
+                    );
+                    records.push((PathBuf::from(&path), None));
+                    entities.push((path, format!("{sub:02}"), run.to_string()));
+                }
+            }
         }
+        let layout = Layout::from_records(records, LayoutOptions::default()).unwrap();
+        let select = |layout: &Layout, subjects: &[&str], runs: &[&str]| {
+            let terms = |vals: &[&str]| {
+                vals.iter()
+                    .map(|v| QueryTerms::String(v.to_string()))
+                    .collect_vec()
+            };
+            let query = HashMap::from([
+                (String::from("subject"), terms(subjects)),
+                (String::from("run"), terms(runs)),
+            ]);
+            let selected = layout.query(Some(query), None, None, false).unwrap();
+            selected
+                .get_paths(true)
+                .map(|path| path.as_str().to_string())
+                .collect_vec()
+        };
+        let expected = |subjects: &[&str], runs: &[&str]| {
+            entities
+                .iter()
+                .filter(|(_, sub, run)| subjects.contains(&&**sub) && runs.contains(&&**run))
+                .map(|(path, _, _)| path.clone())
+                .sorted()
+                .collect_vec()
+        };
+
+        for (subjects, runs) in [
+            (&["01"][..], &["1"][..]),
+            (&["00", "07", "19"], &["2", "4"]),
+            (&["03", "12"], &["1", "2", "3", "4"]),
+        ] {
+            assert_eq!(select(&layout, subjects, runs), expected(subjects, runs));
+        }
+        let view = layout
+            .query(
+                Some(HashMap::from([(
+                    String::from("echo"),
+                    vec![QueryTerms::from("2")],
+                )])),
+                None,
+                None,
+                false,
+            )
+            .unwrap();
+        let in_view = select(&view, &["05", "06"], &["3"]);
+        assert_eq!(in_view.len(), 2);
+        assert!(in_view.iter().all(|path| path.contains("_echo-2_")));
     }
 }
@@ -1,21 +1,87 @@
 use std::{
     collections::{HashMap, HashSet},
     fs::File,
-    io::Read,
+    io::{self, Read},
     ops::Range,
-    path::Path,
+    path::{Path, PathBuf},
     hash::Hash,
 };
 
-use itertools::chain;
+use itertools::{chain, Itertools};
 use serde::{Deserialize, Serialize};
 
-use crate::{errors::MetadataReadErr, standards::get_key_alias};
+use crate::{
+    errors::{BuildErr, MetadataReadErr},
+    standards::{
+        deref_key_alias, entity_position, get_key_alias, ExtraEntities, NO_EXTRA_ENTITIES,
+    },
+};
 
 use super::{builders::primitives::KeyVal, utfpath::UtfPath};
 
 pub type MetadataReadResult = Result<HashMap<String, String>, MetadataReadErr>;
 
+/// Final segment of a possibly compound extension, e.g. `.gz` for `.nii.gz`
+pub fn last_extension(extension: &str) -> &str {
+    match extension.rfind('.') {
+        Some(i) => &extension[i..],
+        None => extension,
+    }
+}
+
+/// Data row of a tab-separated file keyed by the header row, along with its line number
+pub type TsvRow = (usize, Result<HashMap<String, String>, MetadataReadErr>);
+
+/// Read a tab-separated file into one map per data row, keyed by the header row
+///
+/// Cells are kept as written, so missing values appear as the bids `n/a` placeholder.
+/// Blank lines are skipped.
+pub fn read_tsv(path: &Path) -> Result<Vec<HashMap<String, String>>, MetadataReadErr> {
+    let (_, rows) = read_tsv_rows(path)?;
+    rows.into_iter().map(|(_, row)| row).collect()
+}
+
+/// Read a tab-separated file like [`read_tsv`], along with its header
+///
+/// Rows with a different number of columns than the header are errors, which don't stop
+/// the other rows from being read.
+pub fn read_tsv_rows(path: &Path) -> io::Result<(Vec<String>, Vec<TsvRow>)> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    let mut lines = contents
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty());
+    let header = match lines.next() {
+        Some((_, header)) => header.split('\t').map(str::to_string).collect_vec(),
+        None => return Ok((Vec::new(), Vec::new())),
+    };
+    let rows = lines
+        .map(|(i, line)| {
+            let row = line.split('\t').collect_vec();
+            if row.len() != header.len() {
+                return (
+                    i + 1,
+                    Err(MetadataReadErr::Tsv(
+                        path.to_string_lossy().to_string(),
+                        i + 1,
+                        row.len(),
+                        header.len(),
+                    )),
+                );
+            }
+            let row = header
+                .iter()
+                .zip(row)
+                .map(|(key, val)| (key.clone(), val.to_string()))
+                .collect();
+            (i + 1, Ok(row))
+        })
+        .collect();
+    Ok((header, rows))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UnknownDatatype {
     pub value: Range<usize>,
@@ -114,11 +180,48 @@ impl BidsPath {
         Some(())
     }
 
-    pub fn get_full_entities(&self) -> HashMap<&str, &str> {
+    /// Uncertain parents that [`BidsPath::update_parents`] would confirm, in path order
+    fn confirmed_parents(&self, parents: &HashSet<String>) -> &[KeyVal] {
+        let uncertain = self.uncertain_parents.as_deref().unwrap_or_default();
+        match uncertain
+            .iter()
+            .rposition(|parent| parents.contains(parent.get_key(self.as_str())))
+        {
+            Some(last) => &uncertain[..=last],
+            None => &[],
+        }
+    }
+
+    /// Entities as given by [`BidsPath::get_entities`] once [`BidsPath::update_parents`]
+    /// has been called with `parents`, without cloning or modifying the path
+    pub fn get_entities_with_parents(&self, parents: &HashSet<String>) -> HashMap<&str, &str> {
+        let path = self.as_str();
+        let mut entities = HashMap::new();
+        for parent in chain![
+            &self.parents,
+            self.confirmed_parents(parents).iter().rev(),
+            &self.entities
+        ] {
+            let (key, val) = parent.get(path);
+            entities.insert(key, val);
+        }
+        for (key, range) in [
+            ("datatype", &self.datatype),
+            ("suffix", &self.suffix),
+            ("extension", &self.extension),
+        ] {
+            if let Some(range) = range {
+                entities.insert(key, &path[range.clone()]);
+            }
+        }
+        entities
+    }
+
+    pub fn get_full_entities<'a>(&'a self, extra: &'a ExtraEntities) -> HashMap<&'a str, &'a str> {
         let mut entities = HashMap::new();
         for parent in chain![&self.parents, &self.entities] {
             let (key, val) = parent.get(&self.as_str());
-            entities.insert(get_key_alias(key), val);
+            entities.insert(get_key_alias(key, extra), val);
         }
         if let Some(datatype) = &self.datatype {
             entities.insert("datatype", &self.as_str()[datatype.clone()]);
@@ -132,6 +235,33 @@ impl BidsPath {
         entities
     }
 
+    /// Entities in the order they appear in the path, from parent directories through to
+    /// the extension. Keys use their full names, as in [`BidsPath::get_full_entities`].
+    /// Entities repeated between directories and the filename appear once per occurrence.
+    pub fn entities_ordered<'a>(&'a self, extra: &'a ExtraEntities) -> Vec<(&'a str, &'a str)> {
+        let path = self.as_str();
+        let mut entities = chain![&self.parents, &self.entities]
+            .map(|entity| {
+                let (key, val) = entity.get(path);
+                (entity.start(), get_key_alias(key, extra), val)
+            })
+            .collect_vec();
+        for (key, range) in [
+            ("datatype", &self.datatype),
+            ("suffix", &self.suffix),
+            ("extension", &self.extension),
+        ] {
+            if let Some(range) = range {
+                entities.push((range.start, key, &path[range.clone()]));
+            }
+        }
+        entities.sort_by_key(|(start, ..)| *start);
+        entities
+            .into_iter()
+            .map(|(_, key, val)| (key, val))
+            .collect()
+    }
+
     pub fn get_entities(&self) -> HashMap<&str, &str> {
         let mut entities = HashMap::new();
         for parent in chain![&self.parents, &self.entities] {
@@ -150,6 +280,25 @@ impl BidsPath {
         entities
     }
 
+    /// Entities declared in the filename, excluding parent directories and the datatype
+    pub fn get_filename_entities<'a>(
+        &'a self,
+        extra: &'a ExtraEntities,
+    ) -> HashMap<&'a str, &'a str> {
+        let mut entities = HashMap::new();
+        for entity in &self.entities {
+            let (key, val) = entity.get(self.as_str());
+            entities.insert(get_key_alias(key, extra), val);
+        }
+        if let Some(suffix) = &self.suffix {
+            entities.insert("suffix", &self.as_str()[suffix.clone()]);
+        }
+        if let Some(extension) = &self.extension {
+            entities.insert("extension", &self.as_str()[extension.clone()]);
+        }
+        entities
+    }
+
     pub fn get_uncertain_entities(&self) -> Option<HashMap<&str, &str>> {
         if let Some(uncertain_parents) = self.uncertain_parents.as_ref() {
             let mut entities = HashMap::new();
@@ -167,6 +316,15 @@ impl BidsPath {
         &self.as_str()[..self.root]
     }
 
+    /// Path relative to its dataset root, or the full path if no root was identified
+    pub fn relative_path(&self) -> &str {
+        if self.root == 0 {
+            self.as_str()
+        } else {
+            self.as_str()[self.root..].trim_start_matches(std::path::MAIN_SEPARATOR)
+        }
+    }
+
     pub fn get_head(&self) -> &str {
         &self.as_str()[..self.head]
     }
@@ -205,6 +363,13 @@ impl BidsPath {
         })
     }
 
+    /// Returns the full extension (e.g. `.nii.gz`) along with its final segment (`.gz`).
+    /// For simple extensions like `.json`, both are the same.
+    pub fn extensions(&self) -> Option<(&str, &str)> {
+        let full = &self[self.extension.as_ref()?];
+        Some((full, last_extension(full)))
+    }
+
     pub fn read_as_metadata(&self) -> Result<HashMap<String, serde_json::Value>, MetadataReadErr> {
         let mut file = File::open(&self.as_path())?;
         let mut contents = String::new();
@@ -214,6 +379,119 @@ impl BidsPath {
         Ok(parsed)
     }
 
+    /// Read the path as a tab-separated file, such as an `_events.tsv`
+    pub fn read_tsv(&self) -> Result<Vec<HashMap<String, String>>, MetadataReadErr> {
+        read_tsv(self.as_path())
+    }
+
+    /// Build a bids path under `root` from entity-value pairs
+    ///
+    /// Keys may be given as short or long entity names. Entities are ordered according
+    /// to the bids spec, with unknown entities appended afterwards in the order given.
+    /// `subject` and `suffix` are required, and `session` and `datatype`, if present,
+    /// are used as parent directories. Unknown entities are rejected unless
+    /// `allow_unknown` is set.
+    ///
+    /// Values, including the suffix and datatype, must be alphanumeric, so that they are
+    /// parsed back from the path as given. Extensions may also contain `.`.
+    pub fn build(
+        entities: &[(&str, &str)],
+        root: &Path,
+        allow_unknown: bool,
+    ) -> Result<PathBuf, BuildErr> {
+        let mut known = Vec::new();
+        let mut unknown = Vec::new();
+        let mut datatype = None;
+        let mut suffix = None;
+        let mut extension = None;
+        let is_label = |value: &str| !value.is_empty() && value.chars().all(char::is_alphanumeric);
+        for &(key, value) in entities {
+            let key = deref_key_alias(key, &NO_EXTRA_ENTITIES).unwrap_or(key);
+            let valid = match key {
+                "extension" => value
+                    .strip_prefix('.')
+                    .unwrap_or(value)
+                    .split('.')
+                    .all(is_label),
+                _ => is_label(value),
+            };
+            if !valid {
+                return Err(BuildErr::InvalidValue(key.to_string(), value.to_string()));
+            }
+            match key {
+                "datatype" => datatype = Some(value),
+                "suffix" => suffix = Some(value),
+                "extension" => extension = Some(value),
+                _ => match entity_position(key) {
+                    Some(pos) => known.push((pos, key, value)),
+                    None if allow_unknown => unknown.push((key, value)),
+                    None => return Err(BuildErr::UnknownEntity(key.to_string())),
+                },
+            }
+        }
+        let suffix = suffix.ok_or(BuildErr::MissingEntity("suffix"))?;
+        let subject = known
+            .iter()
+            .find(|(_, key, _)| *key == "sub")
+            .map(|(_, _, value)| *value)
+            .ok_or(BuildErr::MissingEntity("subject"))?;
+
+        let mut path = root.join(format!("sub-{}", subject));
+        if let Some((_, _, session)) = known.iter().find(|(_, key, _)| *key == "ses") {
+            path.push(format!("ses-{}", session));
+        }
+        if let Some(datatype) = datatype {
+            path.push(datatype);
+        }
+        let mut name = known
+            .into_iter()
+            .sorted_by_key(|(pos, _, _)| *pos)
+            .map(|(_, key, value)| (key, value))
+            .chain(unknown)
+            .map(|(key, value)| format!("{}-{}_", key, value))
+            .collect::<String>();
+        name.push_str(suffix);
+        if let Some(extension) = extension {
+            if !extension.starts_with('.') {
+                name.push('.');
+            }
+            name.push_str(extension);
+        }
+        path.push(name);
+        Ok(path)
+    }
+
+    /// Check that filename entities follow the canonical bids order
+    ///
+    /// Entities not defined by the spec, along with other unrecognized parts of the
+    /// filename, are permitted only after all recognized entities
+    pub fn check_entity_order(&self) -> bool {
+        let filename = self
+            .as_str()
+            .rfind(std::path::is_separator)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let entities = self
+            .entities
+            .iter()
+            .map(|e| (e.start(), entity_position(e.get_key(self.as_str()))));
+        let parts = self
+            .parts
+            .iter()
+            .flatten()
+            .filter(|part| part.start >= filename)
+            .map(|part| (part.start, None));
+        let mut last = 0;
+        for (_, pos) in entities.chain(parts).sorted() {
+            let pos = pos.unwrap_or(usize::MAX);
+            if pos < last {
+                return false;
+            }
+            last = pos;
+        }
+        true
+    }
+
     /// Create a fresh BidsPath without any entity annotations (just depth and root)
     pub fn clear(self) -> Self {
         Self::new(self.path.clone(), self.root, self.depth)
@@ -247,4 +525,125 @@ impl PartialEq for BidsPath {
     }
 }
 
-impl Eq for BidsPath {}
\ No newline at end of file
+impl Eq for BidsPath {}
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::BidsPath;
+    use crate::{
+        errors::BuildErr, layout::builders::bidspath_builder::BidsPathBuilder,
+        standards::NO_EXTRA_ENTITIES,
+    };
+
+    fn parse(path: &str) -> BidsPath {
+        BidsPathBuilder::new(PathBuf::from(path), 0)
+            .unwrap()
+            .spec_parse(&NO_EXTRA_ENTITIES, false)
+            .unwrap()
+    }
+
+    #[test]
+    fn extensions_split_compound_extensions() {
+        let path = parse("sub-01/anat/sub-01_T1w.nii.gz");
+        assert_eq!(path.extensions(), Some((".nii.gz", ".gz")));
+        let path = parse("sub-01/func/sub-01_task-rest_events.tsv.gz");
+        assert_eq!(path.extensions(), Some((".tsv.gz", ".gz")));
+        let path = parse("sub-01/anat/sub-01_T1w.json");
+        assert_eq!(path.extensions(), Some((".json", ".json")));
+        assert_eq!(parse("sub-01/anat/sub-01_T1w").extensions(), None);
+    }
+
+    #[test]
+    fn entities_ordered_follows_path() {
+        let path = parse("sub-01/ses-1/func/sub-01_ses-1_task-rest_run-1_bold.nii.gz");
+        assert_eq!(
+            path.entities_ordered(&NO_EXTRA_ENTITIES),
+            vec![
+                ("subject", "01"),
+                ("session", "1"),
+                ("datatype", "func"),
+                ("subject", "01"),
+                ("session", "1"),
+                ("task", "rest"),
+                ("run", "1"),
+                ("suffix", "bold"),
+                ("extension", ".nii.gz"),
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_path_without_root_is_full_path() {
+        let path = parse("sub-01/anat/sub-01_T1w.nii.gz");
+        assert_eq!(path.relative_path(), "sub-01/anat/sub-01_T1w.nii.gz");
+    }
+
+    #[test]
+    fn entity_order_is_checked() {
+        assert!(parse("sub-01/func/sub-01_ses-1_task-rest_run-1_bold.nii.gz").check_entity_order());
+        assert!(parse("sub-01/anat/sub-01_T1w.nii.gz").check_entity_order());
+        assert!(!parse("sub-01/func/sub-01_run-1_task-rest_bold.nii.gz").check_entity_order());
+        assert!(!parse("sub-01/func/task-rest_sub-01_bold.nii.gz").check_entity_order());
+    }
+
+    #[test]
+    fn entity_order_permits_trailing_unknown_entities() {
+        assert!(parse("sub-01/anat/sub-01_desc-preproc_foo-bar_T1w.nii.gz").check_entity_order());
+        assert!(!parse("sub-01/anat/sub-01_foo-bar_desc-preproc_T1w.nii.gz").check_entity_order());
+        // Unknown entities in parent directories are not part of the filename
+        assert!(parse("foo-bar/sub-01/anat/sub-01_desc-preproc_T1w.nii.gz").check_entity_order());
+    }
+
+    #[test]
+    fn build_orders_entities_canonically() {
+        let path = BidsPath::build(
+            &[
+                ("extension", ".nii.gz"),
+                ("suffix", "bold"),
+                ("task", "rest"),
+                ("datatype", "func"),
+                ("session", "1"),
+                ("sub", "01"),
+            ],
+            Path::new("root"),
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            path,
+            PathBuf::from("root/sub-01/ses-1/func/sub-01_ses-1_task-rest_bold.nii.gz")
+        );
+    }
+
+    #[test]
+    fn build_appends_unknown_entities_in_order() {
+        let entities = [
+            ("subject", "01"),
+            ("foo", "a"),
+            ("desc", "preproc"),
+            ("bar", "b"),
+            ("suffix", "T1w"),
+        ];
+        assert!(matches!(
+            BidsPath::build(&entities, Path::new(""), false),
+            Err(BuildErr::UnknownEntity(e)) if e == "foo"
+        ));
+        assert_eq!(
+            BidsPath::build(&entities, Path::new(""), true).unwrap(),
+            PathBuf::from("sub-01/sub-01_desc-preproc_foo-a_bar-b_T1w")
+        );
+    }
+
+    #[test]
+    fn build_requires_subject_and_suffix() {
+        assert!(matches!(
+            BidsPath::build(&[("sub", "01")], Path::new(""), false),
+            Err(BuildErr::MissingEntity("suffix"))
+        ));
+        assert!(matches!(
+            BidsPath::build(&[("suffix", "T1w")], Path::new(""), false),
+            Err(BuildErr::MissingEntity("subject"))
+        ));
+    }
+}
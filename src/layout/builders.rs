@@ -4,4 +4,4 @@ pub mod metadata_builder;
 mod parsers;
 pub(super) mod primitives;
 
-pub use layout_builder::{LayoutBuilder, RootLabel};
\ No newline at end of file
+pub use layout_builder::{LayoutBuilder, RootLabel, RootSeed};
\ No newline at end of file
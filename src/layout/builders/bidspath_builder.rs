@@ -111,6 +111,19 @@ pub fn get_components(path: &Path) -> Vec<Range<usize>> {
     components
 }
 
+/// Check that `offset` falls between two components of `path`, as root offsets must
+fn is_component_boundary(path: &str, offset: usize) -> bool {
+    offset == 0
+        || offset == path.len()
+        || path
+            .get(..offset)
+            .zip(path.get(offset..))
+            .is_some_and(|(head, tail)| {
+                head.ends_with(std::path::MAIN_SEPARATOR)
+                    || tail.starts_with(std::path::MAIN_SEPARATOR)
+            })
+}
+
 fn consume_values<'a>(data: &mut Vec<Primitive>, keystart: usize, keyend: usize) -> Elements {
     let mut end = keyend + 1;
     while let Some(last) = data.pop() {
@@ -207,12 +220,27 @@ pub struct BidsPathBuilder {
     pub components: Vec<ComponentType>,
     pub depth: usize,
     pub root: usize,
+    /// Number of leading components making up the root, which are never parsed as
+    /// datatypes or entities
+    pub root_components: usize,
 }
 
 impl BidsPathBuilder {
     pub fn new(path: PathBuf, root: usize) -> Result<Self, BidsPathErr> {
         let path = UtfPath::try_from(path)?;
+        debug_assert!(
+            is_component_boundary(path.as_str(), root),
+            "Root offset {} splits a component of {}",
+            root,
+            path.as_str()
+        );
         let components = get_components(path.as_path());
+        // The filename is always parsed, even for a path given as its own root
+        let root_components = components
+            .iter()
+            .take_while(|comp| comp.end <= root)
+            .count()
+            .min(components.len().saturating_sub(1));
         let mut comps = Vec::new();
         for component in components {
             let elements = parse_path_segment(component, path.as_str());
@@ -224,6 +252,7 @@ impl BidsPathBuilder {
             depth: comps.len(),
             components: comps,
             root,
+            root_components,
         })
     }
 
@@ -265,6 +294,7 @@ impl From<BidsPath> for BidsPathBuilder {
             components: Vec::new(),
             depth: value.depth,
             root: value.root,
+            root_components: 0,
         }
     }
 }
@@ -1,26 +1,28 @@
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
     ffi::OsString,
-    mem,
+    fs, mem,
     ops::Range,
     path::{Components, Path, PathBuf},
     sync::Arc,
+    time::SystemTime,
 };
 
 use itertools::Itertools;
 use once_cell::sync::OnceCell;
+use path_clean::clean;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     errors::BidsPathErr,
     layout::{
         bidspath::{BidsPath, UnknownDatatypeTypes},
-        entity_table::EntityTable,
+        entity_table::{EntityTable, IndexSet},
         roots::{DatasetRoot, RootCategory},
-        Layout,
+        Layout, LayoutOptions,
     },
-    standards::BIDS_ENTITIES,
-    utils::is_subpath_of,
+    standards::check_entity,
+    utils::{absolute_clean, is_subpath_of},
 };
 
 use super::bidspath_builder::BidsPathBuilder;
@@ -28,7 +30,7 @@ use super::bidspath_builder::BidsPathBuilder;
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct FileTree {
     nodes: HashMap<OsString, Box<FileTree>>,
-    files: HashSet<usize>,
+    files: IndexSet,
 }
 
 impl FileTree {
@@ -47,14 +49,86 @@ impl FileTree {
         self.find_impl(path.components())
     }
 
+    /// Files directly within this directory
+    pub fn files(&self) -> &IndexSet {
+        &self.files
+    }
+
     #[inline]
-    pub fn get_subfiles(&self, path: &Path) -> Option<HashSet<usize>> {
+    pub fn get_subfiles(&self, path: &Path) -> Option<IndexSet> {
         let tree = self.find(path)?;
-        let mut result = HashSet::new();
+        let mut result = IndexSet::default();
         tree.get_subfiles_impl(&mut result);
         Some(result)
     }
 
+    /// Render the tree as indented lines, listing files by the name returned by `name`
+    ///
+    /// Files for which `name` returns `None` are left out, along with any directories left
+    /// empty. Directories containing nothing but a single subdirectory are collapsed into
+    /// the first line, so the tree begins where paths diverge. Directories more than
+    /// `max_depth` levels below are listed without their contents.
+    pub fn render<F: Fn(usize) -> Option<String>>(
+        &self,
+        name: F,
+        max_depth: Option<usize>,
+    ) -> String {
+        let mut base = PathBuf::new();
+        let mut tree = self;
+        while tree.files.iter().all(|i| name(*i).is_none()) {
+            let mut children = tree.nodes.iter().filter(|(_, sub)| sub.has_files(&name));
+            match (children.next(), children.next()) {
+                (Some((dir, sub)), None) => {
+                    base.push(dir);
+                    tree = sub;
+                }
+                _ => break,
+            }
+        }
+        let mut lines = vec![format!(
+            "{}{}",
+            base.to_string_lossy(),
+            std::path::MAIN_SEPARATOR
+        )];
+        tree.render_impl(&name, 1, max_depth, &mut lines);
+        lines.join("\n")
+    }
+
+    fn has_files<F: Fn(usize) -> Option<String>>(&self, name: &F) -> bool {
+        self.files.iter().any(|i| name(*i).is_some())
+            || self.nodes.values().any(|sub| sub.has_files(name))
+    }
+
+    fn render_impl<F: Fn(usize) -> Option<String>>(
+        &self,
+        name: &F,
+        depth: usize,
+        max_depth: Option<usize>,
+        lines: &mut Vec<String>,
+    ) {
+        if max_depth.is_some_and(|max| depth > max) {
+            return;
+        }
+        let indent = "    ".repeat(depth);
+        let dirs = self
+            .nodes
+            .iter()
+            .filter(|(_, sub)| sub.has_files(name))
+            .sorted_by(|a, b| a.0.cmp(b.0));
+        for (dir, sub) in dirs {
+            lines.push(format!(
+                "{}{}{}",
+                indent,
+                dir.to_string_lossy(),
+                std::path::MAIN_SEPARATOR
+            ));
+            sub.render_impl(name, depth + 1, max_depth, lines);
+        }
+        for file in self.files.iter().filter_map(|i| name(*i)).sorted() {
+            lines.push(format!("{}{}", indent, file));
+        }
+    }
+
     fn find_impl(&self, mut path: Components) -> Option<&FileTree> {
         if let Some(next) = path.next() {
             let next = next.as_os_str();
@@ -68,7 +142,7 @@ impl FileTree {
         }
     }
 
-    fn get_subfiles_impl(&self, result: &mut HashSet<usize>) {
+    fn get_subfiles_impl(&self, result: &mut IndexSet) {
         for tree in self.nodes.values() {
             tree.get_subfiles_impl(result);
         }
@@ -76,13 +150,21 @@ impl FileTree {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum RootLabel {
     Raw,
     DerivativeUnlabelled,
     DerivativeLabelled(String),
 }
 
+/// A root as originally registered, along with the index of its first path
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RootSeed {
+    pub path: PathBuf,
+    pub label: RootLabel,
+    pub start: usize,
+}
+
 #[derive(Debug, Clone)]
 enum PartialRoot {
     Raw(PathBuf, Range<usize>),
@@ -96,15 +178,47 @@ pub struct LayoutBuilder {
     roots: HashMap<PathBuf, DatasetRoot>,
     derivative_roots: HashMap<PathBuf, DatasetRoot>,
     labelled_roots: HashMap<String, HashMap<PathBuf, DatasetRoot>>,
-    pub(super) heads: HashMap<String, HashSet<usize>>,
-    pub(super) depths: BTreeMap<usize, HashSet<usize>>,
+    pub(super) heads: HashMap<String, IndexSet>,
+    pub(super) depths: BTreeMap<usize, IndexSet>,
     pub(super) filetree: FileTree,
     current_root: Option<PartialRoot>,
     unknown_entities: EntityTable<String>,
-    unknown_datatypes: HashSet<usize>,
+    unknown_datatypes: IndexSet,
+    invalid_paths: Vec<BidsPathErr>,
+    description_errors: Vec<(PathBuf, String)>,
+    mtimes: Vec<Option<SystemTime>>,
+    seeds: Vec<RootSeed>,
+    /// Paths already added, keyed by [`Self::dedup_key`], so files given more than once are
+    /// kept once. Only tracked once roots are found to overlap, as a file can otherwise be
+    /// reached only once.
+    added: Option<HashSet<PathBuf>>,
+    /// Canonical location of each registered root, along with the index of its first path
+    root_keys: Vec<(usize, Option<PathBuf>)>,
+    /// Roots and paths are taken as given, without reading anything from disk
+    detached: bool,
+    options: LayoutOptions,
 }
 
 impl LayoutBuilder {
+    pub fn with_options(options: LayoutOptions) -> Self {
+        Self {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Builder for paths that may not exist, such as those of records
+    ///
+    /// Roots are registered as given rather than located on disk, and are only described
+    /// by [`LayoutOptions::descriptions`].
+    pub fn detached(options: LayoutOptions) -> Self {
+        Self {
+            options,
+            detached: true,
+            ..Default::default()
+        }
+    }
+
     fn current_path(&self) -> usize {
         self.paths.len()
     }
@@ -123,7 +237,8 @@ impl LayoutBuilder {
         if let Some(val) = self.heads.get_mut(head) {
             val.insert(i);
         } else {
-            self.heads.insert(head.to_string(), HashSet::from([i]));
+            self.heads
+                .insert(head.to_string(), IndexSet::from_iter([i]));
         }
     }
 
@@ -132,7 +247,7 @@ impl LayoutBuilder {
         if let Some(val) = self.depths.get_mut(&depth) {
             val.insert(i);
         } else {
-            self.depths.insert(depth, HashSet::from([i]));
+            self.depths.insert(depth, IndexSet::from_iter([i]));
         }
     }
 
@@ -147,7 +262,12 @@ impl LayoutBuilder {
             .insert_entity(self.current_path(), entity, value)
     }
     pub(super) fn check_entity(&self, entity: &str) -> bool {
-        self.entities.contains_key(entity) || BIDS_ENTITIES.contains_left(entity)
+        self.entities.contains_key(entity)
+            || check_entity(
+                entity,
+                &self.options.extra_entities,
+                self.options.ignore_key_case,
+            )
     }
 
     pub(super) fn add_uncertain_datatype(&mut self) {
@@ -170,13 +290,48 @@ impl LayoutBuilder {
     }
 
     pub fn register_root(&mut self, root: Option<&PathBuf>, label: RootLabel) -> Option<usize> {
+        // The returned offset must land on a component boundary of the paths under the
+        // root, so trailing or repeated separators are dropped
+        let root_input = root.map(clean);
         // Paths here come from user input, so safe to use to_string_lossy throughout
-        let (len, root) = root
-            .map(|r| BidsPathBuilder::locate_root(r))
-            .flatten()
+        let (len, root) = root_input
+            .as_ref()
+            .and_then(|r| {
+                if self.detached {
+                    Some((r.to_string_lossy().len(), r.as_path()))
+                } else {
+                    BidsPathBuilder::locate_root(r)
+                }
+            })
             .map(|(len, path)| (Some(len), Some(path.to_owned())))
             .unwrap_or((None, None));
 
+        let root_key = root.as_ref().map(|root| {
+            if self.detached {
+                absolute_clean(root)
+            } else {
+                fs::canonicalize(root).unwrap_or_else(|_| absolute_clean(root))
+            }
+        });
+        if let Some(key) = &root_key {
+            let overlaps = |(_, other): &(usize, Option<PathBuf>)| {
+                other
+                    .as_ref()
+                    .is_some_and(|other| is_subpath_of(other, key) || is_subpath_of(key, other))
+            };
+            if self.added.is_none() && self.root_keys.iter().any(overlaps) {
+                self.added = Some(self.added_keys());
+            }
+        }
+        self.root_keys.push((self.paths.len(), root_key));
+        if let (Some(path), Some(_)) = (root_input, &root) {
+            self.seeds.push(RootSeed {
+                path,
+                label: label.clone(),
+                start: self.paths.len(),
+            });
+        }
+
         // Holding ground for new root, as we don't know the extent of it's range
         let new_range = self.paths.len()..0;
         let mut new_root = root.map(|root| match label {
@@ -203,7 +358,14 @@ impl LayoutBuilder {
 
     fn add_raw_root(&mut self, root: PathBuf, mut range: Range<usize>) {
         range.end = self.paths.len();
-        Self::insert_to_root_map(&mut self.roots, root, range);
+        Self::insert_to_root_map(
+            &mut self.roots,
+            &mut self.description_errors,
+            &self.options,
+            self.detached,
+            root,
+            range,
+        );
     }
 
     fn add_derivative_root(
@@ -215,43 +377,112 @@ impl LayoutBuilder {
         range.end = self.paths.len();
         match label {
             Some(label) => {
-                if let Some(mut map) = self.labelled_roots.get_mut(&label) {
-                    Self::insert_to_root_map(&mut map, root, range);
-                } else {
-                    let new_root = DatasetRoot::new_range(range, Some(Path::new(&root)));
-                    self.labelled_roots
-                        .insert(label, HashMap::from([(root, new_root)]));
-                }
+                let map = self.labelled_roots.entry(label).or_default();
+                Self::insert_to_root_map(
+                    map,
+                    &mut self.description_errors,
+                    &self.options,
+                    self.detached,
+                    root,
+                    range,
+                );
             }
-            None => Self::insert_to_root_map(&mut self.derivative_roots, root, range),
+            None => Self::insert_to_root_map(
+                &mut self.derivative_roots,
+                &mut self.description_errors,
+                &self.options,
+                self.detached,
+                root,
+                range,
+            ),
         }
     }
 
     fn insert_to_root_map(
         map: &mut HashMap<PathBuf, DatasetRoot>,
+        description_errors: &mut Vec<(PathBuf, String)>,
+        options: &LayoutOptions,
+        detached: bool,
         key: PathBuf,
         range: Range<usize>,
     ) {
         if let Some(entry) = map.get_mut(&key) {
             entry.insert(range);
         } else {
-            let new_root = DatasetRoot::new_range(range, Some(&Path::new(&key)));
+            let (new_root, err) = match options.description_for(&key) {
+                Some(description) => (
+                    DatasetRoot::with_description(range, Arc::clone(description)),
+                    None,
+                ),
+                None => DatasetRoot::new_range(range, Some(Path::new(&key)).filter(|_| !detached)),
+            };
+            if let Some(err) = err {
+                description_errors.push((key.clone(), err.to_string()));
+            }
             map.insert(key, new_root);
         }
     }
 
+    /// Identify a file by its path within the canonicalized root, so the same file reached
+    /// through differently spelled or symlinked roots is recognized
+    fn dedup_key(root_key: Option<&PathBuf>, path: &str, root: usize) -> PathBuf {
+        match root_key {
+            Some(root_key) => {
+                root_key.join(path[root..].trim_start_matches(std::path::is_separator))
+            }
+            None => absolute_clean(Path::new(path)),
+        }
+    }
+
+    /// Check if a path was already added, and if not, mark it as added
+    fn is_added(&mut self, path: &str, root: usize) -> bool {
+        let root_key = self.root_keys.last().and_then(|(_, key)| key.as_ref());
+        match &mut self.added {
+            Some(added) => !added.insert(Self::dedup_key(root_key, path, root)),
+            None => false,
+        }
+    }
+
+    /// Keys of every path added so far, for once roots are found to overlap
+    fn added_keys(&self) -> HashSet<PathBuf> {
+        let ends = self
+            .root_keys
+            .iter()
+            .skip(1)
+            .map(|(start, _)| *start)
+            .chain([self.paths.len()]);
+        self.root_keys
+            .iter()
+            .zip(ends)
+            .flat_map(|((start, root_key), end)| {
+                self.paths[*start..end]
+                    .iter()
+                    .map(move |path| Self::dedup_key(root_key.as_ref(), path.as_str(), path.root))
+            })
+            .collect()
+    }
+
+    /// Parse and add a path, recording `mtime` as the time it was last modified
     pub fn add_path(
         &mut self,
         path: PathBuf,
         root: usize,
         with_spec: bool,
+        mtime: Option<SystemTime>,
     ) -> Result<(), BidsPathErr> {
+        if path.to_str().is_some_and(|p| self.is_added(p, root)) {
+            return Ok(());
+        }
         let pathbuf = PathBuf::from(&path);
         let mut pathcomps = pathbuf.components();
         pathcomps.next_back();
         let builder = BidsPathBuilder::new(path, root)?;
         let path = if with_spec {
-            let path = builder.spec_parse()?;
+            let path =
+                builder.spec_parse(&self.options.extra_entities, self.options.ignore_key_case)?;
+            if !path.check_entity_order() {
+                return Err(BidsPathErr::EntityOrder(path));
+            }
             self.merge_path(&path);
             path
         } else {
@@ -261,9 +492,45 @@ impl LayoutBuilder {
         self.add_head(&path.get_head());
         self.add_depth(path.depth);
         self.paths.push(path);
+        self.mtimes.push(mtime);
         Ok(())
     }
 
+    /// Add a path parsed by a previous index along with its entities, skipping parsing
+    pub fn add_parsed_path(
+        &mut self,
+        path: BidsPath,
+        entities: &[(&str, &str)],
+        mtime: Option<SystemTime>,
+    ) {
+        if self.is_added(path.as_str(), path.root) {
+            return;
+        }
+        for (entity, value) in entities {
+            self.add_and_confirm_entity(entity, value);
+        }
+        let mut pathcomps = path.as_path().components();
+        pathcomps.next_back();
+        self.filetree.insert(pathcomps, self.current_path());
+        self.add_head(path.get_head());
+        self.add_depth(path.depth);
+        self.paths.push(path);
+        self.mtimes.push(mtime);
+    }
+
+    /// Add a path, recording it as invalid rather than failing if it cannot be parsed
+    pub fn try_add_path(
+        &mut self,
+        path: PathBuf,
+        root: usize,
+        with_spec: bool,
+        mtime: Option<SystemTime>,
+    ) {
+        if let Err(err) = self.add_path(path, root, with_spec, mtime) {
+            self.invalid_paths.push(err);
+        }
+    }
+
     fn handle_uncertain_datatypes(&mut self, i: usize) {
         let mut datatypes = self.extract_uncertain_datatypes(i);
         if let Some(datatypes) = datatypes.as_mut() {
@@ -317,22 +584,46 @@ impl LayoutBuilder {
             .keys()
             .map(|head| format!("{}{}", head, std::path::MAIN_SEPARATOR_STR))
             .collect_vec();
-        let mut roots = HashMap::new();
-        roots.extend(
-            Self::normalize_roots(&heads, self.roots)
-                .into_iter()
-                .map(|(key, val)| (key, RootCategory::Raw(val))),
-        );
-        roots.extend(
-            Self::normalize_roots(&heads, self.derivative_roots)
-                .into_iter()
-                .map(|(key, val)| (key, RootCategory::Derivative(val))),
-        );
-        roots.extend(self.labelled_roots.into_iter().flat_map(|(label, val)| {
-            Self::normalize_roots(&heads, val)
-                .into_iter()
-                .map(move |(root, val)| (root, RootCategory::Labelled(label.clone(), val)))
-        }));
+        let detached = self.detached;
+        let merge = |roots| {
+            if detached {
+                roots
+            } else {
+                Self::merge_duplicate_roots(roots)
+            }
+        };
+        let categories = merge(Self::normalize_roots(&heads, self.roots))
+            .into_iter()
+            .map(|(key, val)| (key, RootCategory::Raw(val)))
+            .chain(
+                merge(Self::normalize_roots(&heads, self.derivative_roots))
+                    .into_iter()
+                    .map(|(key, val)| (key, RootCategory::Derivative(val))),
+            )
+            .chain(
+                self.labelled_roots
+                    .into_iter()
+                    .sorted_by(|(a, _), (b, _)| a.cmp(b))
+                    .flat_map(|(label, val)| {
+                        merge(Self::normalize_roots(&heads, val)).into_iter().map(
+                            move |(root, val)| (root, RootCategory::Labelled(label.clone(), val)),
+                        )
+                    }),
+            );
+        // The same root given under several categories keeps the first, taking the paths of
+        // the others
+        let mut roots: HashMap<PathBuf, RootCategory> = HashMap::new();
+        for (root, category) in categories {
+            match roots.entry(root) {
+                Entry::Occupied(mut entry) => entry
+                    .get_mut()
+                    .root_mut()
+                    .extend(category.root().get_range()),
+                Entry::Vacant(entry) => {
+                    entry.insert(category);
+                }
+            }
+        }
         Layout {
             paths: Arc::new(self.paths),
             entities: self.entities,
@@ -342,6 +633,14 @@ impl LayoutBuilder {
             depths: Arc::new(self.depths),
             metadata: OnceCell::new(),
             view: OnceCell::new(),
+            positions: OnceCell::new(),
+            metadata_store: OnceCell::new(),
+            invalid_paths: Arc::new(self.invalid_paths),
+            description_errors: Arc::new(self.description_errors),
+            mtimes: Arc::new(self.mtimes),
+            indexed: SystemTime::now(),
+            seeds: Arc::new(self.seeds),
+            options: self.options,
         }
     }
 
@@ -379,4 +678,43 @@ impl LayoutBuilder {
         }
         result
     }
+
+    /// Merge roots referring to the same dataset on disk, such as `ds` and `ds/../ds`
+    ///
+    /// Roots are compared by the canonicalized path of their `dataset_description.json`,
+    /// or of the root itself if it has none. The shortest path of each group is kept.
+    fn merge_duplicate_roots(
+        roots: HashMap<PathBuf, DatasetRoot>,
+    ) -> HashMap<PathBuf, DatasetRoot> {
+        let mut result: HashMap<PathBuf, DatasetRoot> = HashMap::new();
+        let mut canonical: HashMap<PathBuf, PathBuf> = HashMap::new();
+        let roots = roots
+            .into_iter()
+            .sorted_by(|(a, _), (b, _)| (a.as_os_str().len(), a).cmp(&(b.as_os_str().len(), b)));
+        for (root, data) in roots {
+            let description = root.join("dataset_description.json");
+            let target = if description.exists() {
+                description
+            } else {
+                root.clone()
+            };
+            let existing = match fs::canonicalize(target) {
+                Ok(target) => match canonical.entry(target) {
+                    Entry::Occupied(entry) => Some(entry.get().clone()),
+                    Entry::Vacant(entry) => {
+                        entry.insert(root.clone());
+                        None
+                    }
+                },
+                Err(_) => None,
+            };
+            match existing.and_then(|existing| result.get_mut(&existing)) {
+                Some(existing) => existing.extend(data.get_range()),
+                None => {
+                    result.insert(root, data);
+                }
+            }
+        }
+        result
+    }
 }
@@ -1,100 +1,282 @@
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::{Path, PathBuf},
+};
 
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::{json::JsonString, serde_as};
 
 use crate::{
-    construct_query,
-    errors::MetadataIndexErr,
-    layout::{entity_table::EntityTable, Layout},
+    errors::{MetadataIndexErr, MetadataReadErr},
+    layout::{
+        bidspath::read_tsv_rows,
+        entity_table::{EntityTable, IndexSet},
+        Layout,
+    },
 };
 
 use super::layout_builder::FileTree;
 
 pub type MetadataIndexResult = Result<HashMap<String, String>, MetadataIndexErr>;
 
+/// Metadata parsed from the sidecars and `participants.tsv` files, with values in their
+/// json form
+///
+/// Files are read once while indexing. Both the json values and the strings of the
+/// metadata index are taken from here.
+#[serde_as]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetadataStore {
+    /// Contents of each sidecar, keyed by the index of the sidecar
+    #[serde_as(as = "HashMap<_, HashMap<_, JsonString>>")]
+    sidecars: HashMap<usize, HashMap<String, Value>>,
+    /// Indices of the sidecars applying to each path, nearest first
+    applied: HashMap<usize, Vec<usize>>,
+    /// Columns of each `participants.tsv` row, leaving out `n/a` cells
+    participants: Vec<HashMap<String, String>>,
+    /// Row of `participants.tsv` applying to each path
+    participant_rows: HashMap<usize, usize>,
+    /// Metadata files that could not be fully read, along with the reason
+    errors: Vec<(PathBuf, String)>,
+}
+
+impl MetadataStore {
+    /// Metadata of a path merged from all applicable sidecars, nearer sidecars taking
+    /// precedence. Columns of `participants.tsv` are added for keys not in any sidecar.
+    pub fn get(&self, ix: usize) -> HashMap<String, Value> {
+        let mut result = HashMap::new();
+        for sidecar in self.applied.get(&ix).into_iter().flatten() {
+            for (key, val) in &self.sidecars[sidecar] {
+                result.entry(key.clone()).or_insert_with(|| val.clone());
+            }
+        }
+        if let Some(row) = self.participant_rows.get(&ix) {
+            for (key, val) in &self.participants[*row] {
+                result
+                    .entry(key.clone())
+                    .or_insert_with(|| Value::String(val.clone()));
+            }
+        }
+        result
+    }
+
+    /// Metadata of a path as found in the metadata index. Arrays and objects are left out.
+    pub fn get_strings(&self, ix: usize) -> HashMap<String, String> {
+        self.get(ix)
+            .into_iter()
+            .filter_map(|(key, val)| Some((key, index_value(&val)?)))
+            .collect()
+    }
+
+    /// Indices of the sidecars applying to a path, from most distant to nearest
+    pub fn sidecars(&self, ix: usize) -> impl Iterator<Item = usize> + '_ {
+        self.applied.get(&ix).into_iter().flatten().rev().copied()
+    }
+
+    pub fn errors(&self) -> &[(PathBuf, String)] {
+        &self.errors
+    }
+}
+
+/// String under which a json value is indexed, or `None` for arrays and objects
+fn index_value(val: &Value) -> Option<String> {
+    match val {
+        Value::String(str) => Some(str.clone()),
+        Value::Null => Some("null".to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Number(x) => Some(x.to_string()),
+        Value::Array(_) | Value::Object(_) => None,
+    }
+}
+
 #[derive(Default)]
 pub struct MetadataIndexBuilder {
     pub metadata: EntityTable<String>,
-    was_assigned: HashMap<String, HashSet<usize>>,
+    pub store: MetadataStore,
+    was_assigned: HashMap<String, IndexSet>,
 }
 
 impl MetadataIndexBuilder {
-    pub fn add_entry(&mut self, key: &str, val: &serde_json::Value, ix: &HashSet<usize>) {
-        use serde_json::Value;
-        let val = match val {
-            Value::String(str) => str.as_str(),
-            Value::Null => "null",
-            Value::Bool(b) => {
-                if *b {
-                    "true"
-                } else {
-                    "false"
-                }
-            }
-            Value::Number(x) => return self.add_entry(key, &Value::String(x.to_string()), ix),
-            _ => return (),
+    pub fn add_entry(&mut self, key: &str, val: &Value, ix: &IndexSet) {
+        let Some(val) = index_value(val) else {
+            return;
         };
         if let Some(assign) = self.was_assigned.get_mut(key) {
             let tbd = ix.difference(assign).cloned().collect_vec();
-            self.metadata.extend_entities(key, val, tbd.iter().cloned());
+            self.metadata
+                .extend_entities(key, &val, tbd.iter().cloned());
             assign.extend(tbd);
         } else {
-            self.metadata.extend_entities(key, val, ix.iter().cloned());
+            self.metadata.extend_entities(key, &val, ix.iter().cloned());
             self.was_assigned.insert(key.to_string(), ix.clone());
         }
     }
 
+    fn add_sidecar(&mut self, sidecar: usize, contents: HashMap<String, Value>, ix: &IndexSet) {
+        for (key, val) in &contents {
+            self.add_entry(key, val, ix);
+        }
+        self.store.sidecars.insert(sidecar, contents);
+        for i in ix {
+            self.store.applied.entry(*i).or_default().push(sidecar);
+        }
+    }
+
     pub fn build(
-        depths: &BTreeMap<usize, HashSet<usize>>,
+        depths: &BTreeMap<usize, IndexSet>,
         filetree: &FileTree,
         layout: &Layout,
     ) -> MetadataIndexBuilder {
         let mut md_builder = Self::default();
+        let entity_keys = layout.entity_keys().cloned().collect();
+        let extra = &layout.options.extra_entities;
         for vals in depths.values().rev() {
-            // Get all json files at depth. If error, nothing was found, so just continue
-            if let Ok(sub) = layout.query(construct_query!("extension": ".json"), None, Some(vals))
-            {
-                for md in sub.get_paths() {
-                    // For now, we ignore all errors related to metadata handling
-                    // Eventually these can be escalated based on configuration
-                    let _ = || -> Result<(), MetadataIndexErr> {
-                        if let Some(ixs) = filetree
-                            .get_subfiles(&md.as_path().parent().expect("Should have a parent"))
-                        {
-                            let ref_entities = md.get_full_entities();
-                            let ixs = ixs
-                                .into_iter()
-                                .filter(|ix| {
-                                    let child_path = layout.get_path(*ix).expect(
-                                        "Internal state of filetree should match that of layout",
-                                    );
-                                    let path_entities = child_path.get_full_entities();
-                                    for (key, val) in &ref_entities {
-                                        if key == &"extension" {
-                                            continue;
-                                        }
-                                        if let Some(foo) = path_entities.get(key) {
-                                            if foo != val {
-                                                return false;
-                                            }
-                                        } else {
+            // Sidecars are taken from every path at the depth rather than just the view,
+            // so sidecars of filtered out paths still apply to those remaining
+            let sidecars = vals
+                .iter()
+                .copied()
+                .filter(|ix| {
+                    let path = &layout.paths[*ix];
+                    path.extensions().is_some_and(|(ext, _)| ext == ".json")
+                        && !path.suffix.as_ref().is_some_and(|suffix| {
+                            layout.options.is_json_data(&path[suffix.clone()])
+                        })
+                })
+                .sorted();
+            for md_ix in sidecars {
+                let md = &layout.paths[md_ix];
+                // Sidecars that cannot be read are skipped and recorded as errors
+                let result = || -> Result<(), MetadataIndexErr> {
+                    if let Some(ixs) =
+                        filetree.get_subfiles(&md.as_path().parent().expect("Should have a parent"))
+                    {
+                        // Directory entities are already implied by the filetree
+                        // scope, so only the filename needs to match. Data files may
+                        // have additional entities not found in the sidecar
+                        let ref_entities = md.get_filename_entities(extra);
+                        let ixs = ixs
+                            .into_iter()
+                            .filter(|ix| {
+                                let mut child_path = layout.paths[*ix].clone();
+                                child_path.update_parents(&entity_keys);
+                                let path_entities = child_path.get_full_entities(extra);
+                                for (key, val) in &ref_entities {
+                                    if key == &"extension" {
+                                        continue;
+                                    }
+                                    if let Some(foo) = path_entities.get(key) {
+                                        if foo != val {
                                             return false;
                                         }
+                                    } else {
+                                        return false;
                                     }
-                                    true
-                                })
-                                .collect::<HashSet<_>>();
-                            for (key, val) in md.read_as_metadata()? {
-                                md_builder.add_entry(&key, &val, &ixs);
-                            }
-                        }
-
-                        Ok(())
-                    }();
+                                }
+                                true
+                            })
+                            .collect::<IndexSet>();
+                        let contents = md.read_as_metadata()?;
+                        md_builder.add_sidecar(md_ix, contents, &ixs);
+                    }
+
+                    Ok(())
+                }();
+                if let Err(err) = result {
+                    md_builder
+                        .store
+                        .errors
+                        .push((md.as_path().to_path_buf(), err.to_string()));
                 }
             }
             // let len = sub.len();
         }
+        // Added after the sidecars so that values in the sidecars take precedence
+        for (root, dataset_root) in layout.roots.raw_items() {
+            let path = root.join("participants.tsv");
+            if let Err(err) =
+                md_builder.add_participants(&path, layout, |i| dataset_root.contains(i))
+            {
+                if err.kind() != io::ErrorKind::NotFound {
+                    md_builder.store.errors.push((path, err.to_string()));
+                }
+            }
+        }
         md_builder
     }
+
+    /// Index the columns of a `participants.tsv` file for every path of each listed subject
+    ///
+    /// Rows that cannot be read are skipped and recorded as errors. `n/a` cells are left
+    /// out of the index.
+    fn add_participants<F: Fn(&usize) -> bool>(
+        &mut self,
+        path: &Path,
+        layout: &Layout,
+        in_root: F,
+    ) -> Result<(), io::Error> {
+        let (header, rows) = read_tsv_rows(path)?;
+        if header.first().map(String::as_str) != Some("participant_id") {
+            self.store.errors.push((
+                path.to_path_buf(),
+                "First column must be 'participant_id'".to_string(),
+            ));
+            return Ok(());
+        }
+        let subjects = layout.entities.get("sub");
+        for (lineno, row) in rows {
+            let row = match row {
+                Ok(row) => row,
+                Err(MetadataReadErr::Tsv(_, _, found, expected)) => {
+                    self.store.errors.push((
+                        path.to_path_buf(),
+                        format!(
+                            "Line {}: expected {} columns, found {}",
+                            lineno, expected, found
+                        ),
+                    ));
+                    continue;
+                }
+                Err(err) => {
+                    self.store
+                        .errors
+                        .push((path.to_path_buf(), err.to_string()));
+                    continue;
+                }
+            };
+            let participant = &row["participant_id"];
+            let label = match participant.strip_prefix("sub-") {
+                Some(label) if !label.is_empty() => label,
+                _ => {
+                    self.store.errors.push((
+                        path.to_path_buf(),
+                        format!("Line {}: invalid participant_id '{}'", lineno, participant),
+                    ));
+                    continue;
+                }
+            };
+            let ixs: IndexSet = match subjects.and_then(|subjects| subjects.get(label)) {
+                Some(ixs) => ixs.iter().copied().filter(&in_root).collect(),
+                None => continue,
+            };
+            let columns: HashMap<String, String> = header
+                .iter()
+                .skip(1)
+                .filter(|key| row[*key] != "n/a")
+                .map(|key| (key.clone(), row[key].clone()))
+                .collect();
+            for (key, val) in &columns {
+                self.add_entry(key, &Value::String(val.clone()), &ixs);
+            }
+            let row_ix = self.store.participants.len();
+            self.store.participants.push(columns);
+            for i in ixs {
+                self.store.participant_rows.entry(i).or_insert(row_ix);
+            }
+        }
+        Ok(())
+    }
 }
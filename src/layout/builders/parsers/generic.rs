@@ -1,15 +1,11 @@
-use crate::{
-    layout::{
-        bidspath::{BidsPath, UnknownDatatype, UnknownDatatypeTypes},
-        builders::{
-            bidspath_builder::{BidsPathBuilder, BidsPathPart, Name},
-            primitives::ComponentType,
-            LayoutBuilder,
-        },
-        check_datatype,
-        entity_table::EntityTable,
+use crate::layout::{
+    bidspath::{BidsPath, UnknownDatatype, UnknownDatatypeTypes},
+    builders::{
+        bidspath_builder::{BidsPathBuilder, BidsPathPart, Name},
+        primitives::ComponentType,
+        LayoutBuilder,
     },
-    standards::BIDS_ENTITIES,
+    check_datatype,
 };
 
 impl BidsPathBuilder {
@@ -34,13 +30,17 @@ impl BidsPathBuilder {
                 }));
                 break;
             }
+            if i < self.root_components {
+                labelled.push(BidsPathPart::Head(self.root));
+                continue;
+            }
             let next_is_twotype = is_twotype[i + 1];
             labelled.push(Self::label_component_type(
                 labelled.last().unwrap_or(&BidsPathPart::Head(0)),
                 comp,
                 &self.path.as_str(),
                 next_is_twotype,
-                &ds_builder.entities,
+                ds_builder,
             ));
         }
         // dbg!(&self.path, &labelled);
@@ -53,21 +53,18 @@ impl BidsPathBuilder {
         // (BidsPath::new(self.path, self.root), labelled)
     }
 
-    fn check_entity(entity: &str, known_entities: &EntityTable<String>) -> bool {
-        known_entities.contains_key(entity) || BIDS_ENTITIES.contains_left(entity)
-    }
     fn label_component_type<'b>(
         previous: &BidsPathPart,
         comp: ComponentType,
         template: &str,
         next_is_twotype: bool,
-        known_entities: &EntityTable<String>,
+        ds_builder: &LayoutBuilder,
     ) -> BidsPathPart {
         match comp {
             ComponentType::TwoType(elems) => BidsPathPart::Name(Name::from_twotype(elems)),
             ComponentType::OneType(keyval) => match previous {
                 BidsPathPart::Head(..) => {
-                    if Self::check_entity(keyval.get_key(template), known_entities) {
+                    if ds_builder.check_entity(keyval.get_key(template)) {
                         BidsPathPart::Parent(keyval)
                     } else {
                         BidsPathPart::UncertainParent(keyval)
@@ -10,7 +10,7 @@ use crate::{
         },
         check_datatype,
     },
-    standards::check_entity as spec_check_entity,
+    standards::{check_entity as spec_check_entity, ExtraEntities},
 };
 
 struct TemplateParser<I: Fn(&str) -> bool> {
@@ -106,8 +106,12 @@ enum LastMatch {
 
 impl BidsPathBuilder {
     #[inline]
-    pub fn spec_parse(self) -> Result<BidsPath, BidsPathErr> {
-        self.template_parse(spec_check_entity)
+    pub fn spec_parse(
+        self,
+        extra: &ExtraEntities,
+        ignore_key_case: bool,
+    ) -> Result<BidsPath, BidsPathErr> {
+        self.template_parse(|entity| spec_check_entity(entity, extra, ignore_key_case))
     }
 
     pub fn template_parse<I: Fn(&str) -> bool>(
@@ -138,6 +142,8 @@ impl BidsPathBuilder {
                 ) {
                     return Err(BidsPathErr::Validation(parser.bidspath.clear()));
                 }
+            } else if i < self.root_components {
+                lastmatch = parser.handle_head(0..self.root);
             } else {
                 match comp {
                     ComponentType::ZeroType(range) => match lastmatch {
@@ -1,8 +1,9 @@
-use std::{collections::HashSet, fmt, ops::Range};
+use std::{fmt, ops::Range};
 
 use itertools::Itertools;
 use serde::{Serialize, Deserialize};
 
+use crate::layout::entity_table::IndexSet;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MultiRange<I> {
@@ -21,14 +22,14 @@ impl<I> From<Range<I>> for MultiRange<I> {
     }
 }
 
-impl Into<HashSet<usize>> for MultiRange<usize> {
-    fn into(self) -> HashSet<usize> {
+impl Into<IndexSet> for MultiRange<usize> {
+    fn into(self) -> IndexSet {
         self.ranges.into_iter().flat_map(|range| range).collect()
     }
 }
 
-impl Into<HashSet<usize>> for &MultiRange<usize> {
-    fn into(self) -> HashSet<usize> {
+impl Into<IndexSet> for &MultiRange<usize> {
+    fn into(self) -> IndexSet {
         self.ranges
             .iter()
             .cloned()
@@ -108,7 +109,7 @@ impl<I: Ord + Default + Copy> MultiRange<I> {
                         } else {
                             next_left = ours.find(|i| i.end > right.end);
                             next_right = theirs.next();
-                            (right.start, left.end)
+                            (right.start, right.end)
                         }
                     }
                 } else {
@@ -136,11 +137,16 @@ impl<I: Ord + Default + Copy> MultiRange<I> {
 }
 
 impl MultiRange<usize> {
-    pub fn len(&self) {
-        let mut len: usize = 0;
-        for range in &self.ranges {
-            len = (range.start - range.end) + len
-        }
+    /// Number of values covered by the ranges
+    pub fn len(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
@@ -227,3 +233,33 @@ pub enum PrePrimitive {
     KeyLike(usize),
     ValueLike(usize),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multirange_len() {
+        assert_eq!(MultiRange::<usize>::new().len(), 0);
+        assert!(MultiRange::<usize>::new().is_empty());
+        assert!(MultiRange::from(3..3).is_empty());
+        assert_eq!(MultiRange::from(2..5).len(), 3);
+
+        let mut disjoint = MultiRange::from(0..2);
+        disjoint.insert(5..8);
+        assert_eq!(disjoint.len(), 5);
+
+        let mut adjacent = MultiRange::from(0..2);
+        adjacent.insert(2..4);
+        assert_eq!(adjacent.len(), 4);
+
+        let mut overlapping = MultiRange::from(4..10);
+        overlapping.extend(&MultiRange::from(2..6));
+        assert_eq!(overlapping.len(), 8);
+
+        let mut nested = MultiRange::from(3..4);
+        nested.extend(&MultiRange::from(0..10));
+        assert_eq!(nested.len(), 10);
+        assert!(!nested.is_empty());
+    }
+}
@@ -2,49 +2,359 @@ use std::{
     fs,
     io::{self, Read, Write},
     path::PathBuf,
+    time::SystemTime,
 };
 
-use crate::errors::CacheErr;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-use super::Layout;
+use crate::{errors::CacheErr, utils::modified};
 
-const DECLARATION: &[u8] = "<?rsbids version=\"1.0\">\n".as_bytes();
+use super::{
+    builders::{LayoutBuilder, RootLabel},
+    Layout, LayoutOptions,
+};
+
+/// Schema version of the cache body. Bump once per release whenever the serialized
+/// [`Layout`] changes
+const CACHE_VERSION: &str = "1.1";
+
+/// Single `key="value"` attribute of a cache declaration
+static DECLARATION_ATTR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"^\s+(\w+)="([^"]*)""#).expect("valid regex"));
+
+/// Parse a `major.minor` schema version
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let (major, minor) = version.split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Encoding used for the body of a cache file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheFormat {
+    /// The complete serialized layout
+    Binary,
+    /// A json list of files under each root, reindexed on load
+    FileList,
+}
+
+impl CacheFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Binary => "binary",
+            Self::FileList => "filelist",
+        }
+    }
+}
+
+/// Attributes parsed from the `<?rsbids ...>` line heading each cache file
+struct Declaration {
+    version: String,
+    format: CacheFormat,
+    compressed: bool,
+    writer: Option<String>,
+}
+
+impl Declaration {
+    fn current(format: CacheFormat, compressed: bool) -> Self {
+        Self {
+            version: CACHE_VERSION.to_string(),
+            format,
+            compressed,
+            writer: Some(env!("CARGO_PKG_VERSION").to_string()),
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let attrs = line.strip_prefix("<?rsbids")?.strip_suffix('>')?;
+        let mut attrs = attrs.trim_end();
+        let (mut version, mut format, mut writer) = (None, CacheFormat::Binary, None);
+        let mut compressed = false;
+        while let Some(cap) = DECLARATION_ATTR.captures(attrs) {
+            let value = cap[2].to_string();
+            match &cap[1] {
+                "version" => version = Some(value),
+                "format" => {
+                    format = match value.as_str() {
+                        "binary" => CacheFormat::Binary,
+                        "filelist" => CacheFormat::FileList,
+                        _ => return None,
+                    }
+                }
+                "compression" => match value.as_str() {
+                    "gzip" => compressed = true,
+                    _ => return None,
+                },
+                "rsbids" => writer = Some(value),
+                _ => (),
+            }
+            attrs = &attrs[cap[0].len()..];
+        }
+        if !attrs.is_empty() {
+            return None;
+        }
+        Some(Self {
+            version: version?,
+            format,
+            compressed,
+            writer,
+        })
+    }
+
+    fn to_line(&self) -> String {
+        let mut line = format!(
+            "<?rsbids version=\"{}\" format=\"{}\"",
+            self.version,
+            self.format.as_str()
+        );
+        if self.compressed {
+            line.push_str(" compression=\"gzip\"");
+        }
+        if let Some(writer) = &self.writer {
+            line.push_str(&format!(" rsbids=\"{}\"", writer));
+        }
+        line.push_str(">\n");
+        line
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileListRoot {
+    path: PathBuf,
+    label: RootLabel,
+    files: Vec<String>,
+    /// Modification time of each file when it was indexed. Missing from caches written
+    /// before it was recorded, whose files are checked on load instead
+    #[serde(default)]
+    mtimes: Vec<Option<SystemTime>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileList {
+    options: LayoutOptions,
+    roots: Vec<FileListRoot>,
+    /// When the files were listed. Missing from caches written before it was recorded
+    #[serde(default)]
+    indexed: Option<SystemTime>,
+    /// Whether metadata was indexed, in which case it is indexed again on load
+    #[serde(default)]
+    metadata: bool,
+}
 
 pub struct LayoutCache;
 
 impl LayoutCache {
-    fn write(path: PathBuf, data: Vec<u8>) -> io::Result<()> {
-        let decleration = Vec::from(DECLARATION);
+    fn write(path: PathBuf, format: CacheFormat, compress: bool, data: Vec<u8>) -> io::Result<()> {
         let mut file = fs::File::create(path)?;
-        file.write_all(&decleration)?;
-        file.write_all(&data)?;
+        file.write_all(Declaration::current(format, compress).to_line().as_bytes())?;
+        if compress {
+            let mut encoder = GzEncoder::new(file, Compression::default());
+            encoder.write_all(&data)?;
+            encoder.finish()?;
+        } else {
+            file.write_all(&data)?;
+        }
         Ok(())
     }
 
-    fn read(path: PathBuf) -> io::Result<Vec<u8>> {
-        let mut declaration = [0u8; DECLARATION.len()];
-        let mut file = fs::File::open(path.clone())?;
-        file.read_exact(&mut declaration)?;
-        if declaration == DECLARATION {
-            let mut encoded: Vec<u8> = Vec::new();
-            file.read_to_end(&mut encoded)?;
-            Ok(encoded)
-        } else {
-            Err(io::Error::new(
+    fn read(path: PathBuf) -> Result<(CacheFormat, bool, Vec<u8>), CacheErr> {
+        let mut contents = Vec::new();
+        fs::File::open(path.clone())?.read_to_end(&mut contents)?;
+        let header = contents.iter().position(|b| *b == b'\n').and_then(|end| {
+            let declaration = Declaration::parse(std::str::from_utf8(&contents[..end]).ok()?)?;
+            Some((end, declaration))
+        });
+        let Some((end, declaration)) = header else {
+            return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!("'{}' is not a valid rsbids cache file", path.to_string_lossy()),
-            ))
+            )
+            .into());
+        };
+        let mut body = contents.split_off(end + 1);
+        if declaration.compressed {
+            let mut decoded = Vec::new();
+            GzDecoder::new(body.as_slice()).read_to_end(&mut decoded)?;
+            body = decoded;
+        }
+        let body = Self::migrate(&declaration, body)?;
+        Ok((declaration.format, declaration.compressed, body))
+    }
+
+    /// Check if `version` is an earlier release of the current major schema version
+    fn predates_current(version: &str) -> bool {
+        match (parse_version(version), parse_version(CACHE_VERSION)) {
+            (Some(found), Some(current)) => found.0 == current.0 && found < current,
+            _ => false,
+        }
+    }
+
+    /// Upgrade a cache body written under an older schema version, if possible
+    fn migrate(declaration: &Declaration, body: Vec<u8>) -> Result<Vec<u8>, CacheErr> {
+        match (declaration.format, declaration.version.as_str()) {
+            (_, CACHE_VERSION) => Ok(body),
+            // File lists are reindexed on load, so their format has not changed
+            (CacheFormat::FileList, version) if Self::predates_current(version) => Ok(body),
+            (_, version) => Err(CacheErr::Version {
+                found: version.to_string(),
+                writer: declaration.writer.clone(),
+                expected: CACHE_VERSION,
+            }),
         }
     }
 
     pub fn save(layout: &Layout, path: PathBuf) -> Result<(), CacheErr> {
-        let encoded = bincode::serialize(layout)?;
-        Self::write(path, encoded).map_err(|err| Box::new(bincode::ErrorKind::Io(err)))?;
+        Self::save_as(layout, path, CacheFormat::Binary, false)
+    }
+
+    /// Save a cache in the given format, optionally gzip compressing the body
+    pub fn save_as(
+        layout: &Layout,
+        path: PathBuf,
+        format: CacheFormat,
+        compress: bool,
+    ) -> Result<(), CacheErr> {
+        match format {
+            CacheFormat::Binary => {
+                let encoded = bincode::serialize(layout)?;
+                Self::write(path, format, compress, encoded)?;
+            }
+            CacheFormat::FileList => {
+                let encoded = serde_json::to_vec(&Self::file_list(layout))?;
+                Self::write(path, format, compress, encoded)?;
+            }
+        }
         Ok(())
     }
 
+    /// Load a cache file, detecting its format from the declaration
     pub fn load(path: PathBuf) -> Result<Layout, CacheErr> {
-        let encoded = Self::read(path).map_err(|err| Box::new(bincode::ErrorKind::Io(err)))?;
-        Ok(bincode::deserialize(&encoded)?)
+        Ok(Self::load_with_format(path)?.2)
+    }
+
+    /// Load a cache, modify the layout, and write it back in its original format
+    pub fn rewrite<F>(path: PathBuf, modify: F) -> Result<Layout, CacheErr>
+    where
+        F: FnOnce(Layout) -> Result<Layout, CacheErr>,
+    {
+        let (format, compressed, layout) = Self::load_with_format(path.clone())?;
+        let layout = modify(layout)?;
+        Self::save_as(&layout, path, format, compressed)?;
+        Ok(layout)
+    }
+
+    /// Bring a cache up to date with the filesystem, rewriting it in its original format
+    pub fn update(path: PathBuf) -> Result<Layout, CacheErr> {
+        Self::rewrite(path, |layout| Ok(layout.update()?))
+    }
+
+    /// Load a cache, dropping paths that were deleted or modified since it was saved
+    ///
+    /// Modified paths are parsed again if `reparse` is set
+    pub fn load_validated(path: PathBuf, reparse: bool) -> Result<Layout, CacheErr> {
+        Ok(Self::load(path)?.revalidate(reparse))
+    }
+
+    fn load_with_format(path: PathBuf) -> Result<(CacheFormat, bool, Layout), CacheErr> {
+        let (format, compressed, encoded) = Self::read(path)?;
+        let layout = match format {
+            CacheFormat::Binary => bincode::deserialize(&encoded)?,
+            CacheFormat::FileList => {
+                let list: FileList = serde_json::from_slice(&encoded)?;
+                Self::from_file_list(list)
+            }
+        };
+        Ok((format, compressed, layout))
+    }
+
+    /// Group the paths in view under the root they were originally registered with
+    ///
+    /// Roots and paths are written as they were given, like in binary caches, so a layout
+    /// of relative roots is equal to its reloaded copy and can be reopened with them
+    fn file_list(layout: &Layout) -> FileList {
+        let roots = layout
+            .seed_groups()
+            .into_iter()
+            .map(|(seed, ixs)| FileListRoot {
+                path: seed.path.clone(),
+                label: seed.label.clone(),
+                files: ixs
+                    .iter()
+                    .map(|&i| layout.paths[i].as_str().to_string())
+                    .collect(),
+                mtimes: ixs.iter().map(|&i| layout.mtimes[i]).collect(),
+            })
+            .collect();
+        FileList {
+            options: layout.options.clone(),
+            roots,
+            indexed: Some(layout.indexed),
+            metadata: layout.metadata.get().is_some(),
+        }
+    }
+
+    fn from_file_list(list: FileList) -> Layout {
+        let mut builder = LayoutBuilder::with_options(list.options.clone());
+        for root in list.roots {
+            let rootpos = builder
+                .register_root(Some(&root.path), root.label)
+                .unwrap_or(0);
+            for (i, file) in root.files.into_iter().enumerate() {
+                let path = PathBuf::from(file);
+                let mtime = match root.mtimes.get(i) {
+                    Some(mtime) => *mtime,
+                    None => modified(&path).ok().flatten(),
+                };
+                builder.try_add_path(path, rootpos, list.options.validate, mtime);
+            }
+        }
+        let mut layout = builder.finalize();
+        // The files were listed when the cache was written, not when it was loaded
+        if let Some(indexed) = list.indexed {
+            layout.indexed = indexed;
+        }
+        if list.metadata {
+            layout.index_metadata();
+        }
+        layout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_utils::TempDataset;
+
+    #[test]
+    fn file_list_keeps_mtimes_from_when_it_was_saved() {
+        let dataset = TempDataset::new(
+            "filelist",
+            &[
+                ("dataset_description.json", "{}"),
+                ("sub-01/anat/sub-01_T1w.nii.gz", ""),
+                ("sub-01/anat/sub-01_T2w.nii.gz", ""),
+            ],
+        );
+        let layout = dataset.create(LayoutOptions::default(), None);
+        let cache = dataset.path("layout.cache");
+        LayoutCache::save_as(&layout, cache.clone(), CacheFormat::FileList, false).unwrap();
+
+        let touched = dataset.path("sub-01/anat/sub-01_T1w.nii.gz");
+        let mtime = modified(&touched).unwrap().unwrap() + Duration::from_secs(10);
+        fs::File::options()
+            .write(true)
+            .open(&touched)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        // Without reparsing, files modified since the cache was saved are dropped
+        let loaded = LayoutCache::load(cache).unwrap().revalidate(false);
+        assert!(!loaded.contains(&touched));
+        assert!(loaded.contains(&dataset.path("sub-01/anat/sub-01_T2w.nii.gz")));
     }
 }
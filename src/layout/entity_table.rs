@@ -1,11 +1,27 @@
 use std::borrow::Borrow;
 use std::collections::{hash_map, HashMap, HashSet};
+#[cfg(feature = "fxhash")]
+use std::hash::BuildHasherDefault;
 use std::hash::Hash;
 use std::ops::{Deref, DerefMut};
 
 use serde::{Deserialize, Serialize};
 
-type EntityTableType<T> = HashMap<String, HashMap<T, HashSet<usize>>>;
+/// Hasher of the index sets and value maps. The `fxhash` feature swaps the default hasher
+/// for a faster one, which is safe since these tables are internal and not exposed to
+/// adversarial keys.
+#[cfg(feature = "fxhash")]
+pub type IndexHasher = BuildHasherDefault<rustc_hash::FxHasher>;
+#[cfg(not(feature = "fxhash"))]
+pub type IndexHasher = std::collections::hash_map::RandomState;
+
+/// Set of path indices
+pub type IndexSet = HashSet<usize, IndexHasher>;
+
+/// Path indices of each value of an entity
+pub type ValueMap<T> = HashMap<T, IndexSet, IndexHasher>;
+
+type EntityTableType<T> = HashMap<String, ValueMap<T>>;
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct EntityTable<T: Serialize + Eq + Hash>(EntityTableType<T>);
@@ -26,11 +42,11 @@ where
             if let Some(set) = val_map.get_mut(value) {
                 set.insert(i);
             } else {
-                val_map.insert(value.to_owned(), HashSet::from([i]));
+                val_map.insert(value.to_owned(), IndexSet::from_iter([i]));
             }
         } else {
-            let mut val_map = HashMap::new();
-            val_map.insert(value.to_owned(), HashSet::from([i]));
+            let mut val_map = ValueMap::default();
+            val_map.insert(value.to_owned(), IndexSet::from_iter([i]));
             self.0.insert(entity.to_owned(), val_map);
         }
     }
@@ -47,11 +63,20 @@ where
                 val_map.insert(value.to_owned(), ixs.into_iter().collect());
             }
         } else {
-            let mut val_map = HashMap::new();
+            let mut val_map = ValueMap::default();
             val_map.insert(value.to_owned(), ixs.into_iter().collect());
             self.0.insert(entity.to_owned(), val_map);
         }
     }
+
+    /// Add all values and indices from another table into this one
+    pub fn merge(&mut self, other: &Self) {
+        for (entity, values) in other.iter() {
+            for (value, ixs) in values {
+                self.extend_entities(entity, value, ixs.iter().copied());
+            }
+        }
+    }
 }
 
 impl<T> From<EntityTableType<T>> for EntityTable<T>
@@ -86,9 +111,35 @@ impl<T> IntoIterator for EntityTable<T>
 where
     T: Serialize + Eq + Hash,
 {
-    type Item = (String, HashMap<T, HashSet<usize>>);
-    type IntoIter = hash_map::IntoIter<String, HashMap<T, HashSet<usize>>>;
+    type Item = (String, ValueMap<T>);
+    type IntoIter = hash_map::IntoIter<String, ValueMap<T>>;
     fn into_iter(self) -> Self::IntoIter {
         self.0.into_iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn merge_matches_std_sets() {
+        let mut left = EntityTable::<String>::new();
+        let mut right = EntityTable::<String>::new();
+        for i in 0..100 {
+            left.insert_entity(i, "run", &(i % 3).to_string());
+            right.insert_entity(i + 50, "run", &(i % 4).to_string());
+        }
+        left.merge(&right);
+        for (value, ixs) in &left["run"] {
+            let value: usize = value.parse().unwrap();
+            let expected: HashSet<usize> = (0..100)
+                .filter(|i| i % 3 == value)
+                .chain((0..100).filter(|i| i % 4 == value).map(|i| i + 50))
+                .collect();
+            assert_eq!(ixs.iter().copied().collect::<HashSet<_>>(), expected);
+        }
+    }
+}
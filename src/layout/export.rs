@@ -0,0 +1,172 @@
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::{dataset_description::DatasetDescription, errors::ExportErr, utils::absolute_clean};
+
+use super::Layout;
+
+/// Root listed by [`Layout::to_json`]
+#[derive(Serialize)]
+struct JsonRoot {
+    path: PathBuf,
+    /// `raw` or `derivative`, or the label of labelled derivatives
+    category: String,
+    description: Option<Arc<DatasetDescription>>,
+}
+
+/// Path listed by [`Layout::to_json`], with its entities under their full names
+#[derive(Serialize)]
+struct JsonFile {
+    path: PathBuf,
+    root: Option<PathBuf>,
+    entities: BTreeMap<String, String>,
+}
+
+/// Document written by [`Layout::to_json`]. Kept separate from the types of the layout
+/// itself, so the format stays stable as they change.
+#[derive(Serialize)]
+struct JsonLayout {
+    roots: Vec<JsonRoot>,
+    files: Vec<JsonFile>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportMode {
+    Copy,
+    Symlink,
+    Hardlink,
+}
+
+impl ExportMode {
+    fn apply(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        match self {
+            ExportMode::Copy => fs::copy(src, dest).map(|_| ()),
+            ExportMode::Hardlink => fs::hard_link(src, dest),
+            #[cfg(unix)]
+            ExportMode::Symlink => std::os::unix::fs::symlink(src, dest),
+            #[cfg(windows)]
+            ExportMode::Symlink => std::os::windows::fs::symlink_file(src, dest),
+        }
+    }
+}
+
+/// Deepest directory containing all of `dirs`, or `None` if they share none, such as
+/// directories on different drives
+fn common_ancestor<'a>(mut dirs: impl Iterator<Item = &'a PathBuf>) -> Option<PathBuf> {
+    let mut ancestor = dirs.next()?.clone();
+    for dir in dirs {
+        while !dir.starts_with(&ancestor) {
+            if !ancestor.pop() {
+                return None;
+            }
+        }
+    }
+    Some(ancestor)
+}
+
+impl Layout {
+    /// Recreate the paths in view under `dest`, relative to the deepest directory shared by
+    /// their roots
+    ///
+    /// A view of a single root is exported relative to that root, while a raw root and its
+    /// derivatives keep their layout under `dest`. The `dataset_description.json` of each
+    /// root contributing paths is exported at the location of its root. Symlinks point at
+    /// absolute sources. Destinations are planned and checked before anything is written,
+    /// so paths that would overwrite each other or existing files raise an error without
+    /// modifying `dest`.
+    pub fn export(&self, dest: &Path, mode: ExportMode) -> Result<(), ExportErr> {
+        let paths = self
+            .view_indices()
+            .map(|i| {
+                let path = &self.paths[i];
+                let root = match path.get_root() {
+                    "" => path.as_path().parent().unwrap_or(Path::new("")),
+                    root => Path::new(root),
+                };
+                (absolute_clean(path.as_path()), absolute_clean(root))
+            })
+            .collect_vec();
+        let roots: HashSet<&PathBuf> = paths.iter().map(|(_, root)| root).collect();
+        let base = common_ancestor(roots.iter().copied());
+        let mut planned: HashMap<PathBuf, PathBuf> = HashMap::new();
+        let mut plan = |src: PathBuf, root: &Path| {
+            let rel = src
+                .strip_prefix(base.as_deref().unwrap_or(root))
+                .unwrap_or(&src);
+            match planned.entry(dest.join(rel)) {
+                Entry::Occupied(entry) if *entry.get() != src => Err(ExportErr::Collision {
+                    dest: entry.key().clone(),
+                    first: entry.get().clone(),
+                    second: src,
+                }),
+                Entry::Occupied(_) => Ok(()),
+                Entry::Vacant(entry) => {
+                    entry.insert(src);
+                    Ok(())
+                }
+            }
+        };
+        for (path, root) in &paths {
+            plan(path.clone(), root)?;
+        }
+        for root in roots {
+            let description = root.join("dataset_description.json");
+            if description.is_file() {
+                plan(description, root)?;
+            }
+        }
+        // Dangling symlinks left by an earlier export also count as existing
+        if let Some(existing) = planned.keys().find(|dest| dest.symlink_metadata().is_ok()) {
+            return Err(ExportErr::Exists(existing.clone()));
+        }
+        for (dest, src) in planned {
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            mode.apply(&src, &dest)?;
+        }
+        Ok(())
+    }
+
+    /// JSON document listing the roots and paths in view, for use by other tools
+    ///
+    /// Paths are made absolute. Unlike the cache, offsets and other internal details of the
+    /// layout are left out. Fails if a path isn't valid unicode.
+    pub fn to_json(&self) -> Result<String, ExportErr> {
+        let roots = self
+            .filtered_roots(self.roots.items())
+            .map(|(root, data)| JsonRoot {
+                path: absolute_clean(root),
+                category: self
+                    .roots
+                    .category(root)
+                    .map(|category| category.name().to_string())
+                    .unwrap_or_default(),
+                description: data.get_description(),
+            })
+            .sorted_by(|a, b| a.path.cmp(&b.path))
+            .collect();
+        let files = self
+            .get_paths(false)
+            .map(|path| JsonFile {
+                path: absolute_clean(path.as_path()),
+                root: Some(path.get_root())
+                    .filter(|root| !root.is_empty())
+                    .map(|root| absolute_clean(Path::new(root))),
+                entities: path
+                    .get_full_entities(&self.options.extra_entities)
+                    .into_iter()
+                    .map(|(key, val)| (key.to_string(), val.to_string()))
+                    .collect(),
+            })
+            .collect();
+        Ok(serde_json::to_string(&JsonLayout { roots, files })?)
+    }
+}
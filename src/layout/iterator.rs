@@ -1,4 +1,8 @@
-use std::{collections::HashSet, ops::Range, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::Arc,
+};
 
 use super::bidspath::BidsPath;
 
@@ -38,3 +42,38 @@ impl Iterator for BidsPathViewIterator {
         Some(path)
     }
 }
+
+/// Iterator over the paths in a view and their entities, borrowed from the layout
+///
+/// Entities are those of [`BidsPath::get_entities`] after updating parents, as yielded by
+/// [`BidsPathViewIterator`], but nothing is cloned apart from the map holding them.
+pub struct BidsPathRefIterator<'a> {
+    paths: &'a [BidsPath],
+    valid_entities: HashSet<String>,
+    iterator: Box<dyn Iterator<Item = usize> + 'a>,
+}
+
+impl<'a> BidsPathRefIterator<'a> {
+    pub fn new(
+        paths: &'a [BidsPath],
+        entities: HashSet<String>,
+        indices: impl Iterator<Item = usize> + 'a,
+    ) -> BidsPathRefIterator<'a> {
+        BidsPathRefIterator {
+            paths,
+            valid_entities: entities,
+            iterator: Box::new(indices),
+        }
+    }
+}
+
+impl<'a> Iterator for BidsPathRefIterator<'a> {
+    type Item = (&'a str, HashMap<&'a str, &'a str>);
+    fn next(&mut self) -> Option<Self::Item> {
+        let path = &self.paths[self.iterator.next()?];
+        Some((
+            path.as_str(),
+            path.get_entities_with_parents(&self.valid_entities),
+        ))
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     collections::{HashMap, HashSet},
+    io,
     mem::swap,
     ops::Range,
     path::{Path, PathBuf},
@@ -13,10 +14,23 @@ use serde_with::serde_as;
 
 use crate::{
     dataset_description::{DatasetDescription, DatasetDescriptionBin},
-    errors::GlobErr,
+    errors::{DatasetDescriptionErr, GlobErr},
+    utils::{absolute_clean, is_subpath_of},
 };
 
-use super::{builders::primitives::MultiRange, QueryErr};
+use super::{builders::primitives::MultiRange, entity_table::IndexSet, QueryErr};
+
+/// How a root given in a query is compared against the roots of a layout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RootMatch {
+    /// Only the identical root
+    Exact,
+    /// The identical root if there is one, otherwise roots matching the path as a glob
+    #[default]
+    Glob,
+    /// The root and any root nested beneath it
+    Prefix,
+}
 
 #[serde_as]
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -34,21 +48,40 @@ pub struct DatasetRoot {
 }
 
 impl DatasetRoot {
-    pub fn new_range(range: Range<usize>, desc_path: Option<&Path>) -> Self {
-        let description = desc_path
-            .map(|p| {
-                DatasetDescription::open(p)
-            })
-            .transpose()
-            // Ignoring opening errors for now
-            .unwrap_or(None);
-        Self {
+    /// Create a root, reading its description from `desc_path`
+    ///
+    /// Roots with a description that cannot be read are treated as seed roots. Errors other
+    /// than a missing description are returned alongside the root.
+    pub fn new_range(
+        range: Range<usize>,
+        desc_path: Option<&Path>,
+    ) -> (Self, Option<DatasetDescriptionErr>) {
+        let (description, err) = match desc_path.map(DatasetDescription::open) {
+            Some(Ok(desc)) => (Some(desc), None),
+            Some(Err(DatasetDescriptionErr::IoErr(err)))
+                if err.kind() == io::ErrorKind::NotFound =>
+            {
+                (None, None)
+            }
+            Some(Err(err)) => (None, Some(err)),
+            None => (None, None),
+        };
+        let root = Self {
             roottype: match description {
                 Some(desc) => RootType::DatasetRoot(Arc::new(desc), range.into()),
                 None => RootType::SeedRoot(range.into()),
             },
+        };
+        (root, err)
+    }
+
+    /// Create a root with a description given directly, rather than read from disk
+    pub fn with_description(range: Range<usize>, description: Arc<DatasetDescription>) -> Self {
+        Self {
+            roottype: RootType::DatasetRoot(description, range.into()),
         }
     }
+
     pub fn get_range(&self) -> &MultiRange<usize> {
         match &self.roottype {
             RootType::DatasetRoot(_, ranges) => ranges,
@@ -91,8 +124,8 @@ impl DatasetRoot {
     }
 }
 
-impl Into<HashSet<usize>> for &DatasetRoot {
-    fn into(self) -> HashSet<usize> {
+impl Into<IndexSet> for &DatasetRoot {
+    fn into(self) -> IndexSet {
         match &self.roottype {
             RootType::DatasetRoot(_, ranges) => ranges.into(),
             RootType::SeedRoot(ranges) => ranges.into(),
@@ -115,6 +148,33 @@ pub enum RootCategory {
     Labelled(String, DatasetRoot),
 }
 
+impl RootCategory {
+    /// `raw` or `derivative`, or the label of labelled derivatives
+    pub fn name(&self) -> &str {
+        match self {
+            RootCategory::Raw(_) => "raw",
+            RootCategory::Derivative(_) => "derivative",
+            RootCategory::Labelled(label, _) => label,
+        }
+    }
+
+    pub fn root(&self) -> &DatasetRoot {
+        match self {
+            RootCategory::Raw(root)
+            | RootCategory::Derivative(root)
+            | RootCategory::Labelled(_, root) => root,
+        }
+    }
+
+    pub fn root_mut(&mut self) -> &mut DatasetRoot {
+        match self {
+            RootCategory::Raw(root)
+            | RootCategory::Derivative(root)
+            | RootCategory::Labelled(_, root) => root,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct DatasetRoots {
     roots: HashMap<PathBuf, RootCategory>,
@@ -124,13 +184,14 @@ impl DatasetRoots {
     pub fn get_scopes(&self, scopes: Vec<String>) -> Result<Option<Vec<PathBuf>>, QueryErr> {
         let mut result = Vec::new();
         let mut errs = Vec::new();
+        let mut all = false;
         for scope in scopes {
             if scope == "raw" || scope == "self" {
                 result.extend(self.raw_keys())
             } else if scope == "derivatives" {
                 result.extend(self.derivative_keys())
             } else if scope == "all" {
-                return Ok(None);
+                all = true;
             } else if let Some(labelled) = self.find_by_label(&scope) {
                 result.extend(labelled);
             } else if let Some(pipelines) = self.find_by_pipeline(&scope) {
@@ -139,17 +200,57 @@ impl DatasetRoots {
                 errs.push(scope)
             }
         }
-        // Skip errors from missing scope for now
-        if false && errs.len() > 0 {
+        if !errs.is_empty() {
             Err(QueryErr::MissingVal(String::from("scope"), errs))
+        } else if all {
+            Ok(None)
         } else {
-            Ok(Some(result.iter_mut().map(|s| s.clone()).collect()))
+            Ok(Some(result.into_iter().cloned().collect()))
         }
     }
+
+    /// Roots in any of `scopes` that match `root`, or are nested beneath a root that does
+    ///
+    /// This limits a scope, such as a pipeline name, to the derivatives of a single dataset.
+    pub fn get_scoped_roots(
+        &self,
+        root: (PathBuf, RootMatch),
+        scopes: Vec<String>,
+    ) -> Result<Vec<PathBuf>, QueryErr> {
+        let matched = self.match_roots(vec![root])?;
+        let in_scope = self.get_scopes(scopes)?;
+        Ok(self
+            .roots
+            .keys()
+            .filter(|key| in_scope.as_ref().is_none_or(|scoped| scoped.contains(key)))
+            .filter(|key| matched.keys().any(|root| key.starts_with(root)))
+            .cloned()
+            .collect())
+    }
+    /// Find the most specific root containing `path`
+    pub fn nearest_root(&self, path: &Path) -> Option<(&PathBuf, &RootCategory)> {
+        let path = absolute_clean(path);
+        self.roots
+            .iter()
+            .map(|(root, category)| (absolute_clean(root), root, category))
+            .filter(|(abs, ..)| is_subpath_of(abs, &path))
+            .max_by_key(|(abs, ..)| abs.components().count())
+            .map(|(_, root, category)| (root, category))
+    }
+
     pub fn keys(&self) -> impl Iterator<Item = &PathBuf> {
         self.roots.keys()
     }
 
+    pub fn categories(&self) -> impl Iterator<Item = (&PathBuf, &RootCategory)> {
+        self.roots.iter()
+    }
+
+    /// Category of a root, given exactly as stored
+    pub fn category(&self, root: &Path) -> Option<&RootCategory> {
+        self.roots.get(root)
+    }
+
     pub fn items(&self) -> impl Iterator<Item = (&PathBuf, &DatasetRoot)> {
         self.roots.iter().map(|(root, data)| match data {
             RootCategory::Derivative(ranges)
@@ -269,59 +370,57 @@ impl DatasetRoots {
     /// Return indices of all paths corresponding to roots
     ///
     /// If no roots, returns an empty set
-    pub fn into_set(&self) -> HashSet<usize> {
+    pub fn into_set(&self) -> IndexSet {
         self.ranges()
             .map_into()
-            .fold(HashSet::new(), |set, next| &set | &next)
+            .fold(IndexSet::default(), |set, next| &set | &next)
     }
 
-    /// Return a new DatasetRoots with only roots matching the given glob patterns
-    pub fn glob_roots(&self, roots: Vec<PathBuf>) -> Result<Self, GlobErr> {
+    /// Return a new DatasetRoots containing the roots of both sets
+    pub fn merge(&self, other: &Self) -> Self {
+        let mut roots = self.roots.clone();
+        for (root, data) in &other.roots {
+            roots.entry(root.clone()).or_insert_with(|| data.clone());
+        }
+        Self { roots }
+    }
+
+    /// Return a new DatasetRoots with only roots matching any of the given patterns
+    pub fn match_roots(&self, roots: Vec<(PathBuf, RootMatch)>) -> Result<Self, GlobErr> {
         let mut builder = GlobSetBuilder::new();
         let mut exact = HashSet::new();
+        let mut prefixes = Vec::new();
 
-        // Do exact match checks to avoid globbing on paths with potentially invalid glob syntax
-        for root in roots {
-            if self.roots.contains_key(&root) {
-                exact.insert(root);
-            } else {
-                builder.add(Glob::new(
-                    &root
-                        .to_str()
-                        .ok_or_else(|| GlobErr::Encoding(root.clone()))?,
-                )?);
+        for (root, mode) in roots {
+            match mode {
+                RootMatch::Exact => {
+                    exact.insert(root);
+                }
+                // Do exact match checks to avoid globbing on paths with potentially invalid
+                // glob syntax
+                RootMatch::Glob if self.roots.contains_key(&root) => {
+                    exact.insert(root);
+                }
+                RootMatch::Glob => {
+                    builder.add(Glob::new(
+                        &root
+                            .to_str()
+                            .ok_or_else(|| GlobErr::Encoding(root.clone()))?,
+                    )?);
+                }
+                RootMatch::Prefix => prefixes.push(root),
             }
         }
         let glob = builder.build()?;
         Ok(self
             .roots
             .iter()
-            .filter_map(|(root, data)| match data {
-                RootCategory::Derivative(ranges) => {
-                    if exact.contains(root) || glob.is_match(root) {
-                        Some((root.clone(), RootCategory::Derivative(ranges.clone())))
-                    } else {
-                        None
-                    }
-                }
-                RootCategory::Raw(ranges) => {
-                    if exact.contains(root) || glob.is_match(root) {
-                        Some((root.clone(), RootCategory::Raw(ranges.clone())))
-                    } else {
-                        None
-                    }
-                }
-                RootCategory::Labelled(label, ranges) => {
-                    if exact.contains(root) || glob.is_match(root) {
-                        Some((
-                            root.clone(),
-                            RootCategory::Labelled(label.clone(), ranges.clone()),
-                        ))
-                    } else {
-                        None
-                    }
-                }
+            .filter(|(root, _)| {
+                exact.contains(*root)
+                    || glob.is_match(root)
+                    || prefixes.iter().any(|prefix| root.starts_with(prefix))
             })
+            .map(|(root, data)| (root.clone(), data.clone()))
             .collect())
     }
 }
@@ -1,10 +1,12 @@
-use crate::py::pybidspath::create_pybidspath;
-use crate::py::pylayout::PyLayout;
+use crate::py::pybidspath::{build_path, create_pybidspath, read_tsv};
+use crate::py::pylayout::{PyCancelToken, PyLayout};
 use py::pydescription::{PyDatasetDescription, PyGeneratedBy, PySourceDataset};
 use py::pylayout_iterator::LayoutIterator;
+use py::pyparams::entity_query::PyNot;
 use standards::deref_key_alias;
 use crate::standards::get_key_alias;
 use pyo3::prelude::*;
+use std::collections::HashMap;
 
 pub mod layout;
 pub mod dataset_description;
@@ -14,16 +16,23 @@ pub mod utils;
 pub mod standards;
 pub mod serialize;
 pub mod errors;
+#[cfg(test)]
+mod test_utils;
 
 
 #[pyfunction]
 fn entity_long_to_short(e: &str) -> &str {
-    deref_key_alias(e).unwrap_or(e)
+    deref_key_alias(e, &standards::NO_EXTRA_ENTITIES).unwrap_or(e)
 }
 
 #[pyfunction]
 fn entity_short_to_long(e: &str) -> &str {
-    get_key_alias(e)
+    get_key_alias(e, &standards::NO_EXTRA_ENTITIES)
+}
+
+#[pyfunction]
+fn entity_aliases() -> HashMap<&'static str, &'static str> {
+    standards::entity_aliases(&standards::NO_EXTRA_ENTITIES)
 }
 
 /// A Python module implemented in Rust.
@@ -31,13 +40,18 @@ fn entity_short_to_long(e: &str) -> &str {
 #[pyo3(name = "_lib")]
 fn rsbids(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<PyLayout>()?;
+    m.add_class::<PyCancelToken>()?;
     m.add_class::<LayoutIterator>()?;
     m.add_class::<PyDatasetDescription>()?;
     m.add_class::<PyGeneratedBy>()?;
     m.add_class::<PySourceDataset>()?;
+    m.add_class::<PyNot>()?;
     m.add_function(wrap_pyfunction!(create_pybidspath, m)?)?;
+    m.add_function(wrap_pyfunction!(build_path, m)?)?;
+    m.add_function(wrap_pyfunction!(read_tsv, m)?)?;
     m.add_function(wrap_pyfunction!(entity_long_to_short, m)?)?;
     m.add_function(wrap_pyfunction!(entity_short_to_long, m)?)?;
+    m.add_function(wrap_pyfunction!(entity_aliases, m)?)?;
     Ok(())
 }
 
@@ -46,7 +60,7 @@ mod tests {
 
     use std::path::PathBuf;
 
-    use crate::layout::Layout;
+    use crate::layout::{Layout, LayoutOptions};
 
     #[test]
     fn it_works() {
@@ -56,6 +70,11 @@ mod tests {
 
     #[test]
     fn main() {
-        let _ = Layout::create(vec![PathBuf::from("data")], None, false);
+        let _ = Layout::create(
+            vec![PathBuf::from("data")],
+            None,
+            LayoutOptions::default(),
+            None,
+        );
     }
 }
@@ -4,7 +4,7 @@ use std::{
 };
 
 use itertools::Itertools;
-use rsbids::layout::Layout;
+use rsbids::layout::{Layout, LayoutOptions};
 
 fn main() {
     let args: Vec<_> = env::args().dropping(1).map(PathBuf::from).collect();
@@ -12,5 +12,11 @@ fn main() {
         eprintln!("No arguments given!");
         exit(1)
     }
-    let _ = Layout::create(args, None, false);
+    match Layout::create(args, None, LayoutOptions::default(), None) {
+        Ok(layout) => println!("{}", layout),
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1)
+        }
+    }
 }
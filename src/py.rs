@@ -4,3 +4,4 @@ pub mod pyparams;
 pub mod pylayout;
 pub mod pydescription;
 pub mod pylayout_iterator;
+pub mod pyjson;
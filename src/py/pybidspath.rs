@@ -1,15 +1,38 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use pyo3::{prelude::*, types::PyDict};
 
-use crate::layout::{bidspath::BidsPath, builders::bidspath_builder::BidsPathBuilder};
+use crate::{
+    layout::{
+        bidspath::{self, BidsPath},
+        builders::bidspath_builder::BidsPathBuilder,
+    },
+    standards::{ExtraEntities, NO_EXTRA_ENTITIES},
+};
 
-pub fn to_pybidspath(path: BidsPath) -> PyResult<PyObject> {
+/// Convert to a python BidsPath, optionally recording the layout it was taken from
+///
+/// Entities are named by their long names, including those declared in `extra`.
+pub fn to_pybidspath(
+    path: BidsPath,
+    extra: &ExtraEntities,
+    spec: Option<&PyObject>,
+) -> PyResult<PyObject> {
     Python::with_gil(|py| {
         let bidspathcls = py.import("rsbids.bidspath")?.getattr("BidsPath")?;
         let kwargs = PyDict::new(py);
-        kwargs.set_item("_entities", path.get_full_entities())?;
+        kwargs.set_item("_entities", path.get_full_entities(extra))?;
+        kwargs.set_item("_entities_ordered", path.entities_ordered(extra))?;
         kwargs.set_item("_dataset_root", path.get_root())?;
+        kwargs.set_item("_relative_path", path.relative_path())?;
+        kwargs.set_item("_head", path.get_head())?;
+        kwargs.set_item("_root_offset", path.root)?;
+        if let Some(spec) = spec {
+            kwargs.set_item("_spec", spec)?;
+        }
 
         bidspathcls
             .call((path.as_str(),), Some(kwargs))
@@ -20,8 +43,28 @@ pub fn to_pybidspath(path: BidsPath) -> PyResult<PyObject> {
 #[pyfunction]
 pub fn create_pybidspath(path: PathBuf) -> PyResult<PyObject> {
     let builder = BidsPathBuilder::new(path, 0)?;
-    match builder.spec_parse() {
-        Ok(bidspath) => to_pybidspath(bidspath),
-        Err(builder) => to_pybidspath(builder.get_bidspath()?),
+    match builder.spec_parse(&NO_EXTRA_ENTITIES, false) {
+        Ok(bidspath) => to_pybidspath(bidspath, &NO_EXTRA_ENTITIES, None),
+        Err(builder) => to_pybidspath(builder.get_bidspath()?, &NO_EXTRA_ENTITIES, None),
     }
 }
+
+#[pyfunction]
+#[pyo3(signature = (entities, *, root=None, allow_unknown=false))]
+pub fn build_path(
+    entities: &PyDict,
+    root: Option<PathBuf>,
+    allow_unknown: bool,
+) -> PyResult<PathBuf> {
+    let entities = entities
+        .iter()
+        .map(|(key, value)| Ok((key.extract()?, value.str()?.to_str()?)))
+        .collect::<PyResult<Vec<(&str, &str)>>>()?;
+    let root = root.as_deref().unwrap_or(Path::new(""));
+    Ok(BidsPath::build(&entities, root, allow_unknown)?)
+}
+
+#[pyfunction]
+pub fn read_tsv(path: PathBuf) -> PyResult<Vec<HashMap<String, String>>> {
+    Ok(bidspath::read_tsv(&path)?)
+}
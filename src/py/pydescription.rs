@@ -1,7 +1,7 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
 use itertools::Itertools;
-use pyo3::prelude::*;
+use pyo3::{prelude::*, types::PyType};
 
 use crate::dataset_description::{DatasetDescription, GeneratedBy, SourceDataset};
 
@@ -13,6 +13,24 @@ pub struct PyGeneratedBy {
 
 #[pymethods]
 impl PyGeneratedBy {
+    #[new]
+    #[pyo3(signature = (name, version=None, description=None, code_url=None, container=None))]
+    fn new(
+        name: String,
+        version: Option<String>,
+        description: Option<String>,
+        code_url: Option<String>,
+        container: Option<String>,
+    ) -> Self {
+        GeneratedBy {
+            name,
+            version,
+            description,
+            code_url,
+            container,
+        }
+        .into()
+    }
     #[getter]
     fn name(&self) -> &String {
         &self.inner.name
@@ -52,6 +70,11 @@ pub struct PySourceDataset {
 
 #[pymethods]
 impl PySourceDataset {
+    #[new]
+    #[pyo3(signature = (uri=None, doi=None, version=None))]
+    fn new(uri: Option<String>, doi: Option<String>, version: Option<String>) -> Self {
+        SourceDataset { uri, doi, version }.into()
+    }
     #[getter]
     fn uri(&self) -> Option<&String> {
         self.inner.uri.as_ref()
@@ -83,58 +106,129 @@ pub struct PyDatasetDescription {
 
 #[pymethods]
 impl PyDatasetDescription {
+    /// Create a new description. Remaining fields can be filled using the attribute
+    /// setters
+    #[new]
+    #[pyo3(signature = (name=None, *, bids_version=None, dataset_type=None, authors=None))]
+    fn new(
+        name: Option<String>,
+        bids_version: Option<String>,
+        dataset_type: Option<String>,
+        authors: Option<Vec<String>>,
+    ) -> Self {
+        Arc::new(DatasetDescription {
+            name,
+            bids_version,
+            dataset_type,
+            authors,
+            ..Default::default()
+        })
+        .into()
+    }
     #[getter]
     fn name(&self) -> Option<&String> {
         self.inner.name.as_ref()
     }
+    #[setter]
+    fn set_name(&mut self, value: Option<String>) {
+        Arc::make_mut(&mut self.inner).name = value;
+    }
     #[getter]
     fn bids_version(&self) -> Option<&String> {
         self.inner.bids_version.as_ref()
     }
+    #[setter]
+    fn set_bids_version(&mut self, value: Option<String>) {
+        Arc::make_mut(&mut self.inner).bids_version = value;
+    }
     #[getter]
     fn hed_version(&self) -> Option<Vec<String>> {
         self.inner.hed_version.clone()
     }
+    #[setter]
+    fn set_hed_version(&mut self, value: Option<Vec<String>>) {
+        Arc::make_mut(&mut self.inner).hed_version = value;
+    }
     #[getter]
     fn dataset_links(&self) -> Option<HashMap<String, String>> {
         self.inner.dataset_links.clone()
     }
+    #[setter]
+    fn set_dataset_links(&mut self, value: Option<HashMap<String, String>>) {
+        Arc::make_mut(&mut self.inner).dataset_links = value;
+    }
     #[getter]
     fn dataset_type(&self) -> Option<&String> {
         self.inner.dataset_type.as_ref()
     }
+    #[setter]
+    fn set_dataset_type(&mut self, value: Option<String>) {
+        Arc::make_mut(&mut self.inner).dataset_type = value;
+    }
     #[getter]
     fn license(&self) -> Option<&String> {
         self.inner.license.as_ref()
     }
+    #[setter]
+    fn set_license(&mut self, value: Option<String>) {
+        Arc::make_mut(&mut self.inner).license = value;
+    }
     #[getter]
     fn acknowledgements(&self) -> Option<&String> {
         self.inner.acknowledgements.as_ref()
     }
+    #[setter]
+    fn set_acknowledgements(&mut self, value: Option<String>) {
+        Arc::make_mut(&mut self.inner).acknowledgements = value;
+    }
     #[getter]
     fn how_to_acknowledge(&self) -> Option<&String> {
         self.inner.how_to_acknowledge.as_ref()
     }
+    #[setter]
+    fn set_how_to_acknowledge(&mut self, value: Option<String>) {
+        Arc::make_mut(&mut self.inner).how_to_acknowledge = value;
+    }
     #[getter]
     fn authors(&self) -> Option<Vec<String>> {
         self.inner.authors.clone()
     }
+    #[setter]
+    fn set_authors(&mut self, value: Option<Vec<String>>) {
+        Arc::make_mut(&mut self.inner).authors = value;
+    }
     #[getter]
     fn funding(&self) -> Option<Vec<String>> {
         self.inner.funding.clone()
     }
+    #[setter]
+    fn set_funding(&mut self, value: Option<Vec<String>>) {
+        Arc::make_mut(&mut self.inner).funding = value;
+    }
     #[getter]
     fn ethics_approvals(&self) -> Option<Vec<String>> {
         self.inner.ethics_approvals.clone()
     }
+    #[setter]
+    fn set_ethics_approvals(&mut self, value: Option<Vec<String>>) {
+        Arc::make_mut(&mut self.inner).ethics_approvals = value;
+    }
     #[getter]
     fn references_and_links(&self) -> Option<Vec<String>> {
         self.inner.references_and_links.clone()
     }
+    #[setter]
+    fn set_references_and_links(&mut self, value: Option<Vec<String>>) {
+        Arc::make_mut(&mut self.inner).references_and_links = value;
+    }
     #[getter]
     fn dataset_doi(&self) -> Option<&String> {
         self.inner.dataset_doi.as_ref()
     }
+    #[setter]
+    fn set_dataset_doi(&mut self, value: Option<String>) {
+        Arc::make_mut(&mut self.inner).dataset_doi = value;
+    }
     #[getter]
     fn generated_by(&self) -> Option<Vec<PyGeneratedBy>> {
         self.inner
@@ -142,6 +236,11 @@ impl PyDatasetDescription {
             .as_ref()
             .map(|g| g.iter().cloned().map_into().collect_vec())
     }
+    #[setter]
+    fn set_generated_by(&mut self, value: Option<Vec<PyGeneratedBy>>) {
+        Arc::make_mut(&mut self.inner).generated_by =
+            value.map(|v| v.into_iter().map(|item| item.inner).collect());
+    }
     #[getter]
     fn source_datasets(&self) -> Option<Vec<PySourceDataset>> {
         self.inner
@@ -149,6 +248,11 @@ impl PyDatasetDescription {
             .as_ref()
             .map(|g| g.iter().cloned().map_into().collect_vec())
     }
+    #[setter]
+    fn set_source_datasets(&mut self, value: Option<Vec<PySourceDataset>>) {
+        Arc::make_mut(&mut self.inner).source_datasets =
+            value.map(|v| v.into_iter().map(|item| item.inner).collect());
+    }
     #[getter]
     fn pipeline_description(&self) -> Option<PyGeneratedBy> {
         self.inner
@@ -156,6 +260,40 @@ impl PyDatasetDescription {
             .as_ref()
             .map(|g| g.clone().into())
     }
+    #[setter]
+    fn set_pipeline_description(&mut self, value: Option<PyGeneratedBy>) {
+        Arc::make_mut(&mut self.inner).pipeline_description = value.map(|v| v.inner);
+    }
+
+    /// Description of a derivative dataset generated from `source`
+    #[classmethod]
+    #[pyo3(signature = (source, generated_by, *, uri=None))]
+    fn derivative_scaffold(
+        _cls: &PyType,
+        source: &Self,
+        generated_by: PyGeneratedBy,
+        uri: Option<String>,
+    ) -> Self {
+        Arc::new(DatasetDescription::derivative_scaffold(
+            &source.inner,
+            generated_by.inner,
+            uri,
+        ))
+        .into()
+    }
+
+    /// Append a pipeline to `generated_by`
+    fn add_generated_by(&mut self, generated_by: PyGeneratedBy) {
+        Arc::make_mut(&mut self.inner)
+            .generated_by
+            .get_or_insert_with(Vec::new)
+            .push(generated_by.inner);
+    }
+
+    /// Write the description as json, into `dataset_description.json` if path is a directory
+    fn save(&self, path: PathBuf) -> PyResult<()> {
+        Ok(self.inner.save(&path)?)
+    }
 
     fn __repr__(&self) -> String {
         format!("{:#?}", self)
@@ -167,3 +305,9 @@ impl From<Arc<DatasetDescription>> for PyDatasetDescription {
         Self { inner: value }
     }
 }
+
+impl From<PyDatasetDescription> for Arc<DatasetDescription> {
+    fn from(value: PyDatasetDescription) -> Self {
+        value.inner
+    }
+}
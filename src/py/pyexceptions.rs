@@ -1,26 +1,44 @@
 use pyo3::{
-    exceptions::{PyIOError, PyKeyError, PyUnicodeError, PyValueError},
+    exceptions::{
+        asyncio::CancelledError, PyFileExistsError, PyIOError, PyKeyError, PyUnicodeError,
+        PyValueError,
+    },
     prelude::*,
 };
 
 pub use pyo3::PyResult;
 
-use crate::errors::{BidsPathErr, CacheErr, IterdirErr, QueryErr};
+use crate::errors::{
+    BidsPathErr, BuildErr, CacheErr, DatasetDescriptionErr, ExportErr, IterdirErr, MetadataReadErr,
+    QueryErr,
+};
 
 impl From<BidsPathErr> for PyErr {
     fn from(value: BidsPathErr) -> PyErr {
         match value {
             BidsPathErr::Encoding(..) => PyUnicodeError::new_err(format!("{}", value)),
-            BidsPathErr::Validation(..) => PyValueError::new_err(format!("{}", value)),
+            BidsPathErr::Validation(..) | BidsPathErr::EntityOrder(..) => {
+                PyValueError::new_err(format!("{}", value))
+            }
         }
     }
 }
 
+impl From<BuildErr> for PyErr {
+    fn from(value: BuildErr) -> Self {
+        PyValueError::new_err(format!("{}", value))
+    }
+}
+
 impl From<IterdirErr> for PyErr {
     fn from(value: IterdirErr) -> Self {
         match value {
             IterdirErr::Interrupt(err) => err,
             IterdirErr::Io(err) => PyIOError::new_err(err),
+            IterdirErr::Cancelled => CancelledError::new_err(format!("{}", value)),
+            IterdirErr::BidsIgnore(..) | IterdirErr::Include(..) | IterdirErr::Entity(..) => {
+                PyValueError::new_err(format!("{}", value))
+            }
         }
     }
 }
@@ -31,6 +49,14 @@ impl From<QueryErr> for PyErr {
             QueryErr::MissingVal(..)
             | QueryErr::GlobErr(..)
             | QueryErr::MutliErr(..)
+            | QueryErr::RegexErr(..)
+            | QueryErr::UnrelatedLayouts
+            | QueryErr::UnknownDatatype(..)
+            | QueryErr::InvalidSuffix(..)
+            | QueryErr::UnknownPipeline(..)
+            | QueryErr::SuffixWithExtension(..)
+            | QueryErr::NegativeRangeBound(..)
+            | QueryErr::ConflictingOptions(..)
             | QueryErr::AmbiguousQuery(..) => PyValueError::new_err(format!("{}", value)),
             QueryErr::MissingEntity(..) => PyKeyError::new_err(format!("{}", value)),
         }
@@ -39,6 +65,39 @@ impl From<QueryErr> for PyErr {
 
 impl From<CacheErr> for PyErr {
     fn from(value: CacheErr) -> Self {
-        PyIOError::new_err(format!("{}", value))
+        match value {
+            CacheErr::Index(err) => err.into(),
+            _ => PyIOError::new_err(format!("{}", value)),
+        }
+    }
+}
+
+impl From<DatasetDescriptionErr> for PyErr {
+    fn from(value: DatasetDescriptionErr) -> Self {
+        match value {
+            DatasetDescriptionErr::IoErr(err) => PyIOError::new_err(err),
+            DatasetDescriptionErr::JsonErr(err) => PyValueError::new_err(err.to_string()),
+        }
+    }
+}
+
+impl From<MetadataReadErr> for PyErr {
+    fn from(value: MetadataReadErr) -> Self {
+        match value {
+            MetadataReadErr::Io(err) => err.into(),
+            _ => PyValueError::new_err(format!("{}", value)),
+        }
+    }
+}
+
+impl From<ExportErr> for PyErr {
+    fn from(value: ExportErr) -> Self {
+        match value {
+            ExportErr::Io(err) => err.into(),
+            ExportErr::Exists(_) => PyFileExistsError::new_err(format!("{}", value)),
+            ExportErr::Collision { .. } | ExportErr::Json(_) => {
+                PyValueError::new_err(format!("{}", value))
+            }
+        }
     }
 }
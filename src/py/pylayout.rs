@@ -1,11 +1,15 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use itertools::Itertools;
-use pyo3::exceptions::{PyAttributeError, PyBaseException, PyException, PyKeyError, PyValueError};
+use pyo3::exceptions::{
+    PyAttributeError, PyBaseException, PyException, PyIndexError, PyKeyError, PyUserWarning,
+    PyValueError,
+};
 use pyo3::prelude::*;
-use pyo3::types::{PyBytes, PyType};
+use pyo3::types::{PyBytes, PyDict, PySlice, PyTuple, PyType};
 use serde::{Deserialize, Serialize};
 
 use super::pydescription::PyDatasetDescription;
@@ -14,17 +18,86 @@ use super::pyparams::derivatives::DerivativeSpec;
 use super::pyparams::entity_query::QueryParams;
 use super::{
     pybidspath::to_pybidspath,
+    pyjson::json_to_py,
     pyparams::{
+        datatype::DatatypeList,
         derivatives::{discover_derivatives, DerivativeSpecModes, DerivativesParam},
+        description::DescriptionsParam,
+        globs::GlobList,
         pathlist::PathList,
         scope::ScopeList,
+        suffix::SuffixList,
     },
 };
 use crate::dataset_description::DatasetDescription;
-use crate::errors::CacheErr;
-use crate::layout::cache::LayoutCache;
-use crate::layout::roots::RootCategory;
-use crate::layout::Layout;
+use crate::errors::{CacheErr, ExportErr, IterdirErr, ValidationIssue};
+use crate::layout::cache::{CacheFormat, LayoutCache};
+use crate::layout::entity_table::IndexSet;
+use crate::layout::export::ExportMode;
+use crate::layout::roots::{RootCategory, RootMatch};
+use crate::layout::{Layout, LayoutOptions, QueryTerms};
+use crate::standards::get_key_alias;
+use crate::utils::absolute_clean;
+
+#[derive(FromPyObject)]
+enum LayoutIndex<'a> {
+    Int(isize),
+    Slice(&'a PySlice),
+}
+
+/// Resolve the `derivatives` argument of a layout constructor, discovering derivatives
+/// under the single root in `paths` if requested
+fn unpack_derivatives(
+    paths: &[PathBuf],
+    derivatives: Option<DerivativesParam>,
+) -> PyResult<Option<Vec<DerivativeSpec>>> {
+    if let Some(d) = derivatives {
+        match d.unpack()? {
+            Some(DerivativeSpecModes::Set(d)) => Ok(Some(d)),
+            Some(DerivativeSpecModes::Discover) => match paths.first() {
+                Some(path) => {
+                    if paths.len() > 1 {
+                        Err(PyValueError::new_err(
+                            "derivatives=True can only be specified when a single root is provided",
+                        ))
+                    } else {
+                        Ok(discover_derivatives(Path::new(path))?)
+                    }
+                }
+                None => Err(PyValueError::new_err(
+                    "derivatives=True can only be specified when a root is provided",
+                )),
+            },
+            None => Ok(None),
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+/// Flag used to stop a layout being indexed on another thread
+#[pyclass(module = "rsbids", name = "CancelToken")]
+#[derive(Default)]
+pub struct PyCancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+#[pymethods]
+impl PyCancelToken {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    #[getter]
+    fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
 
 #[pyclass(module = "rsbids", name = "BidsLayout")]
 #[derive(Serialize, Deserialize)]
@@ -35,64 +108,250 @@ pub struct PyLayout {
 #[pymethods]
 impl PyLayout {
     #[new]
-    #[pyo3(signature = (roots=None, derivatives=None, validate=false, cache=None, reset_cache=false))]
+    #[pyo3(signature = (roots=None, derivatives=None, validate=false, cache=None, reset_cache=false, nthreads=1, update_cache=false, reindex_metadata=false, revalidate=false, follow_links=false, extra_entities=None, ignore_key_case=false, ignore_dirs=None, include=None, json_data_suffixes=None, descriptions=None, quiet=false, on_progress=None, progress_every=1000))]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        py: Python<'_>,
         roots: Option<PathList>,
         derivatives: Option<DerivativesParam>,
         validate: bool,
         cache: Option<PathBuf>,
         reset_cache: bool,
+        nthreads: usize,
+        update_cache: bool,
+        reindex_metadata: bool,
+        revalidate: bool,
+        follow_links: bool,
+        extra_entities: Option<BTreeMap<String, String>>,
+        ignore_key_case: bool,
+        ignore_dirs: Option<BTreeSet<String>>,
+        include: Option<GlobList>,
+        json_data_suffixes: Option<BTreeSet<String>>,
+        descriptions: Option<DescriptionsParam>,
+        quiet: bool,
+        on_progress: Option<&PyAny>,
+        progress_every: usize,
     ) -> PyResult<Self> {
         let paths = roots
             .map(|r| Ok::<_, PyErr>(r.unpack()?))
             .transpose()?
             .unwrap_or_else(|| Vec::new());
-        let derivatives = if let Some(d) = derivatives {
-            match d.unpack()? {
-                Some(DerivativeSpecModes::Set(d)) => Ok(Some(d)),
-                Some(DerivativeSpecModes::Discover) => match paths.first() {
-                    Some(path) => {
-                        if paths.len() > 1 {
-                            Err(PyValueError::new_err(
-                                "derivatives=True can only be specified when a single root is provided"
-                            ))
-                        } else {
-                            Ok(discover_derivatives(Path::new(path))?)
-                        }
-                    }
-                    None => Err(PyValueError::new_err(
-                        "derivatives=True can only be specified when a root is provided",
-                    )),
-                },
-                None => Ok(None),
-            }?
-        } else {
-            None
-        };
+        let derivatives = unpack_derivatives(&paths, derivatives)?;
         if let Some(db_path) = &cache {
             if !reset_cache && db_path.exists() {
-                return Self::load_with_roots(paths, derivatives, db_path.to_path_buf());
+                let layout = if update_cache || reindex_metadata {
+                    LayoutCache::rewrite(db_path.to_path_buf(), |mut layout| {
+                        if update_cache {
+                            layout = layout.update()?;
+                        } else if revalidate {
+                            layout = layout.revalidate(true);
+                        }
+                        if reindex_metadata {
+                            layout.reindex_metadata();
+                        }
+                        Ok(layout)
+                    })?
+                } else if revalidate {
+                    LayoutCache::load_validated(db_path.to_path_buf(), true)?
+                } else {
+                    LayoutCache::load(db_path.to_path_buf())?
+                };
+                return Self::from_cache(layout, paths, derivatives);
             }
         }
         let result = Self {
-            inner: Layout::create(paths, derivatives, validate)?,
+            inner: Layout::create_with_progress(
+                paths,
+                derivatives,
+                LayoutOptions {
+                    validate,
+                    nthreads,
+                    follow_links,
+                    extra_entities: extra_entities.unwrap_or_default(),
+                    ignore_key_case,
+                    ignore_dirs,
+                    include: include.map(Vec::try_from).transpose()?,
+                    json_data_suffixes,
+                    descriptions: DescriptionsParam::unpack(descriptions)?,
+                },
+                progress_every,
+                |found| match on_progress {
+                    Some(on_progress) => on_progress
+                        .call1((found,))
+                        .map(|_| ())
+                        .map_err(IterdirErr::Interrupt),
+                    None => Ok(()),
+                },
+            )?,
         };
         if let Some(db_path) = cache {
-            result.save(db_path)?;
+            LayoutCache::save(&result.inner, db_path)?;
+        }
+        if !quiet {
+            result.warn_invalid_paths(py)?;
         }
         Ok(result)
     }
 
+    /// Number of paths with each combination of values of two entities
+    fn crosstab(
+        &self,
+        row: &str,
+        col: &str,
+    ) -> PyResult<HashMap<&String, HashMap<&String, usize>>> {
+        self.inner.cross_tab(row, col).ok_or_else(|| {
+            let missing = if self.inner.count_by(row).is_none() {
+                row
+            } else {
+                col
+            };
+            PyKeyError::new_err(format!("Entity not found: '{}'", missing))
+        })
+    }
+
+    /// Entities that still have more than one value, and so must be filtered to select a
+    /// single path
+    fn ambiguous_entities(&self) -> HashMap<&str, Vec<&String>> {
+        let extra = &self.inner.options().extra_entities;
+        self.inner
+            .ambiguous_entities()
+            .into_iter()
+            .map(|(key, vals)| (get_key_alias(key, extra), vals))
+            .collect()
+    }
+
+    /// Longest directory shared by every path in the layout
+    fn common_prefix(&self) -> Option<PathBuf> {
+        self.inner.common_prefix()
+    }
+
+    /// Directory hierarchy of the layout as an indented string
+    #[pyo3(signature = (max_depth=None))]
+    fn tree(&self, max_depth: Option<usize>) -> String {
+        self.inner.tree(max_depth)
+    }
+
+    /// The indexed path at `path`, or `None` if it is not in the layout
+    fn get_file(cell: &PyCell<Self>, path: PathBuf) -> PyResult<Option<PyObject>> {
+        let layout = cell.to_object(cell.py());
+        let slf = cell.borrow();
+        let extra = &slf.inner.options().extra_entities;
+        slf.inner
+            .get_by_path(&path)
+            .map(|path| to_pybidspath(path, extra, Some(&layout)))
+            .transpose()
+    }
+
+    /// Distinct prefixes preceding the first entity directory of each path
+    fn heads(&self) -> Vec<&str> {
+        self.inner.heads()
+    }
+
+    /// Paths anywhere beneath a directory
+    fn files_under(cell: &PyCell<Self>, path: PathBuf) -> PyResult<Vec<PyObject>> {
+        let layout = cell.to_object(cell.py());
+        let slf = cell.borrow();
+        let extra = &slf.inner.options().extra_entities;
+        slf.inner
+            .files_under(&path)
+            .into_iter()
+            .map(|path| to_pybidspath(path, extra, Some(&layout)))
+            .collect()
+    }
+
+    /// Number of paths with each value of an entity
+    fn count_by(&self, entity: &str) -> PyResult<HashMap<&String, usize>> {
+        self.inner
+            .count_by(entity)
+            .ok_or_else(|| PyKeyError::new_err(format!("Entity not found: '{}'", entity)))
+    }
+
+    /// Distinct combinations of values of several entities
+    #[pyo3(signature = (entities, *, partial=false))]
+    fn unique_combinations(
+        &self,
+        entities: Vec<String>,
+        partial: bool,
+    ) -> PyResult<Vec<HashMap<String, String>>> {
+        self.inner
+            .unique_combinations(&entities, partial)
+            .ok_or_else(|| {
+                let missing = entities
+                    .iter()
+                    .find(|entity| self.inner.count_by(entity).is_none())
+                    .map(String::as_str)
+                    .unwrap_or_default();
+                PyKeyError::new_err(format!("Entity not found: '{}'", missing))
+            })
+    }
+
     #[getter]
     fn entities(&self) -> PyResult<HashMap<&str, Vec<&String>>> {
         Ok(self.inner.entity_fullkey_vals())
     }
 
+    /// Paths left out of the layout, each with the reason it could not be parsed
+    #[getter]
+    fn invalid_paths(&self) -> Vec<(String, String)> {
+        self.inner
+            .invalid_paths()
+            .iter()
+            .map(|err| (err.path_str(), err.to_string()))
+            .collect()
+    }
+
+    /// The paths of `invalid_paths` left out for not being valid unicode, with invalid
+    /// bytes replaced
+    #[getter]
+    fn skipped_paths(&self) -> Vec<String> {
+        self.inner.skipped_paths()
+    }
+
+    /// Check paths for common problems, returning one dict per issue found
+    fn validate(&self) -> Vec<HashMap<&'static str, String>> {
+        self.inner
+            .validate()
+            .into_iter()
+            .map(|issue| {
+                let mut record = HashMap::from([
+                    ("issue", issue.kind().to_string()),
+                    ("path", issue.path().to_string()),
+                    ("message", issue.to_string()),
+                ]);
+                match issue {
+                    ValidationIssue::DuplicateEntity(_, entity) => {
+                        record.insert("entity", entity);
+                    }
+                    ValidationIssue::EntityMismatch(_, entity, parent_val, file_val) => {
+                        record.insert("entity", entity);
+                        record.insert("parent_value", parent_val);
+                        record.insert("file_value", file_val);
+                    }
+                    _ => (),
+                }
+                record
+            })
+            .collect()
+    }
+
+    #[getter]
+    fn description_errors(&self) -> Vec<(PathBuf, String)> {
+        self.inner.description_errors().to_vec()
+    }
+
+    #[getter]
+    fn metadata_errors(&self) -> PyResult<Vec<(PathBuf, String)>> {
+        self.inner
+            .metadata_errors()
+            .map(|errors| errors.to_vec())
+            .ok_or_else(Self::unindexed_metadata_err)
+    }
+
     #[getter]
     fn metadata(&self) -> PyResult<HashMap<&str, Vec<&String>>> {
-        self.inner.metadata_key_vals().ok_or_else(|| {
-            PyAttributeError::new_err("Metadata must first be indexed by calling .index_metadata()")
-        })
+        self.inner
+            .metadata_key_vals()
+            .ok_or_else(Self::unindexed_metadata_err)
     }
 
     #[getter]
@@ -126,6 +385,45 @@ impl PyLayout {
         }
     }
 
+    /// Every root in the layout with its category, label, number of paths, and whether it
+    /// has a `dataset_description.json`
+    fn roots_detailed(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        self.inner
+            .roots_detailed()
+            .into_iter()
+            .map(|(root, category, count)| {
+                let dict = PyDict::new(py);
+                dict.set_item("path", root)?;
+                let (category_name, label) = match category {
+                    RootCategory::Raw(_) => ("raw", None),
+                    RootCategory::Derivative(_) => ("derivative", None),
+                    RootCategory::Labelled(label, _) => ("derivative", Some(label)),
+                };
+                dict.set_item("category", category_name)?;
+                dict.set_item("label", label)?;
+                dict.set_item("files", count)?;
+                dict.set_item(
+                    "has_description",
+                    category.root().get_description().is_some(),
+                )?;
+                Ok(dict.into())
+            })
+            .collect()
+    }
+
+    /// The most specific root containing `path`, with its category: `raw`, `derivative`,
+    /// or the label of a labelled derivative
+    fn root_for(&self, path: PathBuf) -> Option<(&PathBuf, &str)> {
+        self.inner
+            .root_for(&path)
+            .map(|(root, category)| (root, category.name()))
+    }
+
+    /// Description of a specific root of the layout
+    fn description_for(&self, root: PathBuf) -> Option<PyDatasetDescription> {
+        self.inner.description_for(&root).map(Into::into)
+    }
+
     #[getter]
     fn description(&self) -> PyResult<PyDatasetDescription> {
         fn try_with(
@@ -158,7 +456,7 @@ impl PyLayout {
             .inner
             .roots
             .derivative_keys()
-            .map(|s| s.to_owned())
+            .map(|s| (s.to_owned(), RootMatch::Exact))
             .collect_vec();
         if deriv_roots.len() == 0 {
             return Err(PyValueError::new_err("Layout has no derivatives"));
@@ -166,20 +464,81 @@ impl PyLayout {
         Ok(Self {
             inner: self
                 .inner
-                .query(None, Some(deriv_roots), None)
+                .query(None, Some(deriv_roots), None, false)
                 .expect("Unexpected error"),
         })
     }
 
-    #[pyo3(signature = (**entities))]
-    fn get(&self, entities: Option<QueryParams>) -> PyResult<PyLayout> {
-        let entities = entities.map(|entities| entities.unpack()).transpose()?;
+    #[pyo3(signature = (query=None, /, *, ignore_case=false, strict=false, numeric="strict", **entities))]
+    fn get(
+        &self,
+        query: Option<QueryParams>,
+        ignore_case: bool,
+        strict: bool,
+        numeric: &str,
+        entities: Option<QueryParams>,
+    ) -> PyResult<PyLayout> {
+        let coalesce = match numeric {
+            "strict" => false,
+            "coalesce" => true,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown numeric mode '{}'. Expected 'strict' or 'coalesce'",
+                    numeric
+                )))
+            }
+        };
+        // Keys are normalized before merging so that keyword arguments override the dict
+        // even when naming the same entity differently
+        let mut entities = match (query, entities) {
+            (None, None) => None,
+            (query, entities) => {
+                let mut merged = HashMap::new();
+                for params in [query, entities].into_iter().flatten() {
+                    merged.extend(self.inner.normalize_query(params.unpack()?));
+                }
+                Some(merged)
+            }
+        };
+        if let Some(entities) = &mut entities {
+            for terms in entities.values_mut() {
+                if ignore_case {
+                    *terms = std::mem::take(terms)
+                        .into_iter()
+                        .map(QueryTerms::ignore_case)
+                        .collect();
+                }
+                if coalesce {
+                    *terms = std::mem::take(terms)
+                        .into_iter()
+                        .map(QueryTerms::coalesce_numbers)
+                        .collect();
+                }
+            }
+        }
 
-        Ok(self.inner.query(entities, None, None).map(Self::from)?)
+        Ok(self.inner.query(entities, None, None, strict).map(Self::from)?)
     }
 
-    #[pyo3(signature = (*, root=None, scope=None))]
-    fn filter(&self, root: Option<PathList>, scope: Option<ScopeList>) -> PyResult<PyLayout> {
+    #[pyo3(signature = (*, root=None, scope=None, root_match="glob", root_scopes=None))]
+    fn filter(
+        &self,
+        root: Option<PathList>,
+        scope: Option<ScopeList>,
+        root_match: &str,
+        root_scopes: Option<Vec<(PathBuf, ScopeList)>>,
+    ) -> PyResult<PyLayout> {
+        let mode = match root_match {
+            "exact" => RootMatch::Exact,
+            "glob" => RootMatch::Glob,
+            "prefix" => RootMatch::Prefix,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown root_match '{}'. Expected 'exact', 'glob', or 'prefix'",
+                    root_match
+                )))
+            }
+        };
         // Normalize scope
         let scopes = scope
             .map(|scope| -> PyResult<_> { Ok(self.inner.get_scopes(scope.try_into()?)?) })
@@ -187,61 +546,176 @@ impl PyLayout {
             .flatten();
 
         // Normalize Root
-        let mut root = root.map(|root| root.unpack()).transpose()?;
-        if let Some(scopes) = scopes {
+        let mut root = root
+            .map(|root| -> PyResult<Vec<_>> {
+                Ok(root.unpack()?.into_iter().map(|r| (r, mode)).collect())
+            })
+            .transpose()?;
+        // Each pair contributes the roots of its scopes found at or beneath its root
+        let root_scopes = root_scopes
+            .map(|pairs| -> PyResult<Vec<_>> {
+                let mut roots = Vec::new();
+                for (root, scope) in pairs {
+                    roots.extend(
+                        self.inner
+                            .get_scoped_roots((root, mode), scope.try_into()?)?,
+                    );
+                }
+                Ok(roots)
+            })
+            .transpose()?;
+        for scopes in [scopes, root_scopes].into_iter().flatten() {
+            let scopes = scopes.into_iter().map(|r| (r, RootMatch::Exact));
             if let Some(root) = &mut root {
                 root.extend(scopes)
             } else {
-                root = Some(scopes)
+                root = Some(scopes.collect())
             }
         }
 
-        Ok(self.inner.query(None, root, None).map(Self::from)?)
+        Ok(self.inner.query(None, root, None, false).map(Self::from)?)
+    }
+
+    /// Select paths of any of the given datatypes, raising on datatypes not in the spec
+    fn by_datatype(&self, datatype: DatatypeList) -> PyResult<PyLayout> {
+        Ok(self
+            .inner
+            .by_datatype(datatype.try_into()?)
+            .map(Self::from)?)
+    }
+
+    /// Select paths with any of the given suffixes, raising on suffixes that are not
+    /// alphanumeric
+    fn by_suffix(&self, suffix: SuffixList) -> PyResult<PyLayout> {
+        Ok(self.inner.by_suffix(suffix.try_into()?).map(Self::from)?)
+    }
+
+    /// Select paths under roots generated by the named pipeline
+    fn by_pipeline(&self, name: &str) -> PyResult<PyLayout> {
+        Ok(self.inner.by_pipeline(name).map(Self::from)?)
+    }
+
+    /// Restore paths excluded only by their value of `entity`, inferring the other filters
+    /// from the current view
+    fn without_entity(&self, entity: &str) -> PyResult<PyLayout> {
+        Ok(self.inner.without_entity(entity).map(Self::from)?)
     }
 
     fn parse(&self, path: PathBuf) -> PyResult<PyObject> {
-        to_pybidspath(self.inner.parse(path)?)
+        to_pybidspath(
+            self.inner.parse(path)?,
+            &self.inner.options().extra_entities,
+            None,
+        )
     }
 
     #[getter]
-    fn one(&self) -> PyResult<PyObject> {
-        if self.inner.len() == 0 {
+    fn one(cell: &PyCell<Self>) -> PyResult<PyObject> {
+        let slf = cell.borrow();
+        if slf.inner.len() == 0 {
             Err(PyValueError::new_err("Layout is empty"))
-        } else if self.inner.len() > 1 {
+        } else if slf.inner.len() > 1 {
             let mut msg = String::from("Expected one path in layout, but got:\n");
-            msg.push_str(&self.inner.fmt_elided_list(5));
-            let problem_entities: HashMap<_, _> = self
-                .inner
-                .entity_key_vals()
-                .into_iter()
-                .filter_map(|(key, val)| {
-                    if val.len() > 1 {
-                        Some((key, val))
-                    } else {
-                        None
-                    }
-                })
-                .collect();
+            msg.push_str(&slf.inner.fmt_elided_list(5));
+            let problem_entities = slf.inner.ambiguous_entities();
             msg.push_str("\n\nThe following entities remain to be filtered:\n");
             msg.push_str(&format!("{:#?}", problem_entities));
             Err(PyValueError::new_err(msg))
         } else {
-            Ok(to_pybidspath(self.inner.get_path(0).unwrap())?)
+            Ok(to_pybidspath(
+                slf.inner.get_path(0).unwrap(),
+                &slf.inner.options().extra_entities,
+                Some(&cell.to_object(cell.py())),
+            )?)
         }
     }
 
-    fn index_metadata(mut slf: PyRefMut<'_, Self>) -> PyRefMut<'_, Self> {
-        slf.inner.index_metadata();
+    /// Return the first path of the layout, however many paths it has
+    fn first(cell: &PyCell<Self>) -> PyResult<PyObject> {
+        let slf = cell.borrow();
+        match slf.inner.get_path(0) {
+            Some(path) => Ok(to_pybidspath(
+                path,
+                &slf.inner.options().extra_entities,
+                Some(&cell.to_object(cell.py())),
+            )?),
+            None => Err(PyValueError::new_err("Layout is empty")),
+        }
+    }
+
+    fn index_metadata(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        let layout = &slf.inner;
+        slf.py().allow_threads(|| layout.index_metadata());
         slf
     }
 
-    fn __getitem__(&self, i: usize) -> PyResult<PyObject> {
-        match self.inner.get_path(i).map(|path| to_pybidspath(path)) {
-            Some(path) => path,
-            None => Err(PyKeyError::new_err(format!("Index {} out of range", i))),
+    fn __getitem__(cell: &PyCell<Self>, py: Python, index: LayoutIndex) -> PyResult<PyObject> {
+        let slf = cell.borrow();
+        match index {
+            LayoutIndex::Int(i) => {
+                let layout = cell.to_object(py);
+                let extra = &slf.inner.options().extra_entities;
+                let len = slf.inner.len() as isize;
+                let ix = if i < 0 { i + len } else { i };
+                match usize::try_from(ix)
+                    .ok()
+                    .and_then(|ix| slf.inner.get_path(ix))
+                    .map(|path| to_pybidspath(path, extra, Some(&layout)))
+                {
+                    Some(path) => path,
+                    None => Err(PyIndexError::new_err(format!("Index {} out of range", i))),
+                }
+            }
+            LayoutIndex::Slice(slice) => {
+                let indices = slice.indices(slf.inner.len() as std::os::raw::c_long)?;
+                // Views are always kept in index order, so they cannot be reversed
+                if indices.step < 0 {
+                    return Err(PyValueError::new_err(
+                        "Layouts cannot be sliced with a negative step",
+                    ));
+                }
+                let mask: IndexSet = (0..indices.slicelength)
+                    .filter_map(|k| {
+                        slf.inner
+                            .view_index((indices.start + k * indices.step) as usize)
+                    })
+                    .collect();
+                let layout = slf.inner.query(None, None, Some(&mask), false)?;
+                Ok(Self::from(layout).into_py(py))
+            }
         }
     }
 
+    /// One dict per path holding its entities and absolute path, e.g. for
+    /// `pandas.DataFrame.from_records`
+    fn to_records(&self) -> Vec<HashMap<String, String>> {
+        self.inner
+            .get_paths(false)
+            .map(|path| {
+                let mut record: HashMap<String, String> = path
+                    .get_full_entities(&self.inner.options().extra_entities)
+                    .into_iter()
+                    .map(|(key, val)| (key.to_string(), val.to_string()))
+                    .collect();
+                record.insert(
+                    "path".to_string(),
+                    absolute_clean(path.as_path()).to_string_lossy().to_string(),
+                );
+                record
+            })
+            .collect()
+    }
+
+    /// JSON document listing the roots (with their categories and descriptions) and
+    /// paths (with their entities) of the layout, for use outside python
+    fn to_json(&self) -> Result<String, ExportErr> {
+        self.inner.to_json()
+    }
+
+    fn __contains__(&self, path: PathBuf) -> bool {
+        self.inner.contains(&path)
+    }
+
     fn __len__(&self) -> usize {
         self.inner.len()
     }
@@ -280,20 +754,236 @@ impl PyLayout {
         repr
     }
 
-    fn __iter__(&self) -> LayoutIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> LayoutIterator {
+        let py = slf.py();
         LayoutIterator {
-            iter: self.inner.get_paths(),
+            iter: slf.inner.get_paths(false),
+            extra_entities: slf.inner.options().extra_entities.clone(),
+            layout: slf.into_py(py),
         }
     }
 
+    /// Iterate over the paths ordered by their path string
+    fn sorted_paths(slf: PyRef<'_, Self>) -> LayoutIterator {
+        let py = slf.py();
+        LayoutIterator {
+            iter: slf.inner.get_paths(true),
+            extra_entities: slf.inner.options().extra_entities.clone(),
+            layout: slf.into_py(py),
+        }
+    }
+
+    /// Metadata merged from all sidecars applying to a path, as strings
+    fn metadata_for(&self, path: PathBuf) -> PyResult<HashMap<String, String>> {
+        self.inner
+            .metadata_for(self.position(&path)?)
+            .ok_or_else(Self::unindexed_metadata_err)
+    }
+
+    /// Metadata merged from all sidecars applying to a path, as native python objects
+    fn get_raw_metadata(&self, py: Python, path: PathBuf) -> PyResult<PyObject> {
+        let metadata = self
+            .inner
+            .get_raw_metadata(self.position(&path)?)
+            .ok_or_else(Self::unindexed_metadata_err)?;
+        let dict = PyDict::new(py);
+        for (key, val) in metadata {
+            dict.set_item(key, json_to_py(py, &val)?)?;
+        }
+        Ok(dict.into())
+    }
+
+    /// Sidecars applying to a path, from most distant to nearest
+    fn sidecars_for(&self, path: PathBuf) -> PyResult<Vec<PathBuf>> {
+        self.inner
+            .sidecars_for(self.position(&path)?)
+            .ok_or_else(Self::unindexed_metadata_err)
+    }
+
     fn __eq__(&self, other: &Self) -> bool {
         self.inner.eq(&other.inner)
     }
 
+    fn __hash__(&self) -> u64 {
+        self.inner.content_hash()
+    }
+
+    /// Number of paths in the layout, ignoring any filters
+    #[getter]
+    fn total(&self) -> usize {
+        self.inner.num_paths()
+    }
+
+    /// Whether any paths have been filtered out of the layout
+    fn is_filtered(&self) -> bool {
+        self.inner.is_filtered()
+    }
+
     fn __bool__(&self) -> bool {
         self.inner.len() > 0
     }
 
+    fn __or__(&self, other: &Self) -> PyResult<Self> {
+        Ok(self.inner.union(&other.inner).map(Self::from)?)
+    }
+
+    fn __and__(&self, other: &Self) -> PyResult<Self> {
+        Ok(self.inner.intersect(&other.inner).map(Self::from)?)
+    }
+
+    fn __sub__(&self, other: &Self) -> PyResult<Self> {
+        Ok(self.inner.difference(&other.inner).map(Self::from)?)
+    }
+
+    /// Combine with a layout indexed separately, such as one built from other roots
+    fn concat(&self, other: &Self) -> PyResult<Self> {
+        Ok(self.inner.concat(&other.inner).map(Self::from)?)
+    }
+
+    /// Index an explicit list of files, inferring the root of each
+    #[classmethod]
+    #[pyo3(signature = (paths, *, validate=false, follow_links=false, extra_entities=None, ignore_key_case=false, descriptions=None))]
+    fn from_paths(
+        _cls: &PyType,
+        paths: PathList,
+        validate: bool,
+        follow_links: bool,
+        extra_entities: Option<BTreeMap<String, String>>,
+        ignore_key_case: bool,
+        descriptions: Option<DescriptionsParam>,
+    ) -> PyResult<Self> {
+        Ok(Self {
+            inner: Layout::from_paths(
+                paths.unpack()?,
+                LayoutOptions {
+                    validate,
+                    follow_links,
+                    extra_entities: extra_entities.unwrap_or_default(),
+                    ignore_key_case,
+                    descriptions: DescriptionsParam::unpack(descriptions)?,
+                    ..Default::default()
+                },
+            )?,
+        })
+    }
+
+    /// Index paths from a list of dicts, such as those returned by `to_records`
+    ///
+    /// Each dict needs a `"path"` key. Any other keys are taken as the entities of the
+    /// path, in place of those parsed from it.
+    #[classmethod]
+    #[pyo3(signature = (records, *, validate=false, extra_entities=None, ignore_key_case=false, descriptions=None))]
+    fn from_records(
+        _cls: &PyType,
+        records: Vec<HashMap<String, String>>,
+        validate: bool,
+        extra_entities: Option<BTreeMap<String, String>>,
+        ignore_key_case: bool,
+        descriptions: Option<DescriptionsParam>,
+    ) -> PyResult<Self> {
+        let records = records
+            .into_iter()
+            .map(|mut record| {
+                let path = record
+                    .remove("path")
+                    .ok_or_else(|| PyValueError::new_err("Each record must have a 'path' key"))?;
+                let entities = if record.is_empty() {
+                    None
+                } else {
+                    Some(record)
+                };
+                Ok((PathBuf::from(path), entities))
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(Self {
+            inner: Layout::from_records(
+                records,
+                LayoutOptions {
+                    validate,
+                    extra_entities: extra_entities.unwrap_or_default(),
+                    ignore_key_case,
+                    descriptions: DescriptionsParam::unpack(descriptions)?,
+                    ..Default::default()
+                },
+            )?,
+        })
+    }
+
+    /// Index a layout without blocking the event loop, returning a coroutine
+    ///
+    /// Cancelling the awaiting task stops the walk; no partial layout is returned.
+    #[classmethod]
+    #[pyo3(signature = (*args, **kwargs))]
+    fn create_async(cls: &PyType, args: &PyTuple, kwargs: Option<&PyDict>) -> PyResult<PyObject> {
+        let py = cls.py();
+        let create = py.import("rsbids._async")?.getattr("create_async")?;
+        let args = PyTuple::new(py, std::iter::once(cls.as_ref()).chain(args).collect_vec());
+        Ok(create.call(args, kwargs)?.into())
+    }
+
+    /// Index a layout with the GIL released, stopping once `token` is cancelled
+    ///
+    /// Backs `create_async`, which runs it on a worker thread. `on_progress` is called on
+    /// that thread with the number of files found after every `progress_every` files.
+    #[classmethod]
+    #[pyo3(signature = (roots, derivatives, token, *, validate=false, follow_links=false, extra_entities=None, ignore_key_case=false, ignore_dirs=None, include=None, json_data_suffixes=None, descriptions=None, quiet=false, on_progress=None, progress_every=1000))]
+    #[allow(clippy::too_many_arguments)]
+    fn _create_cancellable(
+        cls: &PyType,
+        roots: Option<PathList>,
+        derivatives: Option<DerivativesParam>,
+        token: PyRef<PyCancelToken>,
+        validate: bool,
+        follow_links: bool,
+        extra_entities: Option<BTreeMap<String, String>>,
+        ignore_key_case: bool,
+        ignore_dirs: Option<BTreeSet<String>>,
+        include: Option<GlobList>,
+        json_data_suffixes: Option<BTreeSet<String>>,
+        descriptions: Option<DescriptionsParam>,
+        quiet: bool,
+        on_progress: Option<PyObject>,
+        progress_every: usize,
+    ) -> PyResult<Self> {
+        let paths = roots.map(|r| r.unpack()).transpose()?.unwrap_or_default();
+        let derivatives = unpack_derivatives(&paths, derivatives)?;
+        let options = LayoutOptions {
+            validate,
+            follow_links,
+            extra_entities: extra_entities.unwrap_or_default(),
+            ignore_key_case,
+            ignore_dirs,
+            include: include.map(Vec::try_from).transpose()?,
+            json_data_suffixes,
+            descriptions: DescriptionsParam::unpack(descriptions)?,
+            ..Default::default()
+        };
+        let cancelled = token.cancelled.clone();
+        let inner = cls.py().allow_threads(|| {
+            Layout::create_cancellable(
+                paths,
+                derivatives,
+                options,
+                &cancelled,
+                progress_every,
+                |found| match &on_progress {
+                    Some(on_progress) => Python::with_gil(|py| {
+                        on_progress
+                            .call1(py, (found,))
+                            .map(|_| ())
+                            .map_err(IterdirErr::Interrupt)
+                    }),
+                    None => Ok(()),
+                },
+            )
+        })?;
+        let result = Self { inner };
+        if !quiet {
+            result.warn_invalid_paths(cls.py())?;
+        }
+        Ok(result)
+    }
+
     #[classmethod]
     fn load(_cls: &PyType, path: PathBuf) -> PyResult<Self> {
         Ok(Self {
@@ -301,11 +991,64 @@ impl PyLayout {
         })
     }
 
-    pub fn save(&self, path: PathBuf) -> PyResult<()> {
-        LayoutCache::save(&self.inner, path)?;
+    /// Check whether any root directory was modified since the layout was indexed
+    fn is_stale(&self) -> bool {
+        self.inner.is_stale()
+    }
+
+    /// Paths in view that still exist on disk
+    fn filter_existing(&self) -> Self {
+        Self {
+            inner: self.inner.filter_existing(),
+        }
+    }
+
+    /// Paths in view that no longer exist on disk
+    fn missing_files(cell: &PyCell<Self>) -> PyResult<Vec<PyObject>> {
+        let layout = cell.to_object(cell.py());
+        let slf = cell.borrow();
+        let extra = &slf.inner.options().extra_entities;
+        slf.inner
+            .missing_files()
+            .into_iter()
+            .map(|path| to_pybidspath(path, extra, Some(&layout)))
+            .collect()
+    }
+
+    #[pyo3(signature = (path, *, format="binary", compress=false))]
+    pub fn save(&self, path: PathBuf, format: &str, compress: bool) -> PyResult<()> {
+        let format = match format {
+            "binary" => CacheFormat::Binary,
+            "filelist" => CacheFormat::FileList,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown cache format '{}'. Expected 'binary' or 'filelist'",
+                    format
+                )))
+            }
+        };
+        LayoutCache::save_as(&self.inner, path, format, compress)?;
         Ok(())
     }
 
+    /// Copy or link the paths in view into `dest`, preserving their paths relative to
+    /// the directory shared by their roots
+    #[pyo3(signature = (dest, mode="symlink"))]
+    pub fn export(&self, dest: PathBuf, mode: &str) -> PyResult<()> {
+        let mode = match mode {
+            "copy" => ExportMode::Copy,
+            "symlink" => ExportMode::Symlink,
+            "hardlink" => ExportMode::Hardlink,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown export mode '{}'. Expected 'copy', 'symlink', or 'hardlink'",
+                    mode
+                )))
+            }
+        };
+        Ok(self.inner.export(&dest, mode)?)
+    }
+
     pub fn clone(&self) -> Self {
         Self {
             inner: self.inner.deep_clone(),
@@ -331,6 +1074,38 @@ enum Category {
     Labelled(String),
 }
 impl PyLayout {
+    fn position(&self, path: &Path) -> PyResult<usize> {
+        self.inner.position(path).ok_or_else(|| {
+            PyKeyError::new_err(format!("'{}' is not in the layout", path.to_string_lossy()))
+        })
+    }
+
+    /// Emit a single `UserWarning` counting the paths left out because they could not be
+    /// parsed
+    fn warn_invalid_paths(&self, py: Python<'_>) -> PyResult<()> {
+        let count = self.inner.invalid_paths().len();
+        if count == 0 {
+            return Ok(());
+        }
+        PyErr::warn(
+            py,
+            py.get_type::<PyUserWarning>(),
+            &format!(
+                "{} file{} could not be parsed as BIDS and {} left out of the layout. See \
+                 `invalid_paths` for the full list, or pass `quiet=True` to silence this \
+                 warning",
+                count,
+                if count == 1 { "" } else { "s" },
+                if count == 1 { "was" } else { "were" },
+            ),
+            1,
+        )
+    }
+
+    fn unindexed_metadata_err() -> PyErr {
+        PyAttributeError::new_err("Metadata must first be indexed by calling .index_metadata()")
+    }
+
     fn set_category(layout: &mut Layout, root: &Path, category: Category) -> PyResult<()> {
         let result = match category {
             Category::Raw => layout.roots.set_category(&root, RootCategory::Raw),
@@ -349,12 +1124,11 @@ impl PyLayout {
             ))),
         }
     }
-    pub fn load_with_roots(
+    pub fn from_cache(
+        mut layout: Layout,
         roots: Vec<PathBuf>,
         derivatives: Option<Vec<DerivativeSpec>>,
-        db_path: PathBuf,
     ) -> PyResult<Self> {
-        let mut layout = LayoutCache::load(db_path)?;
         for root in &roots {
             Self::set_category(&mut layout, &root, Category::Raw)?
         }
@@ -373,11 +1147,11 @@ impl PyLayout {
         let all_roots = roots
             .iter()
             .chain(derivatives.iter().flatten().flat_map(|d| &d.paths))
-            .map(|s| s.to_owned())
+            .map(|s| (s.to_owned(), RootMatch::Glob))
             .collect_vec();
         Ok(Self {
             inner: layout
-                .query(None, Some(all_roots), None)
+                .query(None, Some(all_roots), None, false)
                 .expect("Unexpected Error"),
         })
     }
@@ -1,12 +1,14 @@
 use pyo3::prelude::*;
 
-use crate::layout::BidsPathViewIterator;
+use crate::{layout::BidsPathViewIterator, standards::ExtraEntities};
 
 use super::pybidspath::to_pybidspath;
 
 #[pyclass(module = "rsbids", name = "BidsLayoutIterator")]
 pub struct LayoutIterator {
     pub iter: BidsPathViewIterator,
+    pub extra_entities: ExtraEntities,
+    pub layout: PyObject,
 }
 
 #[pymethods]
@@ -16,6 +18,9 @@ impl LayoutIterator {
     }
 
     fn __next__(mut slf: PyRefMut<'_, Self>) -> PyResult<Option<PyObject>> {
-        slf.iter.next().map(|obj| to_pybidspath(obj)).transpose()
+        let layout = slf.layout.clone();
+        let path = slf.iter.next();
+        path.map(|obj| to_pybidspath(obj, &slf.extra_entities, Some(&layout)))
+            .transpose()
     }
 }
\ No newline at end of file
@@ -2,5 +2,9 @@ pub mod derivatives;
 pub mod pathlist;
 pub mod iterable;
 pub mod scope;
+pub mod globs;
 pub mod pyiterable;
-pub mod entity_query;
\ No newline at end of file
+pub mod entity_query;
+pub mod datatype;
+pub mod suffix;
+pub mod description;
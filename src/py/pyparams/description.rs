@@ -0,0 +1,42 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use pyo3::{FromPyObject, PyResult};
+
+use crate::{dataset_description::DatasetDescription, py::pydescription::PyDatasetDescription};
+
+/// A description given directly for a root, either as a `DatasetDescription` or the text
+/// of a `dataset_description.json`
+#[derive(FromPyObject)]
+pub enum DescriptionParam {
+    Description(PyDatasetDescription),
+    Json(String),
+}
+
+impl DescriptionParam {
+    pub fn unpack(self) -> PyResult<Arc<DatasetDescription>> {
+        Ok(match self {
+            Self::Description(description) => description.into(),
+            Self::Json(json) => Arc::new(DatasetDescription::try_from(json)?),
+        })
+    }
+}
+
+/// Descriptions keyed by the root they describe
+#[derive(FromPyObject)]
+#[pyo3(transparent)]
+pub struct DescriptionsParam(HashMap<PathBuf, DescriptionParam>);
+
+impl DescriptionsParam {
+    /// Unpack the descriptions given to a layout constructor, if any
+    pub fn unpack(param: Option<Self>) -> PyResult<BTreeMap<PathBuf, Arc<DatasetDescription>>> {
+        param
+            .into_iter()
+            .flat_map(|param| param.0)
+            .map(|(root, description)| Ok((root, description.unpack()?)))
+            .collect()
+    }
+}
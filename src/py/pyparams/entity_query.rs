@@ -1,24 +1,132 @@
 use std::collections::HashMap;
 
-use pyo3::{FromPyObject, PyResult};
+use pyo3::{
+    exceptions::PyTypeError, pyclass, pymethods, types::PySlice, FromPyObject, PyAny, PyResult,
+};
 
-use crate::{layout::QueryTerms, pyiterable};
+use crate::{errors::QueryErr, layout::QueryTerms, pyiterable};
+
+/// A compiled python regex (`re.Pattern`), unpacked into its pattern string
+pub struct PyPattern(String);
+
+impl<'a> FromPyObject<'a> for PyPattern {
+    fn extract(ob: &'a PyAny) -> PyResult<Self> {
+        let pattern_cls = ob.py().import("re")?.getattr("Pattern")?;
+        if !ob.is_instance(pattern_cls)? {
+            return Err(PyTypeError::new_err("Expected a compiled re.Pattern"));
+        }
+        let mut pattern: String = ob.getattr("pattern")?.extract()?;
+        // Translate the python flags that have a direct inline equivalent
+        let flags: u32 = ob.getattr("flags")?.extract()?;
+        let inline = [(2, 'i'), (8, 'm'), (16, 's'), (64, 'x')]
+            .into_iter()
+            .filter_map(|(flag, c)| if flags & flag != 0 { Some(c) } else { None })
+            .collect::<String>();
+        if !inline.is_empty() {
+            pattern = format!("(?{}){}", inline, pattern);
+        }
+        Ok(Self(pattern))
+    }
+}
+
+/// A python `slice` or `range` of integers, e.g. `slice(2, None)` or `range(1, 4)`. Like
+/// python, the stop value is exclusive. Stepped ranges are rejected here, leaving them
+/// to be iterated as individual numbers. Bounds are checked to be non-negative when
+/// converted into [`QueryTerms`].
+pub struct PyNumRange {
+    start: Option<i64>,
+    end: Option<i64>,
+}
+
+impl<'a> FromPyObject<'a> for PyNumRange {
+    fn extract(ob: &'a PyAny) -> PyResult<Self> {
+        let range_cls = ob.py().import("builtins")?.getattr("range")?;
+        if !ob.is_instance_of::<PySlice>() && !ob.is_instance(range_cls)? {
+            return Err(PyTypeError::new_err("Expected a slice or range"));
+        }
+        let step: Option<i64> = ob.getattr("step")?.extract()?;
+        if !matches!(step, None | Some(1)) {
+            return Err(PyTypeError::new_err(
+                "Stepped ranges are matched as numbers",
+            ));
+        }
+        Ok(Self {
+            start: ob.getattr("start")?.extract()?,
+            end: ob.getattr("stop")?.extract()?,
+        })
+    }
+}
+
+/// Query term excluding a single entity value, e.g. `layout.get(run=Not("01"))`
+#[pyclass(module = "rsbids", name = "Not")]
+#[derive(Clone)]
+pub struct PyNot {
+    #[pyo3(get)]
+    value: String,
+}
+
+#[pymethods]
+impl PyNot {
+    #[new]
+    fn new(value: String) -> Self {
+        Self { value }
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Not({:?})", self.value)
+    }
+}
+
+/// Python's `Ellipsis`, selecting every path with any value for the key, e.g.
+/// `layout.get(SliceTiming=...)`
+pub struct PyEllipsis;
+
+impl<'a> FromPyObject<'a> for PyEllipsis {
+    fn extract(ob: &'a PyAny) -> PyResult<Self> {
+        if ob.is(ob.py().Ellipsis().as_ref(ob.py())) {
+            Ok(Self)
+        } else {
+            Err(PyTypeError::new_err("Expected Ellipsis"))
+        }
+    }
+}
 
 #[derive(pyo3::FromPyObject)]
 pub enum QueryPrimitives {
+    Present(PyEllipsis),
     String(String),
     Bool(bool),
     Number(u64),
+    Regex(PyPattern),
+    Not(PyNot),
+    Range(PyNumRange),
 }
 
-impl From<Option<QueryPrimitives>> for QueryTerms {
-    fn from(value: Option<QueryPrimitives>) -> Self {
-        match value {
-            Some(QueryPrimitives::Bool(b)) => Self::Bool(b),
+impl TryFrom<Option<QueryPrimitives>> for QueryTerms {
+    type Error = QueryErr;
+    fn try_from(value: Option<QueryPrimitives>) -> Result<Self, Self::Error> {
+        Ok(match value {
+            Some(QueryPrimitives::Present(_)) => Self::Present,
+            // Booleans check for the key rather than matching JSON `true`/`false`
+            // values, which are queried as strings
+            Some(QueryPrimitives::Bool(b)) => b.into(),
             Some(QueryPrimitives::String(s)) => Self::String(s),
             Some(QueryPrimitives::Number(x)) => Self::Number(x),
+            Some(QueryPrimitives::Regex(PyPattern(p))) => Self::regex(&p)?,
+            Some(QueryPrimitives::Not(n)) => Self::Not(n.value),
+            Some(QueryPrimitives::Range(r)) => {
+                let bound = |b: Option<i64>| {
+                    b.map(|b| u64::try_from(b).map_err(|_| QueryErr::NegativeRangeBound(b)))
+                        .transpose()
+                };
+                Self::NumRange {
+                    start: bound(r.start)?,
+                    end: bound(r.end)?,
+                    inclusive: false,
+                }
+            }
             None => Self::Any,
-        }
+        })
     }
 }
 
@@ -33,7 +141,14 @@ impl QueryParams<'_> {
         let mut result: HashMap<String, Vec<QueryTerms>> = HashMap::new();
         for (key, val) in self.0 {
             if let Some(val) = val {
-                result.insert(key, val.try_into()?);
+                let primitives: Vec<Option<QueryPrimitives>> = val.try_into()?;
+                result.insert(
+                    key,
+                    primitives
+                        .into_iter()
+                        .map(QueryTerms::try_from)
+                        .collect::<Result<_, _>>()?,
+                );
             }
         }
         Ok(result)
@@ -25,14 +25,6 @@ macro_rules! pyiterable {
             Iterable(crate::py::pyparams::pyiterable::PyIterable<$inner_ty>),
         }
 
-        impl<'a> pyo3::FromPyObject<'a> for crate::py::pyparams::pyiterable::PyIterable<$inner_ty> {
-            fn extract(ob: &'a pyo3::PyAny) -> pyo3::PyResult<Self> {
-                Ok(Self {
-                    data: pyo3::Python::with_gil(|py| Self::collect(py, ob))?,
-                })
-            }
-        }
-
         impl<'a, J> TryFrom<$enum_name<'a>> for Vec<J>
         where
             J: From<$inner_ty>,
@@ -73,4 +65,12 @@ impl<'a, I: FromPyObject<'a>> PyIterable<I> {
         }
     }
 
-}
\ No newline at end of file
+}
+
+impl<'a, I: FromPyObject<'a>> FromPyObject<'a> for PyIterable<I> {
+    fn extract(ob: &'a PyAny) -> PyResult<Self> {
+        Ok(Self {
+            data: Self::collect(ob.py(), ob)?,
+        })
+    }
+}
@@ -0,0 +1,3 @@
+use crate::pyiterable;
+
+pyiterable!(SuffixList<String>);
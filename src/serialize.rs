@@ -40,3 +40,28 @@ pub fn deserialize<'de, T: Deserialize<'de>, D: Deserializer<'de>>(
     deserializer.deserialize_option(OnceCellVisitor(PhantomData))
 }
 
+
+#[cfg(test)]
+mod tests {
+    use once_cell::sync::OnceCell;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct Cells {
+        #[serde(serialize_with = "super::serialize", deserialize_with = "super::deserialize")]
+        full: OnceCell<Vec<String>>,
+        #[serde(serialize_with = "super::serialize", deserialize_with = "super::deserialize")]
+        empty: OnceCell<Vec<String>>,
+    }
+
+    #[test]
+    fn roundtrip_oncecell() {
+        let cells = Cells {
+            full: OnceCell::with_value(vec!["a".to_string(), "b".to_string()]),
+            empty: OnceCell::new(),
+        };
+        let decoded: Cells = bincode::deserialize(&bincode::serialize(&cells).unwrap()).unwrap();
+        assert_eq!(decoded.full.get(), cells.full.get());
+        assert!(decoded.empty.get().is_none());
+    }
+}
@@ -1,69 +1,182 @@
 use bimap::BiMap;
 use once_cell::sync::Lazy;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-pub fn get_key_alias(key: &str) -> &str {
-    match BIDS_ENTITIES.get_by_left(key) {
-        Some(key) => key,
-        None => key,
+use crate::errors::EntityErr;
+
+/// Entities declared by users in addition to those of the spec, mapping short names to
+/// long names
+pub type ExtraEntities = BTreeMap<String, String>;
+
+/// For lookups outside of a layout, where only the entities of the spec are known
+pub static NO_EXTRA_ENTITIES: ExtraEntities = BTreeMap::new();
+
+/// Long name of an entity given by its short name, or `key` unchanged if it isn't one
+pub fn get_key_alias<'a>(key: &'a str, extra: &'a ExtraEntities) -> &'a str {
+    if let Some(long) = BIDS_ENTITIES.get_by_left(key) {
+        return long;
+    }
+    extra.get(key).map(String::as_str).unwrap_or(key)
+}
+
+/// Short name of an entity given by its long name
+///
+/// Long names are matched exactly, as they are spelled the same way as keys of the
+/// sidecar metadata (e.g. `Modality`).
+pub fn deref_key_alias<'a>(key: &'a str, extra: &'a ExtraEntities) -> Option<&'a str> {
+    if let Some(short) = BIDS_ENTITIES.get_by_right(key) {
+        return Some(short);
     }
+    extra
+        .iter()
+        .find(|(_, long)| *long == key)
+        .map(|(short, _)| short.as_str())
 }
 
-pub fn deref_key_alias(key: &str) -> Option<&str> {
-     BIDS_ENTITIES.get_by_right(key).copied()
+/// Short name of the known entity matching `key` ignoring case, such as `sub` for `Sub` or
+/// `SUB`. Only keys are folded, never values.
+pub fn fold_key_case<'a>(key: &str, extra: &'a ExtraEntities) -> Option<&'a str> {
+    known_entities(extra)
+        .find(|(short, _)| short.eq_ignore_ascii_case(key))
+        .map(|(short, _)| short)
 }
 
-pub fn check_entity(entity: &str) -> bool {
+/// Entities of the spec followed by those declared in `extra`, as (short, long) pairs
+fn known_entities(extra: &ExtraEntities) -> impl Iterator<Item = (&str, &str)> {
+    BIDS_ENTITIES
+        .iter()
+        .map(|(short, long)| (*short, *long))
+        .chain(
+            extra
+                .iter()
+                .map(|(short, long)| (short.as_str(), long.as_str())),
+        )
+}
+
+/// Long names accepted in queries in addition to those of [`BIDS_ENTITIES`], which keeps
+/// the names used by pybids
+const QUERY_ALIASES: [(&str, &str); 2] = [("description", "desc"), ("staining", "stain")];
+
+/// Short name of an entity queried by `key`, which may be its short or long name or an
+/// entry of [`QUERY_ALIASES`]
+pub fn deref_query_alias<'a>(key: &'a str, extra: &'a ExtraEntities) -> Option<&'a str> {
+    deref_key_alias(key, extra).or_else(|| {
+        QUERY_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == key)
+            .map(|(_, short)| *short)
+    })
+}
+
+/// Check if `key` is the short or long name of a known entity
+pub fn is_known_key(key: &str, extra: &ExtraEntities) -> bool {
+    deref_query_alias(key, extra).is_some() || get_key_alias(key, extra) != key
+}
+
+/// Every known entity, including those declared in `extra`, mapped from short to long name
+pub fn entity_aliases(extra: &ExtraEntities) -> HashMap<&str, &str> {
+    known_entities(extra).collect()
+}
+
+/// Check if `entity` is the short name of a known entity
+///
+/// With `ignore_case`, names differing only in case from a known entity (`Sub`, `SUB`) are
+/// recognized too. Such keys are still indexed as spelled, so they never collide with the
+/// entity they resemble.
+pub fn check_entity(entity: &str, extra: &ExtraEntities, ignore_case: bool) -> bool {
     BIDS_ENTITIES.contains_left(entity)
+        || extra.contains_key(entity)
+        || (ignore_case && fold_key_case(entity, extra).is_some())
 }
 
-pub static BIDS_ENTITIES: Lazy<BiMap<&'static str, &'static str>> = Lazy::new(|| {
-    {
-        [
-            ("sub", "subject"),
-            ("ses", "session"),
-            ("datatype", "datatype"),
-            ("extension", "extension"),
-            ("suffix", "suffix"),
-            ("sample", "sample"),
-            ("task", "task"),
-            ("tracksys", "tracksys"),
-            ("acq", "acquisition"),
-            ("ce", "ceagent"),
-            ("stain", "staining"),
-            ("trc", "tracer"),
-            ("rec", "reconstruction"),
-            ("dir", "direction"),
-            ("run", "run"),
-            ("proc", "proc"),
-            ("mod", "modality"),
-            ("echo", "echo"),
-            ("flip", "flip"),
-            ("inv", "inv"),
-            ("mt", "mt"),
-            ("part", "part"),
-            ("recording", "recording"),
-            ("space", "space"),
-            ("chunk", "chunk"),
-            ("split", "split"),
-            ("atlas", "atlas"),
-            ("roi", "roi"),
-            ("label", "label"),
-            ("from", "from"),
-            ("to", "to"),
-            ("mode", "mode"),
-            ("hemi", "hemi"),
-            ("res", "res"),
-            ("den", "den"),
-            ("model", "model"),
-            ("subset", "subset"),
-            ("desc", "desc"),
-        ]
+/// Check that entities declared by users don't rename an entity of the spec, or share a
+/// long name with another entity
+pub fn check_extra_entities(extra: &ExtraEntities) -> Result<(), EntityErr> {
+    for (short, long) in extra {
+        let existing = BIDS_ENTITIES
+            .get_by_left(short.as_str())
+            .map(|l| (short.as_str(), *l))
+            .or_else(|| {
+                BIDS_ENTITIES
+                    .get_by_right(long.as_str())
+                    .map(|s| (*s, long.as_str()))
+            })
+            .or_else(|| {
+                extra
+                    .iter()
+                    .find(|(s, l)| *l == long && *s != short)
+                    .map(|(s, l)| (s.as_str(), l.as_str()))
+            });
+        match existing {
+            Some((s, l)) if s == short && l == long => continue,
+            Some((s, l)) => {
+                return Err(EntityErr::Conflict {
+                    short: short.clone(),
+                    long: long.clone(),
+                    existing: format!("{}/{}", s, l),
+                })
+            }
+            None => (),
+        }
     }
-    .iter()
-    .cloned()
-    .collect()
-});
+    Ok(())
+}
+
+/// Entities in the canonical order they appear in bids filenames, as (short, long) pairs.
+/// The non-filename keys `datatype`, `extension` and `suffix` come first.
+pub static BIDS_ENTITY_ORDER: [(&str, &str); 41] = [
+    ("datatype", "datatype"),
+    ("extension", "extension"),
+    ("suffix", "suffix"),
+    ("sub", "subject"),
+    ("ses", "session"),
+    ("sample", "sample"),
+    ("task", "task"),
+    ("tracksys", "tracksys"),
+    ("acq", "acquisition"),
+    ("nuc", "nucleus"),
+    ("voi", "volume"),
+    ("ce", "ceagent"),
+    ("trc", "tracer"),
+    ("stain", "stain"),
+    ("rec", "reconstruction"),
+    ("dir", "direction"),
+    ("run", "run"),
+    ("mod", "modality"),
+    ("echo", "echo"),
+    ("flip", "flip"),
+    ("inv", "inversion"),
+    ("mt", "mtransfer"),
+    ("part", "part"),
+    ("proc", "processing"),
+    ("hemi", "hemi"),
+    ("space", "space"),
+    ("split", "split"),
+    ("recording", "recording"),
+    ("chunk", "chunk"),
+    ("seg", "segmentation"),
+    ("atlas", "atlas"),
+    ("roi", "roi"),
+    ("from", "from"),
+    ("to", "to"),
+    ("mode", "mode"),
+    ("res", "res"),
+    ("den", "den"),
+    ("model", "model"),
+    ("subset", "subset"),
+    ("label", "label"),
+    ("desc", "desc"),
+];
+
+/// Position of a short entity name in the canonical bids ordering
+pub fn entity_position(entity: &str) -> Option<usize> {
+    BIDS_ENTITY_ORDER
+        .iter()
+        .position(|(short, _)| *short == entity)
+}
+
+pub static BIDS_ENTITIES: Lazy<BiMap<&'static str, &'static str>> =
+    Lazy::new(|| BIDS_ENTITY_ORDER.iter().cloned().collect());
 
 pub static BIDS_DATATYPES: Lazy<HashSet<&'static str>> = Lazy::new(|| {
     [
@@ -74,3 +187,100 @@ pub static BIDS_DATATYPES: Lazy<HashSet<&'static str>> = Lazy::new(|| {
     .cloned()
     .collect()
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entity_aliases_round_trip() {
+        assert_eq!(BIDS_ENTITIES.len(), BIDS_ENTITY_ORDER.len());
+        for (short, long) in BIDS_ENTITY_ORDER {
+            assert_eq!(deref_key_alias(long, &NO_EXTRA_ENTITIES), Some(short));
+            assert_eq!(
+                get_key_alias(
+                    deref_key_alias(long, &NO_EXTRA_ENTITIES).unwrap(),
+                    &NO_EXTRA_ENTITIES
+                ),
+                long
+            );
+        }
+    }
+
+    #[test]
+    fn entity_long_names_follow_spec() {
+        for (short, long) in [
+            ("ce", "ceagent"),
+            ("trc", "tracer"),
+            ("stain", "stain"),
+            ("mod", "modality"),
+            ("inv", "inversion"),
+            ("mt", "mtransfer"),
+            ("proc", "processing"),
+            ("nuc", "nucleus"),
+            ("voi", "volume"),
+            ("seg", "segmentation"),
+        ] {
+            assert_eq!(get_key_alias(short, &NO_EXTRA_ENTITIES), long);
+        }
+        assert_eq!(
+            deref_query_alias("staining", &NO_EXTRA_ENTITIES),
+            Some("stain")
+        );
+    }
+
+    #[test]
+    fn entity_keys_ignore_case() {
+        let extra = &NO_EXTRA_ENTITIES;
+        for key in ["Sub", "SUB"] {
+            assert!(check_entity(key, extra, true));
+            assert!(!check_entity(key, extra, false));
+            assert_eq!(fold_key_case(key, extra), Some("sub"));
+            // Keys are only folded on request, and never for display
+            assert_eq!(get_key_alias(key, extra), key);
+            assert_eq!(deref_query_alias(key, extra), None);
+        }
+        assert_eq!(fold_key_case("Subject", extra), None);
+    }
+
+    #[test]
+    fn long_names_are_matched_exactly() {
+        let extra = &NO_EXTRA_ENTITIES;
+        for key in ["Subject", "Modality", "Density", "Resolution"] {
+            assert_eq!(deref_key_alias(key, extra), None);
+            assert_eq!(deref_query_alias(key, extra), None);
+        }
+    }
+
+    #[test]
+    fn unknown_keys_pass_through() {
+        let extra = &NO_EXTRA_ENTITIES;
+        assert!(!check_entity("Foo", extra, false));
+        assert!(!check_entity("Foo", extra, true));
+        assert_eq!(fold_key_case("Foo", extra), None);
+        assert_eq!(get_key_alias("Foo", extra), "Foo");
+        assert_eq!(deref_key_alias("Foo", extra), None);
+        assert_eq!(deref_query_alias("RepetitionTime", extra), None);
+    }
+
+    #[test]
+    fn extra_entities_are_only_known_where_given() {
+        let extra = ExtraEntities::from([("stim".to_string(), "stimulus".to_string())]);
+        assert!(check_entity("stim", &extra, false));
+        assert!(check_entity("STIM", &extra, true));
+        assert_eq!(get_key_alias("stim", &extra), "stimulus");
+        assert_eq!(deref_query_alias("stimulus", &extra), Some("stim"));
+        assert!(!check_entity("stim", &NO_EXTRA_ENTITIES, true));
+        assert_eq!(get_key_alias("stim", &NO_EXTRA_ENTITIES), "stim");
+    }
+
+    #[test]
+    fn extra_entities_cannot_rename_spec_entities() {
+        for (short, long) in [("sub", "cohort"), ("cohort", "subject")] {
+            let extra = ExtraEntities::from([(short.to_string(), long.to_string())]);
+            assert!(check_extra_entities(&extra).is_err());
+        }
+        let extra = ExtraEntities::from([("sub".to_string(), "subject".to_string())]);
+        assert!(check_extra_entities(&extra).is_ok());
+    }
+}
@@ -0,0 +1,39 @@
+use std::{fs, path::PathBuf};
+
+use crate::{
+    fs::IterIgnore,
+    layout::{Layout, LayoutOptions},
+};
+
+/// Dataset written to a temporary directory, which is removed when dropped
+pub struct TempDataset {
+    pub root: PathBuf,
+}
+
+impl TempDataset {
+    /// Write `files`, given as paths relative to the root along with their contents
+    pub fn new(name: &str, files: &[(&str, &str)]) -> Self {
+        pyo3::prepare_freethreaded_python();
+        let root = std::env::temp_dir().join(format!("rsbids{}{}", name, std::process::id()));
+        for (file, contents) in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        Self { root }
+    }
+
+    pub fn path(&self, file: &str) -> PathBuf {
+        self.root.join(file)
+    }
+
+    pub fn create(&self, options: LayoutOptions, ignore: Option<&IterIgnore>) -> Layout {
+        Layout::create(vec![self.root.clone()], None, options, ignore).unwrap()
+    }
+}
+
+impl Drop for TempDataset {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
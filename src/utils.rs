@@ -1,8 +1,38 @@
-use std::path::Path;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 use path_clean::clean;
 
 
 /// Return True if subpath is the same as or a subpath of parent
 pub fn is_subpath_of(subpath: &Path, parent: &Path) -> bool {
     clean(parent).starts_with(clean(subpath))
-}
\ No newline at end of file
+}
+
+/// Make path absolute relative to the current directory and lexically clean it
+pub fn absolute_clean(path: &Path) -> PathBuf {
+    clean(std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()))
+}
+
+/// Modification time of a file. Errors if the file cannot be found, and returns `None` if
+/// the platform does not record modification times
+pub fn modified(path: &Path) -> io::Result<Option<SystemTime>> {
+    Ok(fs::metadata(path)?.modified().ok())
+}
+
+/// Sort values numerically if all of them are integers, otherwise lexicographically
+///
+/// Numbers differing only in padding (e.g. `1` and `01`) are ordered lexicographically.
+pub fn natural_sort<S: AsRef<str>>(values: &mut [S]) {
+    if values.iter().all(|v| v.as_ref().parse::<u128>().is_ok()) {
+        values.sort_by(|a, b| {
+            let (a, b) = (a.as_ref(), b.as_ref());
+            let number = |s: &str| s.parse::<u128>().ok();
+            number(a).cmp(&number(b)).then_with(|| a.cmp(b))
+        });
+    } else {
+        values.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+    }
+}